@@ -0,0 +1,24 @@
+// The demo scene from the side and from the front, next to the default view
+//
+//     cargo run --release --example camera_angles
+
+use raytracer_clean::output::{self, ExrPrecision};
+use raytracer_clean::{demo, Camera, Float, Point3, Raytracer, Vec3};
+
+fn main() {
+    let scene = demo::create_scene();
+    let aspect_ratio: Float = 4.0 / 3.0;
+    let cube_center = Point3::new(0.0, -0.5, -3.0);
+
+    let views = [
+        ("view_default.png", demo::default_camera(aspect_ratio)),
+        ("view_side.png", Camera::new(Point3::new(5.0, 0.0, -3.0), cube_center, Vec3::up(), 45.0, aspect_ratio)),
+        ("view_front.png", Camera::new(Point3::new(0.0, 0.0, 2.0), cube_center, Vec3::up(), 45.0, aspect_ratio)),
+    ];
+
+    let raytracer = Raytracer::new(400, 300).with_samples(4);
+    for (path, camera) in views {
+        let render = raytracer.render_output(&scene, &camera);
+        output::save_render(&render.image, path, &raytracer.display_transform(), ExrPrecision::Half, &[]);
+    }
+}
@@ -0,0 +1,29 @@
+// The demo cube under a cool key light and a warm fill light instead of the single light,
+// printing the render statistics
+//
+//     cargo run --release --example lighting
+
+use raytracer_clean::output::{self, ExrPrecision};
+use raytracer_clean::{demo, Color, Light, Point3, Raytracer};
+
+fn main() {
+    let mut scene = demo::create_scene();
+    scene.lights.clear();
+
+    scene.add_light(Light::new(
+        Point3::new(3.0, 3.0, 0.0),   // Position
+        Color::new(0.8, 0.8, 1.0),    // Blue tint
+        0.7,                          // Intensity
+    ));
+    scene.add_light(Light::new(
+        Point3::new(-2.0, 4.0, -2.0), // Position
+        Color::new(1.0, 0.8, 0.6),    // Warm tint
+        0.5,                          // Intensity
+    ));
+
+    let raytracer = Raytracer::new(400, 300).with_samples(4);
+    let camera = demo::default_camera(400.0 / 300.0);
+    let render = raytracer.render_output(&scene, &camera);
+    output::save_render(&render.image, "lighting.png", &raytracer.display_transform(), ExrPrecision::Half, &[]);
+    println!("{}", render.stats.to_table());
+}
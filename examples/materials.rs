@@ -0,0 +1,44 @@
+// A row of cubes showing the material parameters: matte, shiny, mirror-like and textured,
+// on a green-tinted floor
+//
+//     cargo run --release --example materials
+
+use raytracer_clean::output::{self, ExrPrecision};
+use raytracer_clean::{Camera, Color, Cube, Float, Light, Material, Plane, Point3, Raytracer, Scene, Vec3};
+
+fn main() {
+    let mut scene = Scene::new();
+    scene.set_background_color(Color::new(0.8, 0.85, 0.9));
+
+    let colored_floor = Material::new()
+        .with_color(Color::new(0.3, 0.5, 0.3)) // Green tint
+        .with_roughness(0.8);
+    scene.add_plane(Plane::new(Point3::new(0.0, -0.95, 0.0), Vec3::up(), colored_floor));
+
+    let materials = [
+        Material::new().with_color(Color::new(0.8, 0.8, 0.8)).with_specular(0.0),
+        Material::new()
+            .with_color(Color::new(1.0, 0.2, 0.2)) // Red
+            .with_specular(0.8)
+            .with_roughness(0.2),
+        Material::new()
+            .with_color(Color::new(0.9, 0.9, 0.9))
+            .with_specular(0.8)
+            .with_reflectivity(0.5),
+        Material::new()
+            .with_checkerboard(2.0, Color::new(1.0, 0.0, 1.0), Color::black())
+            .with_roughness(0.3)
+            .with_specular(0.5),
+    ];
+    for (i, material) in materials.into_iter().enumerate() {
+        let x = i as Float * 1.5 - 2.25;
+        scene.add_cube(Cube::new(Point3::new(x, -0.45, -0.2), Vec3::new(1.0, 1.0, 1.0), material));
+    }
+
+    scene.add_light(Light::new(Point3::new(-3.0, 5.0, 4.0), Color::white(), 1.0));
+
+    let camera = Camera::new(Point3::new(0.0, 2.0, 6.0), Point3::new(0.0, -0.5, 0.0), Vec3::up(), 45.0, 4.0 / 3.0);
+    let raytracer = Raytracer::new(400, 300).with_samples(4);
+    let render = raytracer.render_output(&scene, &camera);
+    output::save_render(&render.image, "materials.png", &raytracer.display_transform(), ExrPrecision::Half, &[]);
+}
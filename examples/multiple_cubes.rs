@@ -0,0 +1,26 @@
+// The demo scene with a second, smaller cube behind the first
+//
+//     cargo run --release --example multiple_cubes
+
+use raytracer_clean::output::{self, ExrPrecision};
+use raytracer_clean::{demo, Color, Cube, Material, Point3, Raytracer, Vec3};
+
+fn main() {
+    let mut scene = demo::create_scene();
+
+    let checkerboard_material = Material::new()
+        .with_checkerboard(1.0, Color::new(1.0, 0.0, 1.0), Color::black())
+        .with_roughness(0.3)
+        .with_specular(0.5)
+        .with_reflectivity(0.2);
+    scene.add_cube(Cube::new(
+        Point3::new(2.0, -0.5, -4.0),
+        Vec3::new(1.0, 1.0, 1.0),
+        checkerboard_material,
+    ));
+
+    let raytracer = Raytracer::new(400, 300).with_samples(4);
+    let camera = demo::default_camera(400.0 / 300.0);
+    let render = raytracer.render_output(&scene, &camera);
+    output::save_render(&render.image, "multiple_cubes.png", &raytracer.display_transform(), ExrPrecision::Half, &[]);
+}
@@ -0,0 +1,464 @@
+// Keyframe animation for cameras, objects, lights and materials
+
+use std::ops::{Add, Mul, Sub};
+
+use crate::vec3::{Float, Vec3};
+use crate::camera::Camera;
+use crate::scene::Scene;
+use crate::transform::Transform;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    Linear,
+    /// Ease-in/ease-out (smoothstep) between each pair of keys
+    Ease,
+    /// Smooth curve through every key, using the neighbouring keys as tangents
+    CatmullRom,
+}
+
+impl Interpolation {
+    pub const ALL: [Interpolation; 3] = [Interpolation::Linear, Interpolation::Ease, Interpolation::CatmullRom];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Interpolation::Linear => "linear",
+            Interpolation::Ease => "ease",
+            Interpolation::CatmullRom => "catmullrom",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Interpolation> {
+        Interpolation::ALL.iter().copied().find(|interpolation| interpolation.name() == name)
+    }
+}
+
+/// Values that can be blended between keyframes
+pub trait Animatable: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<Float, Output = Self> {}
+
+impl Animatable for Float {}
+impl Animatable for Vec3 {}
+
+#[derive(Debug, Clone)]
+pub struct Track<T: Animatable> {
+    pub interpolation: Interpolation,
+    keys: Vec<(Float, T)>,
+}
+
+impl<T: Animatable> Track<T> {
+    pub fn new(interpolation: Interpolation) -> Self {
+        Track { interpolation, keys: Vec::new() }
+    }
+
+    /// Adds a key, keeping keys sorted by time
+    pub fn add_key(&mut self, time: Float, value: T) {
+        let index = self.keys.partition_point(|(key_time, _)| *key_time <= time);
+        self.keys.insert(index, (time, value));
+    }
+
+    /// Value at `time`; holds the first/last key outside the keyed range
+    pub fn sample(&self, time: Float) -> Option<T> {
+        let first = self.keys.first()?;
+        let last = self.keys.last()?;
+
+        if time <= first.0 {
+            return Some(first.1);
+        }
+        if time >= last.0 {
+            return Some(last.1);
+        }
+
+        let next = self.keys.partition_point(|(key_time, _)| *key_time <= time);
+        let (t0, v0) = self.keys[next - 1];
+        let (t1, v1) = self.keys[next];
+        let t = (time - t0) / (t1 - t0);
+
+        Some(match self.interpolation {
+            Interpolation::Linear => lerp(v0, v1, t),
+            Interpolation::Ease => lerp(v0, v1, t * t * (3.0 - 2.0 * t)),
+            Interpolation::CatmullRom => {
+                let before = if next >= 2 { self.keys[next - 2].1 } else { v0 };
+                let after = self.keys.get(next + 1).map_or(v1, |key| key.1);
+                catmull_rom(before, v0, v1, after, t)
+            }
+        })
+    }
+}
+
+fn lerp<T: Animatable>(a: T, b: T, t: Float) -> T {
+    a + (b - a) * t
+}
+
+fn catmull_rom<T: Animatable>(p0: T, p1: T, p2: T, p3: T, t: Float) -> T {
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
+
+/// Animatable vector properties. Indices follow the order objects and lights were added.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorProperty {
+    CameraPosition,
+    CameraTarget,
+    ObjectTranslation(usize),
+    ObjectRotation(usize),
+    ObjectScale(usize),
+    LightPosition(usize),
+    LightColor(usize),
+    MaterialColor(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalarProperty {
+    CameraFov,
+    CameraRoll,
+    LightIntensity(usize),
+    MaterialSpecular(usize),
+    MaterialRoughness(usize),
+    MaterialReflectivity(usize),
+}
+
+pub enum Property {
+    Vector(VectorProperty),
+    Scalar(ScalarProperty),
+}
+
+impl Property {
+    /// Parses names like `camera.position`, `object0.rotation`, `light1.intensity` or
+    /// `material0.color` (the material of object 0)
+    pub fn from_name(name: &str) -> Option<Property> {
+        let (owner, field) = name.split_once('.')?;
+        let index = |prefix: &str| owner.strip_prefix(prefix).and_then(|i| i.parse::<usize>().ok());
+
+        use ScalarProperty as S;
+        use VectorProperty as V;
+
+        let property = if owner == "camera" {
+            match field {
+                "position" => Property::Vector(V::CameraPosition),
+                "target" => Property::Vector(V::CameraTarget),
+                "fov" => Property::Scalar(S::CameraFov),
+                "roll" => Property::Scalar(S::CameraRoll),
+                _ => return None,
+            }
+        } else if let Some(i) = index("object") {
+            match field {
+                "translation" => Property::Vector(V::ObjectTranslation(i)),
+                "rotation" => Property::Vector(V::ObjectRotation(i)),
+                "scale" => Property::Vector(V::ObjectScale(i)),
+                _ => return None,
+            }
+        } else if let Some(i) = index("light") {
+            match field {
+                "position" => Property::Vector(V::LightPosition(i)),
+                "color" => Property::Vector(V::LightColor(i)),
+                "intensity" => Property::Scalar(S::LightIntensity(i)),
+                _ => return None,
+            }
+        } else if let Some(i) = index("material") {
+            match field {
+                "color" => Property::Vector(V::MaterialColor(i)),
+                "specular" => Property::Scalar(S::MaterialSpecular(i)),
+                "roughness" => Property::Scalar(S::MaterialRoughness(i)),
+                "reflectivity" => Property::Scalar(S::MaterialReflectivity(i)),
+                _ => return None,
+            }
+        } else {
+            return None;
+        };
+
+        Some(property)
+    }
+}
+
+/// A set of keyframed tracks over `duration` seconds, rendered at `fps`
+#[derive(Debug, Clone)]
+pub struct Animation {
+    pub duration: Float,
+    pub fps: Float,
+    vector_tracks: Vec<(VectorProperty, Track<Vec3>)>,
+    scalar_tracks: Vec<(ScalarProperty, Track<Float>)>,
+}
+
+impl Animation {
+    pub fn new(duration: Float, fps: Float) -> Self {
+        Animation {
+            duration: duration.max(0.0),
+            fps: fps.max(1e-3),
+            vector_tracks: Vec::new(),
+            scalar_tracks: Vec::new(),
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        ((self.duration * self.fps).round() as usize).max(1)
+    }
+
+    pub fn frame_time(&self, frame: usize) -> Float {
+        frame as Float / self.fps
+    }
+
+    /// Track for a vector property, created with `interpolation` if it doesn't exist yet
+    pub fn vector_track(&mut self, property: VectorProperty, interpolation: Interpolation) -> &mut Track<Vec3> {
+        let index = match self.vector_tracks.iter().position(|(p, _)| *p == property) {
+            Some(index) => index,
+            None => {
+                self.vector_tracks.push((property, Track::new(interpolation)));
+                self.vector_tracks.len() - 1
+            }
+        };
+        &mut self.vector_tracks[index].1
+    }
+
+    /// Track for a scalar property, created with `interpolation` if it doesn't exist yet
+    pub fn scalar_track(&mut self, property: ScalarProperty, interpolation: Interpolation) -> &mut Track<Float> {
+        let index = match self.scalar_tracks.iter().position(|(p, _)| *p == property) {
+            Some(index) => index,
+            None => {
+                self.scalar_tracks.push((property, Track::new(interpolation)));
+                self.scalar_tracks.len() - 1
+            }
+        };
+        &mut self.scalar_tracks[index].1
+    }
+
+    /// Poses the scene and camera at `time`. Properties of missing objects or lights are skipped.
+    pub fn apply(&self, time: Float, scene: &mut Scene, camera: &mut Camera) {
+        for (property, track) in &self.vector_tracks {
+            if let Some(value) = track.sample(time) {
+                apply_vector(*property, value, scene, camera);
+            }
+        }
+
+        for (property, track) in &self.scalar_tracks {
+            if let Some(value) = track.sample(time) {
+                apply_scalar(*property, value, scene, camera);
+            }
+        }
+    }
+
+    /// Poses the scene for a frame at `time` that is exposed over the camera's shutter.
+    /// Animated objects and the camera get their shutter-close pose as an end pose, so rays
+    /// can blend between the two; everything else is taken at shutter open.
+    pub fn apply_over_shutter(&self, time: Float, scene: &mut Scene, camera: &mut Camera) {
+        if !camera.has_motion_blur() {
+            self.apply(time, scene, camera);
+            return;
+        }
+
+        self.apply(time + camera.shutter_close, scene, camera);
+        let end_pose = (camera.position(), camera.target());
+        let end_transforms: Vec<(usize, Transform)> = self
+            .animated_objects()
+            .into_iter()
+            .filter_map(|i| scene.objects.transform_of(i).map(|transform| (i, *transform)))
+            .collect();
+
+        self.apply(time + camera.shutter_open, scene, camera);
+        for (i, end) in end_transforms {
+            if let Some(object) = scene.objects.get_mut(i) {
+                object.set_end_transform(Some(end));
+            }
+        }
+
+        let moving = (end_pose.0 - camera.position()).length() > 0.0
+            || (end_pose.1 - camera.target()).length() > 0.0;
+        camera.set_end_pose(if moving { Some(end_pose) } else { None });
+    }
+
+    fn animated_objects(&self) -> Vec<usize> {
+        let mut objects: Vec<usize> = self
+            .vector_tracks
+            .iter()
+            .filter_map(|(property, _)| match property {
+                VectorProperty::ObjectTranslation(i)
+                | VectorProperty::ObjectRotation(i)
+                | VectorProperty::ObjectScale(i) => Some(*i),
+                _ => None,
+            })
+            .collect();
+        objects.sort_unstable();
+        objects.dedup();
+        objects
+    }
+}
+
+fn apply_vector(property: VectorProperty, value: Vec3, scene: &mut Scene, camera: &mut Camera) {
+    match property {
+        VectorProperty::CameraPosition => camera.set_position(value.into()),
+        VectorProperty::CameraTarget => camera.set_target(value.into()),
+        VectorProperty::ObjectTranslation(i) => {
+            if let Some(transform) = scene.objects.transform_of(i) {
+                transform.translation = value;
+            }
+        }
+        VectorProperty::ObjectRotation(i) => {
+            if let Some(transform) = scene.objects.transform_of(i) {
+                transform.rotation = value;
+            }
+        }
+        VectorProperty::ObjectScale(i) => {
+            if let Some(transform) = scene.objects.transform_of(i) {
+                transform.scale = value;
+            }
+        }
+        VectorProperty::LightPosition(i) => {
+            if let Some(light) = scene.lights.get_mut(i) {
+                light.position = value.into();
+            }
+        }
+        VectorProperty::LightColor(i) => {
+            if let Some(light) = scene.lights.get_mut(i) {
+                light.color = value.into();
+            }
+        }
+        VectorProperty::MaterialColor(i) => {
+            if let Some(material) = scene.objects.get_mut(i).and_then(|object| object.material_mut()) {
+                material.color = value.into();
+            }
+        }
+    }
+}
+
+fn apply_scalar(property: ScalarProperty, value: Float, scene: &mut Scene, camera: &mut Camera) {
+    match property {
+        ScalarProperty::CameraFov => camera.set_fov(value),
+        ScalarProperty::CameraRoll => camera.set_roll(value),
+        ScalarProperty::LightIntensity(i) => {
+            if let Some(light) = scene.lights.get_mut(i) {
+                light.intensity = value;
+            }
+        }
+        ScalarProperty::MaterialSpecular(i) => {
+            if let Some(material) = scene.objects.get_mut(i).and_then(|object| object.material_mut()) {
+                material.specular = value.clamp(0.0, 1.0);
+            }
+        }
+        ScalarProperty::MaterialRoughness(i) => {
+            if let Some(material) = scene.objects.get_mut(i).and_then(|object| object.material_mut()) {
+                material.roughness = value.clamp(0.0, 1.0);
+            }
+        }
+        ScalarProperty::MaterialReflectivity(i) => {
+            if let Some(material) = scene.objects.get_mut(i).and_then(|object| object.material_mut()) {
+                material.reflectivity = value.clamp(0.0, 1.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Cube;
+    use crate::material::Material;
+    use crate::ray::Ray;
+    use crate::vec3::Point3;
+
+    const EPSILON: Float = 1e-5;
+
+    fn track(interpolation: Interpolation, keys: &[(Float, Float)]) -> Track<Float> {
+        let mut track = Track::new(interpolation);
+        for &(time, value) in keys {
+            track.add_key(time, value);
+        }
+        track
+    }
+
+    fn assert_close(actual: Option<Float>, expected: Float) {
+        let actual = actual.expect("track has keys");
+        assert!((actual - expected).abs() < EPSILON, "{} vs {}", actual, expected);
+    }
+
+    #[test]
+    fn keys_are_interpolated() {
+        let keys = [(2.0, 4.0), (0.0, 0.0), (1.0, 1.0)];
+
+        let linear = track(Interpolation::Linear, &keys);
+        assert_close(linear.sample(0.5), 0.5);
+        assert_close(linear.sample(1.5), 2.5);
+
+        let ease = track(Interpolation::Ease, &keys);
+        assert_close(ease.sample(0.25), 0.15625);
+        assert_close(ease.sample(0.5), 0.5);
+
+        // Passes through every key; the first segment uses its own start as the missing tangent
+        let catmull_rom = track(Interpolation::CatmullRom, &keys);
+        assert_close(catmull_rom.sample(1.0), 1.0);
+        assert_close(catmull_rom.sample(0.5), 0.3125);
+
+        let mut vector = Track::new(Interpolation::Linear);
+        vector.add_key(0.0, Vec3::zero());
+        vector.add_key(2.0, Vec3::new(2.0, -4.0, 6.0));
+        assert!(vector.sample(0.5).unwrap().approx_eq(&Vec3::new(0.5, -1.0, 1.5), EPSILON));
+    }
+
+    #[test]
+    fn first_and_last_keys_hold_outside_the_range() {
+        for interpolation in Interpolation::ALL {
+            let track = track(interpolation, &[(1.0, 3.0), (2.0, 5.0)]);
+            assert_close(track.sample(-10.0), 3.0);
+            assert_close(track.sample(1.0), 3.0);
+            assert_close(track.sample(2.0), 5.0);
+            assert_close(track.sample(10.0), 5.0);
+        }
+
+        let single = track(Interpolation::CatmullRom, &[(1.0, 7.0)]);
+        assert_close(single.sample(0.0), 7.0);
+        assert_close(single.sample(5.0), 7.0);
+        assert!(track(Interpolation::Linear, &[]).sample(0.0).is_none());
+    }
+
+    #[test]
+    fn property_names() {
+        assert!(matches!(Property::from_name("camera.position"), Some(Property::Vector(VectorProperty::CameraPosition))));
+        assert!(matches!(Property::from_name("object3.rotation"), Some(Property::Vector(VectorProperty::ObjectRotation(3)))));
+        assert!(matches!(Property::from_name("light1.intensity"), Some(Property::Scalar(ScalarProperty::LightIntensity(1)))));
+        assert!(matches!(
+            Property::from_name("material0.roughness"),
+            Some(Property::Scalar(ScalarProperty::MaterialRoughness(0)))
+        ));
+
+        for unknown in ["camera.zoom", "object.scale", "objectx.scale", "light0", "sun0.color", ""] {
+            assert!(Property::from_name(unknown).is_none(), "{}", unknown);
+        }
+    }
+
+    #[test]
+    fn shutter_close_pose_becomes_the_end_pose() {
+        let mut animation = Animation::new(1.0, 24.0);
+        let camera_track = animation.vector_track(VectorProperty::CameraPosition, Interpolation::Linear);
+        camera_track.add_key(0.0, Vec3::new(0.0, 0.0, 5.0));
+        camera_track.add_key(1.0, Vec3::new(10.0, 0.0, 5.0));
+        let object_track = animation.vector_track(VectorProperty::ObjectTranslation(0), Interpolation::Linear);
+        object_track.add_key(0.0, Vec3::zero());
+        object_track.add_key(1.0, Vec3::new(2.0, 0.0, 0.0));
+
+        let mut scene = Scene::new();
+        scene.add_cube(Cube::new(Point3::origin(), Vec3::new(1.0, 1.0, 1.0), Material::new()));
+        let mut camera = Camera::new(Point3::new(0.0, 0.0, 5.0), Point3::origin(), Vec3::up(), 45.0, 1.0)
+            .with_shutter(0.0, 0.5);
+
+        animation.apply_over_shutter(0.0, &mut scene, &mut camera);
+
+        // The camera sits at its shutter-open pose and moves to the shutter-close one
+        assert!((camera.position() - Point3::new(0.0, 0.0, 5.0)).length() < EPSILON);
+        let end = camera.get_ray_at_time(0.5, 0.5, 0.5, 0.5, 1.0).origin;
+        assert!((end - Point3::new(5.0, 0.0, 5.0)).length() < EPSILON, "{:?}", end);
+
+        // The cube moves from the origin to x = 1 over the shutter
+        let probe = Ray::new(Point3::new(1.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(scene.hit(&probe.clone().with_time(0.0), 0.001, Float::INFINITY).is_none());
+        assert!(scene.hit(&probe.with_time(1.0), 0.001, Float::INFINITY).is_some());
+
+        // Without a shutter interval the frame is posed at its own time
+        let mut still = Camera::new(Point3::new(0.0, 0.0, 5.0), Point3::origin(), Vec3::up(), 45.0, 1.0);
+        animation.apply_over_shutter(0.5, &mut scene, &mut still);
+        assert!((still.position() - Point3::new(5.0, 0.0, 5.0)).length() < EPSILON);
+        assert!((still.get_ray_at_time(0.5, 0.5, 0.5, 0.5, 1.0).origin - still.position()).length() < EPSILON);
+    }
+}
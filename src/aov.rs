@@ -0,0 +1,275 @@
+// Render passes (AOVs): separate layers filled alongside the beauty image for compositing
+
+use crate::vec3::{to_f32, Float, Vec3};
+use crate::geometry::HitRecord;
+use crate::debug;
+use crate::sampling;
+use crate::checkpoint::{Fingerprint, Fingerprinted};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aov {
+    /// Direct diffuse light from the scene's lights
+    Diffuse,
+    Specular,
+    /// Everything seen in mirror reflections, already scaled by reflectivity
+    Reflection,
+    Emission,
+    /// Flat or environment ambient light, after ambient occlusion
+    Ambient,
+    /// Environment seen directly by camera rays that hit nothing
+    Background,
+    /// 1 where every light is visible, 0 where all are blocked
+    Shadow,
+    /// Distance along the camera ray to the first hit
+    Depth,
+    Normal,
+    Albedo,
+    ObjectId,
+    MaterialId,
+}
+
+/// Lighting at a hit split by contribution; `total` is the beauty color
+#[derive(Debug, Clone, Copy)]
+pub struct Shading {
+    pub emission: Vec3,
+    pub ambient: Vec3,
+    pub diffuse: Vec3,
+    pub specular: Vec3,
+    pub reflection: Vec3,
+    pub shadow: Float,
+}
+
+impl Shading {
+    pub fn total(&self) -> Vec3 {
+        self.emission + self.ambient + self.diffuse + self.specular + self.reflection
+    }
+}
+
+impl Aov {
+    pub const ALL: [Aov; 12] = [
+        Aov::Diffuse,
+        Aov::Specular,
+        Aov::Reflection,
+        Aov::Emission,
+        Aov::Ambient,
+        Aov::Background,
+        Aov::Shadow,
+        Aov::Depth,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::ObjectId,
+        Aov::MaterialId,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Diffuse => "diffuse",
+            Aov::Specular => "specular",
+            Aov::Reflection => "reflection",
+            Aov::Emission => "emission",
+            Aov::Ambient => "ambient",
+            Aov::Background => "background",
+            Aov::Shadow => "shadow",
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "objectid",
+            Aov::MaterialId => "materialid",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Aov> {
+        Aov::ALL.iter().copied().find(|aov| aov.name() == name)
+    }
+
+    /// Comma separated pass names, or `all`
+    pub fn parse_list(list: &str) -> Result<Vec<Aov>, String> {
+        if list == "all" {
+            return Ok(Aov::ALL.to_vec());
+        }
+
+        list.split(',')
+            .map(|name| Aov::from_name(name.trim()).ok_or_else(|| format!("unknown pass '{}'", name)))
+            .collect()
+    }
+
+    /// Light passes; these add up to the beauty image and go through tone mapping
+    pub fn is_radiance(&self) -> bool {
+        matches!(
+            self,
+            Aov::Diffuse | Aov::Specular | Aov::Reflection | Aov::Emission | Aov::Ambient | Aov::Background
+        )
+    }
+
+    /// Passes that can't be averaged across an edge take the sample nearest the pixel centre
+    fn is_discrete(&self) -> bool {
+        matches!(self, Aov::Depth | Aov::ObjectId | Aov::MaterialId)
+    }
+
+    /// EXR channel names; single-value passes keep their value in the first component
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Shadow | Aov::ObjectId | Aov::MaterialId => &["Y"],
+            _ => &["R", "G", "B"],
+        }
+    }
+
+    /// Display color for passes that aren't light, in the style of the debug modes
+    pub fn visualize(&self, value: Vec3, depth_range: Float) -> Vec3 {
+        match self {
+            Aov::Depth => {
+                let depth = if value.x.is_finite() { (value.x / depth_range).clamp(0.0, 1.0) } else { 1.0 };
+                Vec3::new(1.0, 1.0, 1.0) * (1.0 - depth)
+            }
+            Aov::Normal => (value + Vec3::new(1.0, 1.0, 1.0)) * 0.5,
+            Aov::Shadow => Vec3::new(value.x, value.x, value.x),
+            Aov::ObjectId | Aov::MaterialId if value.x < 0.0 => Vec3::zero(),
+            Aov::ObjectId | Aov::MaterialId => debug::id_to_color(value.x as usize),
+            _ => value,
+        }
+    }
+}
+
+impl Fingerprinted for Aov {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add(self.name());
+    }
+}
+
+/// Every pass's value for one camera sample, indexed by `Aov as usize`
+#[derive(Debug, Clone, Copy)]
+pub struct AovSample {
+    values: [Vec3; Aov::ALL.len()],
+}
+
+impl AovSample {
+    pub fn hit(hit: &HitRecord, shading: &Shading) -> Self {
+        let mut values = [Vec3::zero(); Aov::ALL.len()];
+        values[Aov::Diffuse as usize] = shading.diffuse;
+        values[Aov::Specular as usize] = shading.specular;
+        values[Aov::Reflection as usize] = shading.reflection;
+        values[Aov::Emission as usize] = shading.emission;
+        values[Aov::Ambient as usize] = shading.ambient;
+        values[Aov::Shadow as usize] = Vec3::new(shading.shadow, 0.0, 0.0);
+        values[Aov::Depth as usize] = Vec3::new(hit.t, 0.0, 0.0);
+        values[Aov::Normal as usize] = hit.normal;
+        values[Aov::Albedo as usize] = hit.material.get_color_at_point(&hit.local_point).to_vec3();
+        values[Aov::ObjectId as usize] = Vec3::new(hit.object_id as Float, 0.0, 0.0);
+        values[Aov::MaterialId as usize] = Vec3::new(hit.material.id as Float, 0.0, 0.0);
+        AovSample { values }
+    }
+
+    /// A camera ray that escaped: only the background pass is lit; IDs are -1
+    pub fn miss(background: Vec3) -> Self {
+        let mut values = [Vec3::zero(); Aov::ALL.len()];
+        values[Aov::Background as usize] = background;
+        values[Aov::Shadow as usize] = Vec3::new(1.0, 0.0, 0.0);
+        values[Aov::Depth as usize] = Vec3::new(Float::INFINITY, 0.0, 0.0);
+        values[Aov::ObjectId as usize] = Vec3::new(-1.0, 0.0, 0.0);
+        values[Aov::MaterialId as usize] = Vec3::new(-1.0, 0.0, 0.0);
+        AovSample { values }
+    }
+}
+
+/// Filtered per-pixel accumulation of AOV samples, mirroring the beauty estimate
+#[derive(Debug, Clone)]
+pub struct AovEstimate {
+    sums: [Vec3; Aov::ALL.len()],
+    weight: Float,
+    abs_weight: Float,
+    nearest: Option<(Float, AovSample)>,
+}
+
+impl AovEstimate {
+    pub fn new() -> Self {
+        AovEstimate {
+            sums: [Vec3::zero(); Aov::ALL.len()],
+            weight: 0.0,
+            abs_weight: 0.0,
+            nearest: None,
+        }
+    }
+
+    /// Adds a sample with its filter weight and squared distance from the pixel centre
+    pub fn add(&mut self, sample: &AovSample, weight: Float, distance_squared: Float) {
+        for (sum, value) in self.sums.iter_mut().zip(&sample.values) {
+            if value.x.is_finite() {
+                *sum += *value * weight;
+            }
+        }
+        self.weight += weight;
+        self.abs_weight += weight.abs();
+
+        if self.nearest.is_none_or(|(nearest, _)| distance_squared < nearest) {
+            self.nearest = Some((distance_squared, *sample));
+        }
+    }
+
+    /// Discrete passes, and pixels whose filter weights cancel, take the nearest sample
+    pub fn value(&self, aov: Aov) -> Vec3 {
+        let index = aov as usize;
+        match &self.nearest {
+            Some((_, sample)) if aov.is_discrete() || sampling::weights_cancel(self.weight, self.abs_weight) => {
+                sample.values[index]
+            }
+            Some(_) => self.sums[index] / self.weight,
+            None => Vec3::zero(),
+        }
+    }
+}
+
+impl Default for AovEstimate {
+    fn default() -> Self {
+        AovEstimate::new()
+    }
+}
+
+/// Image buffers for the requested passes, in request order
+pub struct AovBuffers {
+    pub passes: Vec<(Aov, Vec<Vec<Vec3>>)>,
+}
+
+impl AovBuffers {
+    pub fn new(aovs: &[Aov], width: u32, height: u32) -> Self {
+        let passes = aovs
+            .iter()
+            .map(|&aov| (aov, vec![vec![Vec3::zero(); width as usize]; height as usize]))
+            .collect();
+        AovBuffers { passes }
+    }
+
+    /// The buffer for `aov`; panics if it wasn't requested
+    pub fn get(&self, aov: Aov) -> &[Vec<Vec3>] {
+        self.passes
+            .iter()
+            .find(|(pass, _)| *pass == aov)
+            .map(|(_, image)| image.as_slice())
+            .unwrap_or_else(|| panic!("pass '{}' was not rendered", aov.name()))
+    }
+
+    /// One plane per EXR channel, named `<pass>.<channel>` as multi-layer readers expect
+    pub fn exr_planes(&self) -> Vec<(String, Vec<Vec<f32>>)> {
+        let mut planes = Vec::new();
+
+        for (aov, image) in &self.passes {
+            for (component, channel) in aov.channels().iter().enumerate() {
+                let plane = image
+                    .iter()
+                    .map(|row| {
+                        row.iter()
+                            .map(|value| match component {
+                                0 => to_f32(value.x),
+                                1 => to_f32(value.y),
+                                _ => to_f32(value.z),
+                            })
+                            .collect()
+                    })
+                    .collect();
+                planes.push((format!("{}.{}", aov.name(), channel), plane));
+            }
+        }
+
+        planes
+    }
+}
@@ -0,0 +1,312 @@
+// Timing runs. `--benchmark` compares single rays with packets on the loaded scene;
+// `--benchmark-suite` times each intersection routine and two reference renders, and can
+// save the results to compare against a later build.
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use crate::camera::Camera;
+use crate::demo;
+use crate::geometry::{Cube, Hittable, HittableList, Instance, Plane};
+use crate::light::Light;
+use crate::material::Material;
+use crate::ray::{Ray, RayPacket};
+use crate::raytracer::Raytracer;
+use crate::sampling::Rng;
+use crate::scene::Scene;
+use crate::transform::Transform;
+use crate::vec3::{Color, Float, Point3, Vec3};
+
+/// Each measurement is repeated this many times and the fastest run is reported
+const RUNS: usize = 5;
+
+/// Random rays per intersection micro-benchmark
+const MICRO_RAYS: usize = 200_000;
+
+/// Cubes per side of the grid in the `HittableList` micro-benchmark
+const LIST_SIDE: usize = 10;
+
+/// Cubes per side of the stress scene's grid, 2500 cubes in all
+const STRESS_SIDE: usize = 50;
+
+/// Resolution of the reference renders
+const RENDER_WIDTH: u32 = 200;
+const RENDER_HEIGHT: u32 = 150;
+
+/// Rays traced in `time` by one benchmark of the suite
+pub struct Measurement {
+    pub name: &'static str,
+    pub rays: usize,
+    pub time: Duration,
+}
+
+impl Measurement {
+    pub fn rays_per_second(&self) -> f64 {
+        self.rays as f64 / self.time.as_secs_f64()
+    }
+}
+
+/// Results loaded from a file written by `save`
+pub struct Baseline {
+    /// Build the results were taken from, if git knew it
+    pub commit: Option<String>,
+    pub rays_per_second: HashMap<String, f64>,
+}
+
+/// Times primary-ray intersection one ray at a time and in 4-ray packets, then full
+/// single-sample renders with and without packets, and prints rays per second
+pub fn run(raytracer: &Raytracer, scene: &Scene, camera: &Camera) {
+    let rays: Vec<Ray> = (0..raytracer.height)
+        .flat_map(|y| (0..raytracer.width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let (u, v) = raytracer.image_to_uv(x as Float + 0.5, y as Float + 0.5);
+            camera.get_ray(u, v)
+        })
+        .collect();
+    let packets: Vec<RayPacket> = rays
+        .chunks(4)
+        .map(|chunk| RayPacket::new(std::array::from_fn(|lane| chunk[lane.min(chunk.len() - 1)].clone())))
+        .collect();
+
+    println!("Benchmark: {}x{} primary rays, best of {} runs", raytracer.width, raytracer.height, RUNS);
+
+    let scalar = fastest(|| {
+        for ray in &rays {
+            black_box(scene.hit(ray, 0.0, Float::INFINITY));
+        }
+    });
+    let packet = fastest(|| {
+        for packet in &packets {
+            black_box(scene.hit_packet(packet, 0.0, Float::INFINITY));
+        }
+    });
+    report("Intersection, single rays", rays.len(), scalar, None);
+    report("Intersection, 4-ray packets", rays.len(), packet, Some(scalar));
+
+    let (render_rays, render) = time_render(&raytracer.preview(1).with_packets(false), scene, camera);
+    let (packet_rays, packet_render) = time_render(&raytracer.preview(1).with_packets(true), scene, camera);
+    report("Render, single rays", render_rays, render, None);
+    report("Render, 4-ray packets", packet_rays, packet_render, Some(render));
+}
+
+/// The fastest render of `scene`, with every ray it traces: camera, shadow, reflection and
+/// occlusion rays
+fn time_render(raytracer: &Raytracer, scene: &Scene, camera: &Camera) -> (usize, Duration) {
+    let rays = raytracer.render_output(scene, camera).stats.total_rays() as usize;
+    let time = fastest(|| {
+        black_box(raytracer.render_output(scene, camera));
+    });
+    (rays, time)
+}
+
+fn fastest(mut f: impl FnMut()) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+fn report(name: &str, rays: usize, time: Duration, baseline: Option<Duration>) {
+    let rays_per_second = rays as f64 / time.as_secs_f64();
+    let speedup = baseline.map_or(String::new(), |baseline| {
+        format!("  ({:.2}x)", baseline.as_secs_f64() / time.as_secs_f64())
+    });
+    println!("{:<30} {:>9.2} ms  {:>8.2} Mrays/s{}", name, time.as_secs_f64() * 1000.0, rays_per_second / 1e6, speedup);
+}
+
+/// Runs every micro- and macro-benchmark with fixed inputs, so results are comparable
+/// across builds. Each result is printed as it finishes, along with its change from
+/// `baseline` when one is given.
+pub fn suite(baseline: Option<&Baseline>) -> Vec<Measurement> {
+    let mut results = Vec::new();
+    let mut record = |name: &'static str, rays: usize, time: Duration| {
+        let measurement = Measurement { name, rays, time };
+        let previous = baseline.and_then(|baseline| baseline.rays_per_second.get(name));
+        let change = previous.map_or(String::new(), |previous| {
+            format!("  ({:+.1}%)", (measurement.rays_per_second() / previous - 1.0) * 100.0)
+        });
+        println!(
+            "{:<22} {:>9.2} ms  {:>9.3} Mrays/s{}",
+            name,
+            time.as_secs_f64() * 1000.0,
+            measurement.rays_per_second() / 1e6,
+            change
+        );
+        results.push(measurement);
+    };
+
+    match baseline.and_then(|baseline| baseline.commit.as_deref()) {
+        Some(commit) => println!("Benchmark suite, best of {} runs, compared with {}", RUNS, commit),
+        None => println!("Benchmark suite, best of {} runs", RUNS),
+    }
+
+    // Micro-benchmarks: rays from all around aimed into a box around each object
+    let unit_rays = random_rays(MICRO_RAYS, 4.0, 1.5, 1);
+    let unit_packets = packets_of(&unit_rays);
+    let cube = Cube::new(Point3::origin(), Vec3::new(2.0, 2.0, 2.0), Material::new());
+    let plane = Plane::new(Point3::origin(), Vec3::up(), Material::new());
+    let rotation = Transform::identity().with_rotation(Vec3::new(20.0, 35.0, 10.0));
+    let instance = Instance::new(Box::new(cube.clone()), rotation);
+
+    record("cube.hit", unit_rays.len(), time_hits(&cube, &unit_rays));
+    record("cube.hit_packet", unit_rays.len(), time_packet_hits(&cube, &unit_packets));
+    record("plane.hit", unit_rays.len(), time_hits(&plane, &unit_rays));
+    record("plane.hit_packet", unit_rays.len(), time_packet_hits(&plane, &unit_packets));
+    record("instance.hit", unit_rays.len(), time_hits(&instance, &unit_rays));
+
+    let list = cube_grid(LIST_SIDE);
+    let list_rays = random_rays(MICRO_RAYS / 10, LIST_SIDE as Float * 1.5, LIST_SIDE as Float * 0.5, 2);
+    let list_packets = packets_of(&list_rays);
+    record("list.hit", list_rays.len(), time_hits(&list, &list_rays));
+    record("list.hit_packet", list_rays.len(), time_packet_hits(&list, &list_packets));
+
+    // Macro-benchmarks: whole single-sample renders, counted in all the rays they trace
+    let aspect_ratio = RENDER_WIDTH as Float / RENDER_HEIGHT as Float;
+    let raytracer = Raytracer::new(RENDER_WIDTH, RENDER_HEIGHT);
+
+    let demo = demo::create_scene();
+    let demo_camera = demo::default_camera(aspect_ratio);
+    let (stress, stress_camera) = stress_scene(aspect_ratio);
+    for (name, packets_name, scene, camera) in [
+        ("render.demo", "render.demo_packets", &demo, &demo_camera),
+        ("render.stress", "render.stress_packets", &stress, &stress_camera),
+    ] {
+        let (rays, time) = time_render(&raytracer.preview(1).with_packets(false), scene, camera);
+        record(name, rays, time);
+        let (rays, time) = time_render(&raytracer.preview(1).with_packets(true), scene, camera);
+        record(packets_name, rays, time);
+    }
+
+    results
+}
+
+/// Writes results as `<name> <rays per second>` lines under a header naming the commit
+pub fn save(results: &[Measurement], path: &str) -> Result<(), String> {
+    let mut text = String::from("# Benchmark results in rays per second, from --benchmark-suite\n");
+    if let Some(commit) = current_commit() {
+        text.push_str(&format!("# commit {}\n", commit));
+    }
+    for measurement in results {
+        text.push_str(&format!("{} {:.0}\n", measurement.name, measurement.rays_per_second()));
+    }
+
+    std::fs::write(path, text).map_err(|error| format!("{}: {}", path, error))
+}
+
+pub fn load(path: &str) -> Result<Baseline, String> {
+    let text = std::fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+    let mut baseline = Baseline { commit: None, rays_per_second: HashMap::new() };
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if let Some(comment) = line.strip_prefix('#') {
+            if let Some(commit) = comment.trim().strip_prefix("commit ") {
+                baseline.commit = Some(commit.to_string());
+            }
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let parsed = line.split_once(' ').and_then(|(name, value)| Some((name, value.trim().parse::<f64>().ok()?)));
+        match parsed {
+            Some((name, value)) => {
+                baseline.rays_per_second.insert(name.to_string(), value);
+            }
+            None => return Err(format!("{} line {}: expected '<name> <rays per second>'", path, number + 1)),
+        }
+    }
+
+    Ok(baseline)
+}
+
+/// `git describe` of the working tree, when run inside a checkout
+fn current_commit() -> Option<String> {
+    let output = std::process::Command::new("git").args(["describe", "--always", "--dirty"]).output().ok()?;
+    let commit = String::from_utf8(output.stdout).ok()?;
+    (output.status.success() && !commit.trim().is_empty()).then(|| commit.trim().to_string())
+}
+
+/// `count` rays starting on a sphere of `radius` around the origin, each aimed at a random
+/// point inside the cube of half-size `spread`. The same seed gives the same rays.
+fn random_rays(count: usize, radius: Float, spread: Float, seed: u64) -> Vec<Ray> {
+    let mut rng = Rng::new(seed);
+    let mut signed = move || rng.next_float() * 2.0 - 1.0;
+
+    (0..count)
+        .map(|_| {
+            let origin = loop {
+                let point = Vec3::new(signed(), signed(), signed());
+                if point.length_squared() > 1e-4 && point.length_squared() <= 1.0 {
+                    break Point3::from(point.normalize() * radius);
+                }
+            };
+            let target = Point3::from(Vec3::new(signed(), signed(), signed()) * spread);
+            Ray::new(origin, target - origin)
+        })
+        .collect()
+}
+
+/// Groups rays in fours; a short last group repeats its final ray
+fn packets_of(rays: &[Ray]) -> Vec<RayPacket> {
+    rays.chunks(4)
+        .map(|chunk| RayPacket::new(std::array::from_fn(|lane| chunk[lane.min(chunk.len() - 1)].clone())))
+        .collect()
+}
+
+fn time_hits(object: &dyn Hittable, rays: &[Ray]) -> Duration {
+    fastest(|| {
+        for ray in rays {
+            black_box(object.hit(ray, 0.0, Float::INFINITY));
+        }
+    })
+}
+
+fn time_packet_hits(object: &dyn Hittable, packets: &[RayPacket]) -> Duration {
+    fastest(|| {
+        for packet in packets {
+            black_box(object.hit_packet(packet, 0.0, crate::simd::FloatX4::splat(Float::INFINITY)));
+        }
+    })
+}
+
+/// `side` x `side` cubes of varying height, one unit apart and centred on the origin
+fn cube_grid(side: usize) -> HittableList {
+    let mut list = HittableList::new();
+    let material = Material::new().with_checkerboard(0.25, Color::white(), Color::new(0.2, 0.4, 0.8));
+    let half = side as Float * 0.5;
+
+    for row in 0..side {
+        for column in 0..side {
+            let height = 0.3 + ((row * 7 + column * 13) % 10) as Float * 0.1;
+            let center = Point3::new(column as Float - half + 0.5, height * 0.5, row as Float - half + 0.5);
+            list.add(Cube::new(center, Vec3::new(0.6, height, 0.6), material.clone()));
+        }
+    }
+
+    list
+}
+
+/// A floor covered in a grid of cubes, seen at an angle from above
+fn stress_scene(aspect_ratio: Float) -> (Scene, Camera) {
+    let mut scene = Scene::new();
+    scene.objects = cube_grid(STRESS_SIDE);
+    scene.add_plane(Plane::new(Point3::origin(), Vec3::up(), Material::new().with_color(Color::new(0.7, 0.7, 0.7))));
+    scene.add_light(Light::new(Point3::new(-20.0, 30.0, 10.0), Color::white(), 1.0));
+
+    let extent = STRESS_SIDE as Float * 0.5;
+    let camera = Camera::new(
+        Point3::new(extent, extent * 0.8, extent * 1.2),
+        Point3::origin(),
+        Vec3::up(),
+        50.0,
+        aspect_ratio,
+    );
+    (scene, camera)
+}
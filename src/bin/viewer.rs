@@ -0,0 +1,31 @@
+// Interactive viewer on its own: opens a scene file, or the demo scene, in the raylib window
+//
+//     cargo run --release --features viewer --bin viewer -- [scene-file]
+
+use raytracer_clean::{demo, scene_file, viewer, Float, Raytracer};
+
+const WIDTH: u32 = 800;
+const HEIGHT: u32 = 600;
+
+fn main() {
+    let aspect_ratio = WIDTH as Float / HEIGHT as Float;
+    let raytracer = Raytracer::new(WIDTH, HEIGHT);
+
+    match std::env::args().nth(1) {
+        Some(path) => match scene_file::load(&path, aspect_ratio) {
+            Ok(file) => {
+                let camera = file.camera.unwrap_or_else(|| demo::default_camera(aspect_ratio));
+                let raytracer = match file.tone_mapping {
+                    Some(tone_mapping) => raytracer.with_tone_mapping(tone_mapping),
+                    None => raytracer,
+                };
+                viewer::run(raytracer, &file.scene, camera);
+            }
+            Err(message) => {
+                eprintln!("Failed to load scene: {}", message);
+                std::process::exit(1);
+            }
+        },
+        None => viewer::run(raytracer, &demo::create_scene(), demo::default_camera(aspect_ratio)),
+    }
+}
@@ -1,50 +1,423 @@
 // Camera system for raytracing
 
-use crate::vec3::Vec3;
+use crate::vec3::{Float, Point3, Vec3, consts};
 use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::sampling;
+use crate::checkpoint::{Fingerprint, Fingerprinted};
 
-fn degrees_to_radians(degrees: f32) -> f32 {
-    degrees * std::f32::consts::PI / 180.0
+fn degrees_to_radians(degrees: Float) -> Float {
+    degrees * consts::PI / 180.0
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Projection {
+    /// Pinhole/thin-lens perspective; `fov` is the vertical field of view
+    Perspective,
+    /// Parallel rays along the view direction. The view is sized to match the perspective
+    /// framing at the target: half-height = tan(fov / 2) * distance to target.
+    Orthographic,
+    /// Equidistant fisheye; `fov` is the angle covered across the image height
+    Fisheye,
+    /// Full 360 x 180 degree panorama; `fov` is ignored and a 2:1 aspect ratio is expected
+    Equirectangular,
+}
+
+impl Projection {
+    pub const ALL: [Projection; 4] = [
+        Projection::Perspective,
+        Projection::Orthographic,
+        Projection::Fisheye,
+        Projection::Equirectangular,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Projection::Perspective => "perspective",
+            Projection::Orthographic => "orthographic",
+            Projection::Fisheye => "fisheye",
+            Projection::Equirectangular => "equirectangular",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Projection> {
+        Projection::ALL.iter().copied().find(|projection| projection.name() == name)
+    }
+
+    /// Largest field of view in degrees. Perspective and orthographic framing grow with
+    /// tan(fov / 2), which has no value at 180.
+    pub fn max_fov(&self) -> Float {
+        match self {
+            Projection::Perspective | Projection::Orthographic => 179.0,
+            Projection::Fisheye | Projection::Equirectangular => 359.0,
+        }
+    }
+}
+
+impl Fingerprinted for Projection {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add(self.name());
+    }
+}
+
+/// Pinhole or thin-lens camera. Position, orientation and field of view are private so the
+/// derived basis (`forward`, `right`, `camera_up`) can't go stale; change them through the
+/// setters, which rebuild it.
 #[derive(Debug, Clone)]
 pub struct Camera {
-    pub position: Vec3,
-    pub target: Vec3,
-    pub up: Vec3,
-    pub fov: f32,
-    pub aspect_ratio: f32,
-    pub forward: Vec3,
-    pub right: Vec3,
-    pub camera_up: Vec3,
-    pub focal_length: f32,
+    position: Point3,
+    target: Point3,
+    up: Vec3,
+    roll: Float,
+    fov: Float,
+    aspect_ratio: Float,
+    forward: Vec3,
+    right: Vec3,
+    camera_up: Vec3,
+    pub focal_length: Float,
+    pub aperture: Float,
+    pub focus_distance: Float,
+    pub aperture_blades: u32,
+    pub aperture_rotation: Float,
+    pub projection: Projection,
+    /// Shutter interval in seconds, relative to the frame time. Rays are spread across it
+    /// when `shutter_close > shutter_open`, blurring anything that moves.
+    pub shutter_open: Float,
+    pub shutter_close: Float,
+    /// Position and target at shutter close, when the camera itself moves during the exposure
+    end_pose: Option<(Point3, Point3)>,
 }
 
 impl Camera {
-    pub fn new(position: Vec3, target: Vec3, up: Vec3, fov: f32, aspect_ratio: f32) -> Self {
+    pub fn new(position: Point3, target: Point3, up: Vec3, fov: Float, aspect_ratio: Float) -> Self {
         let mut camera = Camera {
             position,
             target,
             up,
+            roll: 0.0,
             fov,
             aspect_ratio,
             forward: Vec3::zero(),
             right: Vec3::zero(),
             camera_up: Vec3::zero(),
             focal_length: 1.0,
+            aperture: 0.0,
+            focus_distance: (target - position).length(),
+            aperture_blades: 0,
+            aperture_rotation: 0.0,
+            projection: Projection::Perspective,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            end_pose: None,
         };
 
+        camera.set_fov(fov);
         camera.update_camera_vectors();
         camera
     }
 
+    /// Lens diameter and the distance to the plane in perfect focus. An aperture of 0 is a pinhole.
+    pub fn with_aperture(mut self, aperture: Float, focus_distance: Float) -> Self {
+        self.aperture = aperture.max(0.0);
+        self.focus_distance = focus_distance.max(0.001);
+        self
+    }
+
+    /// Polygonal aperture with `blades` sides (0 keeps it circular), rotated by `rotation` degrees
+    pub fn with_aperture_blades(mut self, blades: u32, rotation: Float) -> Self {
+        self.aperture_blades = blades;
+        self.aperture_rotation = rotation;
+        self
+    }
+
+    /// Also clamps the field of view to what `projection` can show
+    pub fn with_projection(mut self, projection: Projection) -> Self {
+        self.projection = projection;
+        self.set_fov(self.fov);
+        self
+    }
+
+    /// Shutter open and close times in seconds; an empty interval disables motion blur
+    pub fn with_shutter(mut self, open: Float, close: Float) -> Self {
+        self.shutter_open = open;
+        self.shutter_close = close.max(open);
+        self
+    }
+
+    pub fn has_motion_blur(&self) -> bool {
+        self.shutter_close > self.shutter_open
+    }
+
+    /// Position and target the camera reaches at shutter close (None keeps it still)
+    pub fn set_end_pose(&mut self, end_pose: Option<(Point3, Point3)>) {
+        self.end_pose = end_pose;
+    }
+
+    /// Rotates the camera around its target by yaw/pitch degrees, keeping the distance.
+    /// Pitch stays within 89 degrees of the horizon.
+    pub fn orbit(&mut self, yaw_degrees: Float, pitch_degrees: Float) {
+        let offset = self.position - self.target;
+        let radius = offset.length();
+        if radius == 0.0 {
+            return;
+        }
+
+        let yaw = offset.x.atan2(offset.z) + degrees_to_radians(yaw_degrees);
+        let max_pitch = degrees_to_radians(89.0);
+        let pitch = ((offset.y / radius).clamp(-1.0, 1.0).asin() + degrees_to_radians(pitch_degrees))
+            .clamp(-max_pitch, max_pitch);
+
+        self.set_position(self.target + Vec3::new(
+            radius * pitch.cos() * yaw.sin(),
+            radius * pitch.sin(),
+            radius * pitch.cos() * yaw.cos(),
+        ));
+    }
+
+    /// Moves position and target together within the view plane
+    pub fn pan(&mut self, right: Float, up: Float) {
+        let offset = self.right * right + self.camera_up * up;
+        self.look_at(self.position + offset, self.target + offset, self.up);
+    }
+
+    /// Moves toward the target by `fraction` of the current distance (negative moves away)
+    pub fn dolly(&mut self, fraction: Float) {
+        let distance = ((self.target - self.position).length() * (1.0 - fraction)).max(0.1);
+        self.set_position(self.target - self.forward * distance);
+    }
+
+    /// Moves position and target together along the camera's own axes
+    pub fn fly(&mut self, forward: Float, right: Float, up: Float) {
+        let offset = self.forward * forward + self.right * right + self.camera_up * up;
+        self.look_at(self.position + offset, self.target + offset, self.up);
+    }
+
+    pub fn position(&self) -> Point3 {
+        self.position
+    }
+
+    pub fn target(&self) -> Point3 {
+        self.target
+    }
+
+    pub fn up(&self) -> Vec3 {
+        self.up
+    }
+
+    pub fn fov(&self) -> Float {
+        self.fov
+    }
+
+    pub fn aspect_ratio(&self) -> Float {
+        self.aspect_ratio
+    }
+
+    /// Rotation around the view direction in degrees
+    pub fn roll(&self) -> Float {
+        self.roll
+    }
+
+    /// Heading in degrees around the world Y axis; 0 looks down -Z, 90 looks down +X
+    pub fn yaw(&self) -> Float {
+        self.forward.x.atan2(-self.forward.z).to_degrees()
+    }
+
+    /// Elevation of the view direction in degrees; positive looks up
+    pub fn pitch(&self) -> Float {
+        self.forward.y.clamp(-1.0, 1.0).asin().to_degrees()
+    }
+
+    /// The view direction, for code that builds its own rays
+    pub fn forward(&self) -> Vec3 {
+        self.forward
+    }
+
+    pub fn right(&self) -> Vec3 {
+        self.right
+    }
+
+    /// Up in the image, perpendicular to `forward` and `right`
+    pub fn camera_up(&self) -> Vec3 {
+        self.camera_up
+    }
+
+    pub fn look_at(&mut self, position: Point3, target: Point3, up: Vec3) {
+        self.position = position;
+        self.target = target;
+        self.up = up;
+        self.update_camera_vectors();
+    }
+
+    /// Moves the camera, keeping the target
+    pub fn set_position(&mut self, position: Point3) {
+        self.position = position;
+        self.update_camera_vectors();
+    }
+
+    pub fn set_target(&mut self, target: Point3) {
+        self.target = target;
+        self.update_camera_vectors();
+    }
+
+    /// Clamped to what the projection can show; see `Projection::max_fov`
+    pub fn set_fov(&mut self, fov: Float) {
+        self.fov = fov.clamp(0.01, self.projection.max_fov());
+    }
+
+    /// Width over height of the output, for when the image size changes
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: Float) {
+        self.aspect_ratio = aspect_ratio.max(1e-6);
+    }
+
+    pub fn set_roll(&mut self, roll_degrees: Float) {
+        self.roll = roll_degrees;
+        self.update_camera_vectors();
+    }
+
+    pub fn set_yaw(&mut self, yaw_degrees: Float) {
+        self.set_yaw_pitch(yaw_degrees, self.pitch());
+    }
+
+    pub fn set_pitch(&mut self, pitch_degrees: Float) {
+        self.set_yaw_pitch(self.yaw(), pitch_degrees);
+    }
+
+    /// Turns the camera in place: the target moves to keep its distance in the new direction
+    pub fn set_yaw_pitch(&mut self, yaw_degrees: Float, pitch_degrees: Float) {
+        let yaw = degrees_to_radians(yaw_degrees);
+        let pitch = degrees_to_radians(pitch_degrees.clamp(-90.0, 90.0));
+        let distance = (self.target - self.position).length().max(1e-3);
+
+        let direction = Vec3::new(pitch.cos() * yaw.sin(), pitch.sin(), -pitch.cos() * yaw.cos());
+        self.target = self.position + direction * distance;
+        self.update_camera_vectors();
+    }
+
+    /// Rebuilds the orthonormal basis. Handles a target on top of the position (keeps the old
+    /// forward) and a view direction parallel to `up` (keeps the old right vector, or picks any
+    /// perpendicular one), so the basis never contains NaNs.
     fn update_camera_vectors(&mut self) {
-        self.forward = (self.target - self.position).normalize();
-        self.right = self.forward.cross(&self.up).normalize();
-        self.camera_up = self.right.cross(&self.forward).normalize();
+        let forward = (self.target - self.position).normalize();
+        if forward.length() > 0.5 {
+            self.forward = forward;
+        } else if self.forward.length() < 0.5 {
+            self.forward = Vec3::new(0.0, 0.0, -1.0);
+        }
+
+        let up = if self.up.length() > 0.0 { self.up } else { Vec3::up() };
+        let mut right = self.forward.cross(&up);
+
+        if right.length() < 1e-6 {
+            let previous = self.right - self.forward * self.right.dot(&self.forward);
+            right = if previous.length() > 1e-3 {
+                previous
+            } else {
+                let axis = if self.forward.x.abs() < 0.9 { Vec3::new(1.0, 0.0, 0.0) } else { Vec3::new(0.0, 0.0, 1.0) };
+                self.forward.cross(&axis)
+            };
+        }
+
+        let right = right.normalize();
+        let camera_up = right.cross(&self.forward).normalize();
+
+        let roll = degrees_to_radians(self.roll);
+        self.right = right * roll.cos() + camera_up * roll.sin();
+        self.camera_up = camera_up * roll.cos() - right * roll.sin();
+    }
+
+    pub fn get_ray(&self, u: Float, v: Float) -> Ray {
+        let ndc_x = (u * 2.0) - 1.0;
+        let ndc_y = (v * 2.0) - 1.0;
+
+        match self.projection {
+            Projection::Perspective => Ray::new(self.position, self.pinhole_direction(u, v)),
+            Projection::Orthographic => {
+                let half_height = (degrees_to_radians(self.fov) * 0.5).tan() * (self.target - self.position).length();
+                let half_width = half_height * self.aspect_ratio;
+                let origin = self.position
+                    + self.right * (ndc_x * half_width)
+                    + self.camera_up * (ndc_y * half_height);
+
+                Ray::new(origin, self.forward)
+            }
+            Projection::Fisheye => {
+                let x = ndc_x * self.aspect_ratio;
+                let radius = (x * x + ndc_y * ndc_y).sqrt();
+                if radius == 0.0 {
+                    return Ray::new(self.position, self.forward);
+                }
+
+                let theta = (radius * degrees_to_radians(self.fov) * 0.5).min(consts::PI);
+                let side = (self.right * (x / radius) + self.camera_up * (ndc_y / radius)) * theta.sin();
+
+                Ray::new(self.position, self.forward * theta.cos() + side)
+            }
+            Projection::Equirectangular => {
+                let longitude = ndc_x * consts::PI;
+                let latitude = ndc_y * consts::FRAC_PI_2;
+                let direction = self.forward * (latitude.cos() * longitude.cos())
+                    + self.right * (latitude.cos() * longitude.sin())
+                    + self.camera_up * latitude.sin();
+
+                Ray::new(self.position, direction)
+            }
+        }
+    }
+
+    /// Ray from a point on the lens, where `lens_u` and `lens_v` in [0, 1) select the point
+    /// on the aperture. All rays through the same (u, v) meet on the focus plane.
+    /// Only the perspective projection has a lens; the others always act as pinholes.
+    pub fn get_ray_through_lens(&self, u: Float, v: Float, lens_u: Float, lens_v: Float) -> Ray {
+        if self.aperture <= 0.0 || self.projection != Projection::Perspective {
+            return self.get_ray(u, v);
+        }
+
+        let direction = self.pinhole_direction(u, v);
+
+        let (lens_x, lens_y) = if self.aperture_blades >= 3 {
+            sampling::regular_polygon(lens_u, lens_v, self.aperture_blades, degrees_to_radians(self.aperture_rotation))
+        } else {
+            sampling::concentric_disk(lens_u, lens_v)
+        };
+
+        let lens_radius = self.aperture * 0.5;
+        let origin = self.position + self.right * (lens_x * lens_radius) + self.camera_up * (lens_y * lens_radius);
+        let focus_point = self.position + direction * (self.focus_distance / direction.dot(&self.forward));
+
+        Ray::new(origin, focus_point - origin)
+    }
+
+    /// Lens ray at `time` in [0, 1] across the shutter interval. A moving camera is posed
+    /// between its start and end pose first.
+    pub fn get_ray_at_time(&self, u: Float, v: Float, lens_u: Float, lens_v: Float, time: Float) -> Ray {
+        let ray = match self.end_pose {
+            Some((end_position, end_target)) if time > 0.0 => {
+                let mut posed = self.clone();
+                posed.look_at(
+                    self.position.lerp(&end_position, time),
+                    self.target.lerp(&end_target, time),
+                    self.up,
+                );
+                posed.get_ray_through_lens(u, v, lens_u, lens_v)
+            }
+            _ => self.get_ray_through_lens(u, v, lens_u, lens_v),
+        };
+
+        ray.with_time(time)
+    }
+
+    /// Sets the focus distance to whatever the ray through (u, v) hits first.
+    /// Returns the new focus distance, or None if the ray hits nothing.
+    pub fn autofocus(&mut self, scene: &Scene, u: Float, v: Float) -> Option<Float> {
+        let ray = self.get_ray(u, v);
+        let hit = scene.hit(&ray, 0.0, Float::INFINITY)?;
+
+        // Focus plane is perpendicular to the view direction
+        self.focus_distance = hit.t * ray.direction.dot(&self.forward);
+        Some(self.focus_distance)
     }
 
-    pub fn get_ray(&self, u: f32, v: f32) -> Ray {
+    fn pinhole_direction(&self, u: Float, v: Float) -> Vec3 {
         let ndc_x = (u * 2.0) - 1.0;
         let ndc_y = (v * 2.0) - 1.0;
 
@@ -55,8 +428,113 @@ impl Camera {
                           + self.right * (ndc_x * half_width)
                           + self.camera_up * (ndc_y * half_height);
 
-        let direction = (target_point - self.position).normalize();
+        (target_point - self.position).normalize()
+    }
+}
+
+/// The pose, lens and shutter; the basis is derived from them
+impl Fingerprinted for Camera {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint
+            .add(&self.position)
+            .add(&self.target)
+            .add(&self.up)
+            .add(&self.roll)
+            .add(&self.fov)
+            .add(&self.aspect_ratio)
+            .add(&self.focal_length)
+            .add(&self.aperture)
+            .add(&self.focus_distance)
+            .add(&self.aperture_blades)
+            .add(&self.aperture_rotation)
+            .add(&self.projection)
+            .add(&self.shutter_open)
+            .add(&self.shutter_close)
+            .add(&self.end_pose);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene_file;
+
+    fn camera(fov: Float) -> Camera {
+        Camera::new(Point3::new(0.0, 0.0, 5.0), Point3::origin(), Vec3::up(), fov, 1.0)
+    }
+
+    #[test]
+    fn fov_is_clamped_for_the_projection() {
+        assert_eq!(camera(200.0).fov(), 179.0);
+        assert_eq!(camera(200.0).with_projection(Projection::Orthographic).fov(), 179.0);
+
+        let mut fisheye = camera(45.0).with_projection(Projection::Fisheye);
+        fisheye.set_fov(200.0);
+        assert_eq!(fisheye.fov(), 200.0);
+        fisheye.set_fov(400.0);
+        assert_eq!(fisheye.fov(), 359.0);
+        assert_eq!(fisheye.with_projection(Projection::Perspective).fov(), 179.0);
+
+        // A perspective camera never sees past the side of its view
+        let wide = camera(500.0);
+        let edge = wide.get_ray(1.0, 0.5).direction;
+        assert!(edge.x.is_finite() && edge.dot(&wide.forward()) > 0.0, "{:?}", edge);
+    }
+
+    #[test]
+    fn scene_files_keep_a_wide_fisheye_fov() {
+        let camera = scene_file::parse_camera_line("position=0,0,5 target=0,0,0 fov=220 projection=fisheye", 1.0).unwrap();
+        assert_eq!(camera.fov(), 220.0);
+        let line = scene_file::camera_to_line(&camera);
+        assert_eq!(scene_file::parse_camera_line(&line, 1.0).unwrap().fov(), 220.0);
+    }
+
+    fn assert_orthonormal(camera: &Camera) {
+        let basis = [camera.forward(), camera.right(), camera.camera_up()];
+        for (i, axis) in basis.iter().enumerate() {
+            assert!(axis.x.is_finite() && axis.y.is_finite() && axis.z.is_finite(), "{:?}", basis);
+            assert!((axis.length() - 1.0).abs() < 1e-4, "{:?}", basis);
+            for other in &basis[i + 1..] {
+                assert!(axis.dot(other).abs() < 1e-4, "{:?}", basis);
+            }
+        }
+    }
+
+    #[test]
+    fn looking_along_up_keeps_an_orthonormal_basis() {
+        let mut camera = camera(45.0);
+        for target in [Point3::new(0.0, 10.0, 0.0), Point3::new(0.0, -10.0, 0.0)] {
+            camera.look_at(Point3::origin(), target, Vec3::up());
+            assert_orthonormal(&camera);
+            assert!((camera.forward().y.abs() - 1.0).abs() < 1e-4);
+        }
+
+        for pitch in [90.0, -90.0, 90.0] {
+            camera.set_pitch(pitch);
+            assert_orthonormal(&camera);
+            assert!((camera.pitch() - pitch).abs() < 1e-2, "{} vs {}", camera.pitch(), pitch);
+        }
+    }
+
+    #[test]
+    fn yaw_pitch_and_roll_update_the_basis() {
+        let mut camera = camera(45.0);
+
+        camera.set_yaw(90.0);
+        assert_orthonormal(&camera);
+        assert!((camera.forward() - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-4, "{:?}", camera.forward());
+        assert!((camera.yaw() - 90.0).abs() < 1e-3);
+
+        camera.set_pitch(30.0);
+        assert_orthonormal(&camera);
+        assert!((camera.pitch() - 30.0).abs() < 1e-3);
+        assert!((camera.yaw() - 90.0).abs() < 1e-3);
 
-        Ray::new(self.position, direction)
+        let forward = camera.forward();
+        let right = camera.right();
+        camera.set_roll(90.0);
+        assert_orthonormal(&camera);
+        assert!((camera.forward() - forward).length() < 1e-4);
+        assert!((camera.camera_up() + right).length() < 1e-4, "{:?}", camera.camera_up());
     }
 }
@@ -0,0 +1,363 @@
+// Resumable renders: every finished tile is appended to a checkpoint file, so a render that
+// is interrupted, even by Ctrl+C, can pick up with the tiles it had not finished
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use crate::tiles::Rect;
+use crate::vec3::{Color, Float, Point3, Vec3};
+
+const MAGIC: &str = "raytracer checkpoint 1";
+
+/// The pixels of one rendered tile, row by row
+#[derive(Debug, Clone)]
+pub struct TileData {
+    pub rect: Rect,
+    pub colors: Vec<Vec3>,
+    pub sample_counts: Vec<u32>,
+    /// One buffer per rendered pass, in the order the passes were requested
+    pub passes: Vec<Vec<Vec3>>,
+}
+
+impl TileData {
+    /// The tile as bytes, as checkpoints store it
+    pub fn encode(&self) -> Vec<u8> {
+        let pixels = self.colors.len();
+        let mut record = Vec::with_capacity(16 + pixels * record_pixel_size(self.passes.len()));
+
+        for value in [self.rect.x, self.rect.y, self.rect.width, self.rect.height] {
+            record.extend_from_slice(&value.to_le_bytes());
+        }
+        for i in 0..pixels {
+            write_vec3(&mut record, self.colors[i]);
+            record.extend_from_slice(&self.sample_counts[i].to_le_bytes());
+            for pass in &self.passes {
+                write_vec3(&mut record, pass[i]);
+            }
+        }
+        record
+    }
+
+    /// Reads a tile written by `encode` with `pass_count` passes; `None` unless `data` holds
+    /// exactly one tile
+    pub fn decode(data: &[u8], pass_count: usize) -> Option<TileData> {
+        let mut reader = Reader { data, position: 0 };
+        reader.tile(pass_count).filter(|_| reader.position == data.len())
+    }
+}
+
+/// Identifies the settings, scene and camera a checkpoint was rendered with, so a changed
+/// render never resumes from stale tiles. It is 64-bit FNV-1a over the bytes each value's
+/// `Fingerprinted` impl writes, so the same render gets the same fingerprint from any build.
+pub struct Fingerprint(u64);
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+impl Fingerprint {
+    pub fn new() -> Self {
+        Fingerprint(FNV_OFFSET_BASIS)
+    }
+
+    pub fn add(&mut self, value: &(impl Fingerprinted + ?Sized)) -> &mut Self {
+        value.fingerprint(self);
+        self
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(FNV_PRIME);
+        }
+        self
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for Fingerprint {
+    fn default() -> Self {
+        Fingerprint::new()
+    }
+}
+
+/// A value that can change the pixels of a render. Implementations add every field that
+/// does, in a fixed order, and skip values derived from the others. Enums add a name or tag
+/// first, so different variants with the same fields don't collide.
+pub trait Fingerprinted {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint);
+}
+
+impl Fingerprinted for u32 {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.bytes(&self.to_le_bytes());
+    }
+}
+
+impl Fingerprinted for u64 {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.bytes(&self.to_le_bytes());
+    }
+}
+
+impl Fingerprinted for i32 {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.bytes(&self.to_le_bytes());
+    }
+}
+
+impl Fingerprinted for usize {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add(&(*self as u64));
+    }
+}
+
+impl Fingerprinted for bool {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.bytes(&[*self as u8]);
+    }
+}
+
+/// Widened to f64, like tile values, so f32 and f64 builds agree on values both can hold
+impl Fingerprinted for Float {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.bytes(&to_f64(*self).to_le_bytes());
+    }
+}
+
+impl Fingerprinted for str {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add(&self.len()).bytes(self.as_bytes());
+    }
+}
+
+impl Fingerprinted for Vec3 {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add(&self.x).add(&self.y).add(&self.z);
+    }
+}
+
+impl Fingerprinted for Point3 {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add(&self.to_vec3());
+    }
+}
+
+impl Fingerprinted for Color {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add(&self.to_vec3());
+    }
+}
+
+impl<T: Fingerprinted> Fingerprinted for Option<T> {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        match self {
+            Some(value) => fingerprint.add(&true).add(value),
+            None => fingerprint.add(&false),
+        };
+    }
+}
+
+impl<A: Fingerprinted, B: Fingerprinted> Fingerprinted for (A, B) {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add(&self.0).add(&self.1);
+    }
+}
+
+impl<T: Fingerprinted> Fingerprinted for [T] {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add(&self.len());
+        for value in self {
+            fingerprint.add(value);
+        }
+    }
+}
+
+impl<T: Fingerprinted, const N: usize> Fingerprinted for [T; N] {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add(&self[..]);
+    }
+}
+
+impl<T: Fingerprinted + ?Sized> Fingerprinted for Box<T> {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add(&**self);
+    }
+}
+
+/// A checkpoint file open for appending tiles
+pub struct Checkpoint {
+    path: String,
+    file: File,
+    pass_count: usize,
+}
+
+impl Checkpoint {
+    /// Opens the checkpoint at `path` and returns the tiles it holds. A missing file, or one
+    /// written by a render with a different `fingerprint`, starts an empty checkpoint. A tile
+    /// cut short by an interruption is dropped.
+    pub fn open(path: &str, fingerprint: u64, pass_count: usize) -> Result<(Checkpoint, Vec<TileData>), String> {
+        let error = |error: std::io::Error| format!("{}: {}", path, error);
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path).map_err(error)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).map_err(error)?;
+
+        if !data.is_empty() && !data.starts_with(MAGIC.as_bytes()) {
+            return Err(format!("{} exists and is not a checkpoint", path));
+        }
+
+        let header = format!("{} {:016x} {}\n", MAGIC, fingerprint, pass_count);
+        let (tiles, valid_length) = match data.strip_prefix(header.as_bytes()) {
+            Some(records) => {
+                let (tiles, length) = read_tiles(records, pass_count);
+                (tiles, header.len() + length)
+            }
+            None => (Vec::new(), 0),
+        };
+
+        file.set_len(valid_length as u64).map_err(error)?;
+        file.seek(SeekFrom::Start(valid_length as u64)).map_err(error)?;
+        if valid_length == 0 {
+            file.write_all(header.as_bytes()).map_err(error)?;
+        }
+
+        let checkpoint = Checkpoint { path: path.to_string(), file, pass_count };
+        Ok((checkpoint, tiles))
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Appends a finished tile. It is written in one piece, so an interruption loses at most
+    /// this tile.
+    pub fn write(&mut self, tile: &TileData) -> Result<(), String> {
+        debug_assert_eq!(tile.passes.len(), self.pass_count);
+        self.file.write_all(&tile.encode()).map_err(|error| format!("{}: {}", self.path, error))
+    }
+}
+
+/// Bytes per pixel of a tile record: color, sample count and pass values
+fn record_pixel_size(pass_count: usize) -> usize {
+    24 + 4 + pass_count * 24
+}
+
+/// The complete tile records at the start of `records` and the number of bytes they take
+fn read_tiles(records: &[u8], pass_count: usize) -> (Vec<TileData>, usize) {
+    let mut tiles = Vec::new();
+    let mut reader = Reader { data: records, position: 0 };
+
+    loop {
+        let start = reader.position;
+        let Some(tile) = reader.tile(pass_count) else {
+            return (tiles, start);
+        };
+        tiles.push(tile);
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.data.get(self.position..self.position + N)?;
+        self.position += N;
+        bytes.try_into().ok()
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes().map(u32::from_le_bytes)
+    }
+
+    fn vec3(&mut self) -> Option<Vec3> {
+        let mut component = || self.bytes().map(|bytes| from_f64(f64::from_le_bytes(bytes)));
+        Some(Vec3::new(component()?, component()?, component()?))
+    }
+
+    fn tile(&mut self, pass_count: usize) -> Option<TileData> {
+        let rect = Rect::new(self.u32()?, self.u32()?, self.u32()?, self.u32()?);
+        let pixels = rect.area() as usize;
+        if rect.is_empty() || self.data.len() - self.position < pixels * record_pixel_size(pass_count) {
+            return None;
+        }
+
+        let mut tile = TileData {
+            rect,
+            colors: Vec::with_capacity(pixels),
+            sample_counts: Vec::with_capacity(pixels),
+            passes: vec![Vec::with_capacity(pixels); pass_count],
+        };
+        for _ in 0..pixels {
+            tile.colors.push(self.vec3()?);
+            tile.sample_counts.push(self.u32()?);
+            for pass in &mut tile.passes {
+                pass.push(self.vec3()?);
+            }
+        }
+        Some(tile)
+    }
+}
+
+/// Values are stored as f64 so that a resumed render matches an uninterrupted one exactly
+/// in either precision
+fn write_vec3(record: &mut Vec<u8>, value: Vec3) {
+    for component in [value.x, value.y, value.z] {
+        record.extend_from_slice(&to_f64(component).to_le_bytes());
+    }
+}
+
+#[allow(clippy::unnecessary_cast)]
+fn to_f64(value: Float) -> f64 {
+    value as f64
+}
+
+#[allow(clippy::unnecessary_cast)]
+fn from_f64(value: f64) -> Float {
+    value as Float
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Cube;
+    use crate::material::Material;
+    use crate::scene::Scene;
+
+    fn scene_with_cube_at(x: Float) -> Scene {
+        let mut scene = Scene::new();
+        scene.add_cube(Cube::new(Point3::new(x, 0.0, -3.0), Vec3::new(1.0, 1.0, 1.0), Material::new()));
+        scene
+    }
+
+    #[test]
+    fn fingerprint_is_fnv_1a() {
+        // Published FNV-1a 64-bit test vectors
+        assert_eq!(Fingerprint::new().finish(), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(Fingerprint::new().bytes(b"a").finish(), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(Fingerprint::new().bytes(b"foobar").finish(), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn fingerprint_covers_object_geometry() {
+        let fingerprint = |scene: &Scene| Fingerprint::new().add(scene).finish();
+        assert_eq!(fingerprint(&scene_with_cube_at(1.0)), fingerprint(&scene_with_cube_at(1.0)));
+        assert_ne!(fingerprint(&scene_with_cube_at(1.0)), fingerprint(&scene_with_cube_at(1.5)));
+        assert_ne!(fingerprint(&scene_with_cube_at(1.0)), fingerprint(&Scene::new()));
+    }
+
+    #[test]
+    fn fingerprint_separates_neighbouring_values() {
+        // Lengths are added before contents, so moving a value between lists changes it
+        let split = |first: &[u32], second: &[u32]| Fingerprint::new().add(first).add(second).finish();
+        assert_ne!(split(&[1, 2], &[3]), split(&[1], &[2, 3]));
+        assert_ne!(
+            Fingerprint::new().add(&Rect::new(0, 0, 8, 8)).finish(),
+            Fingerprint::new().add(&Rect::new(0, 0, 8, 4)).finish()
+        );
+        assert_ne!(Fingerprint::new().add(&None::<u32>).finish(), Fingerprint::new().add(&Some(0u32)).finish());
+    }
+}
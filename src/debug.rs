@@ -0,0 +1,119 @@
+// Debug visualization modes for inspecting scene hits
+
+use crate::vec3::{Float, Vec3};
+use crate::geometry::HitRecord;
+use crate::checkpoint::{Fingerprint, Fingerprinted};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugMode {
+    Normals,
+    DepthLinear,
+    DepthLog,
+    Uv,
+    Albedo,
+    ObjectId,
+    HitCount,
+    AmbientOcclusion,
+}
+
+impl DebugMode {
+    pub const ALL: [DebugMode; 8] = [
+        DebugMode::Normals,
+        DebugMode::DepthLinear,
+        DebugMode::DepthLog,
+        DebugMode::Uv,
+        DebugMode::Albedo,
+        DebugMode::ObjectId,
+        DebugMode::HitCount,
+        DebugMode::AmbientOcclusion,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DebugMode::Normals => "normals",
+            DebugMode::DepthLinear => "depth",
+            DebugMode::DepthLog => "logdepth",
+            DebugMode::Uv => "uv",
+            DebugMode::Albedo => "albedo",
+            DebugMode::ObjectId => "objectid",
+            DebugMode::HitCount => "hitcount",
+            DebugMode::AmbientOcclusion => "ao",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<DebugMode> {
+        DebugMode::ALL.iter().copied().find(|mode| mode.name() == name)
+    }
+
+    /// Cycles beauty -> each debug mode -> beauty
+    pub fn cycle(mode: Option<DebugMode>) -> Option<DebugMode> {
+        match mode {
+            None => Some(DebugMode::ALL[0]),
+            Some(current) => {
+                let index = DebugMode::ALL.iter().position(|m| *m == current).unwrap_or(0);
+                DebugMode::ALL.get(index + 1).copied()
+            }
+        }
+    }
+
+    /// Color for a primary ray hit. `HitCount` (per image) and `AmbientOcclusion` (needs the
+    /// scene) are resolved by the raytracer.
+    pub fn shade(&self, hit: &HitRecord, depth_range: Float) -> Vec3 {
+        match self {
+            DebugMode::Normals => (hit.normal + Vec3::new(1.0, 1.0, 1.0)) * 0.5,
+            DebugMode::DepthLinear => {
+                let depth = (hit.t / depth_range).clamp(0.0, 1.0);
+                Vec3::new(1.0, 1.0, 1.0) * (1.0 - depth)
+            }
+            DebugMode::DepthLog => {
+                let depth = ((1.0 + hit.t).ln() / (1.0 + depth_range).ln()).clamp(0.0, 1.0);
+                Vec3::new(1.0, 1.0, 1.0) * (1.0 - depth)
+            }
+            DebugMode::Uv => Vec3::new(hit.u, hit.v, 0.0),
+            DebugMode::Albedo => hit.material.get_color_at_point(&hit.local_point).to_vec3(),
+            DebugMode::ObjectId => id_to_color(hit.object_id),
+            DebugMode::HitCount | DebugMode::AmbientOcclusion => Vec3::zero(),
+        }
+    }
+
+    /// Color for rays that miss every object. Open sky is fully unoccluded.
+    pub fn miss_color(&self) -> Vec3 {
+        match self {
+            DebugMode::AmbientOcclusion => Vec3::new(1.0, 1.0, 1.0),
+            _ => Vec3::zero(),
+        }
+    }
+}
+
+impl Fingerprinted for DebugMode {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add(self.name());
+    }
+}
+
+/// Stable false color for an object index
+pub fn id_to_color(id: usize) -> Vec3 {
+    let mut hash = (id as u32).wrapping_add(1).wrapping_mul(0x9E37_79B9);
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x85EB_CA6B);
+    hash ^= hash >> 13;
+
+    Vec3::new(
+        (hash & 0xFF) as Float / 255.0,
+        ((hash >> 8) & 0xFF) as Float / 255.0,
+        ((hash >> 16) & 0xFF) as Float / 255.0,
+    )
+}
+
+/// Blue -> green -> red heat map for a value in [0, 1]
+pub fn heat_map(value: Float) -> Vec3 {
+    let value = value.clamp(0.0, 1.0);
+
+    if value < 0.5 {
+        let t = value * 2.0;
+        Vec3::new(0.0, t, 1.0 - t)
+    } else {
+        let t = (value - 0.5) * 2.0;
+        Vec3::new(t, 1.0 - t, 0.0)
+    }
+}
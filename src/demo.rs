@@ -0,0 +1,65 @@
+// The built-in demo scene: a checkerboard cube on a light gray floor, shown when no scene
+// file is given
+
+use crate::vec3::{Color, Float, Point3, Vec3};
+use crate::material::Material;
+use crate::geometry::{Cube, Plane};
+use crate::light::Light;
+use crate::camera::Camera;
+use crate::scene::Scene;
+
+/// Camera positioned to view the cube from above and at an angle to see 2 sides
+pub fn default_camera(aspect_ratio: Float) -> Camera {
+    Camera::new(
+        Point3::new(3.0, 4.0, 2.0),    // Position: higher and to the side
+        Point3::new(0.0, -0.5, -3.0),  // Look at: the cube center
+        Vec3::up(),
+        45.0,
+        aspect_ratio,
+    )
+}
+
+pub fn create_scene() -> Scene {
+    let mut scene = Scene::new();
+    
+    // Change background to white
+    scene.set_background_color(Color::white());
+    
+    // Add light gray floor
+    let floor_material = Material::new()
+        .with_id(1)
+        .with_color(Color::new(0.7, 0.7, 0.7))  // Light gray
+        .with_roughness(0.9);
+    
+    scene.add_plane(Plane::new(
+        Point3::new(0.0, -2.0, 0.0),  // Position
+        Vec3::up(),                   // Normal pointing up
+        floor_material,
+    ));
+    
+    let checkerboard_material = Material::new()
+        .with_id(2)
+        .with_checkerboard(
+            1.0,
+            Color::new(1.0, 0.0, 1.0), // Magenta
+            Color::black()            // Black
+        )
+        .with_roughness(0.3)
+        .with_specular(0.5)
+        .with_reflectivity(0.2);
+    
+    // Only one cube now
+    scene.add_cube(Cube::new(
+        Point3::new(0.0, -0.5, -3.0),
+        Vec3::new(1.5, 1.5, 1.5),
+        checkerboard_material,
+    ));
+
+    scene.add_light(Light::new(
+        Point3::new(-3.0, 5.0, 2.0),
+        Color::new(1.0, 1.0, 0.9),
+        1.0,
+    ));
+    
+    scene
+}
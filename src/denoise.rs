@@ -0,0 +1,205 @@
+// Edge-avoiding à-trous wavelet denoiser (Dammertz et al. 2010), guided by the albedo,
+// normal and depth passes
+
+use crate::vec3::{Float, Vec3};
+
+/// B3 spline taps of the 5x5 à-trous kernel
+const KERNEL: [Float; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Albedo floor when dividing it out, so black surfaces don't blow up
+const MIN_ALBEDO: Float = 0.01;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Denoiser {
+    /// How different two colors may be and still be averaged; 0 disables the filter,
+    /// 1 suits a few samples per pixel, larger values smooth harder
+    pub strength: Float,
+    /// Filter passes; each doubles the tap spacing, so 5 passes reach 62 pixels across
+    pub iterations: u32,
+    /// Normal similarity exponent; larger keeps creases sharper
+    pub normal_sigma: Float,
+    /// Allowed relative depth change per pixel of distance
+    pub depth_sigma: Float,
+    pub albedo_sigma: Float,
+}
+
+/// Per-pixel guide values from the render passes
+pub struct Guides<'a> {
+    pub albedo: &'a [Vec<Vec3>],
+    pub normal: &'a [Vec<Vec3>],
+    /// Infinite where the camera ray escaped. Those pixels, and pixels touching them, are
+    /// left as they are since their albedo mixes in the background.
+    pub depth: &'a [Vec<Vec3>],
+}
+
+impl Denoiser {
+    /// Passes beyond this reach further than any image is wide
+    pub const MAX_ITERATIONS: u32 = 12;
+
+    pub fn new(strength: Float) -> Self {
+        Denoiser {
+            strength: strength.max(0.0),
+            iterations: 5,
+            normal_sigma: 64.0,
+            depth_sigma: 0.02,
+            albedo_sigma: 0.1,
+        }
+    }
+
+    /// Clamped to `MAX_ITERATIONS`
+    pub fn with_iterations(mut self, iterations: u32) -> Self {
+        self.iterations = iterations.min(Self::MAX_ITERATIONS);
+        self
+    }
+
+    /// Filters linear radiance in place. Lighting is divided by the albedo first so texture
+    /// detail isn't blurred, then multiplied back.
+    pub fn apply(&self, image: &mut [Vec<Vec3>], guides: &Guides) {
+        if self.strength <= 0.0 || self.iterations == 0 {
+            return;
+        }
+
+        let height = image.len();
+        let width = image.first().map_or(0, |row| row.len());
+        let albedo_at = |x: usize, y: usize| {
+            guides.albedo[y][x].max(&Vec3::new(MIN_ALBEDO, MIN_ALBEDO, MIN_ALBEDO))
+        };
+
+        let solid: Vec<Vec<bool>> = (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| {
+                        (y.saturating_sub(1)..(y + 2).min(height))
+                            .all(|qy| (x.saturating_sub(1)..(x + 2).min(width)).all(|qx| guides.depth[qy][qx].x.is_finite()))
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut lighting: Vec<Vec<Vec3>> = (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| {
+                        image[y][x] / albedo_at(x, y)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        for iteration in 0..self.iterations.min(Self::MAX_ITERATIONS) {
+            // The color tolerance tightens as the taps spread, as in the paper
+            let color_sigma = self.strength * 0.25 * Float::powi(0.5, iteration as i32);
+            lighting = self.filter_pass(&lighting, guides, &solid, 1 << iteration, color_sigma);
+        }
+
+        for y in 0..height {
+            for x in 0..width {
+                if solid[y][x] {
+                    image[y][x] = lighting[y][x] * albedo_at(x, y);
+                }
+            }
+        }
+    }
+
+    fn filter_pass(
+        &self,
+        lighting: &[Vec<Vec3>],
+        guides: &Guides,
+        solid: &[Vec<bool>],
+        step: usize,
+        color_sigma: Float,
+    ) -> Vec<Vec<Vec3>> {
+        let height = lighting.len() as isize;
+        let width = lighting.first().map_or(0, |row| row.len()) as isize;
+        let mut filtered = lighting.to_vec();
+
+        for y in 0..height {
+            for x in 0..width {
+                let (px, py) = (x as usize, y as usize);
+                if !solid[py][px] {
+                    continue;
+                }
+
+                // Neighbors are compared against a 3x3 mean so lone outliers still get averaged
+                let color = tonemapped(local_mean(lighting, solid, px, py));
+                let depth = guides.depth[py][px].x;
+                let normal = guides.normal[py][px];
+                let albedo = guides.albedo[py][px];
+                let depth_tolerance = self.depth_sigma * depth * step as Float;
+
+                let mut sum = Vec3::zero();
+                let mut weight_sum = 0.0;
+
+                for (j, ky) in KERNEL.iter().enumerate() {
+                    let qy = y + (j as isize - 2) * step as isize;
+                    if qy < 0 || qy >= height {
+                        continue;
+                    }
+
+                    for (i, kx) in KERNEL.iter().enumerate() {
+                        let qx = x + (i as isize - 2) * step as isize;
+                        if qx < 0 || qx >= width {
+                            continue;
+                        }
+
+                        let (qx, qy) = (qx as usize, qy as usize);
+                        if !solid[qy][qx] {
+                            continue;
+                        }
+                        let q_depth = guides.depth[qy][qx].x;
+
+                        // The pixel itself always keeps its kernel weight, so pixels that
+                        // match no neighbor (mixed materials at edges) stay as they are
+                        if (qx, qy) == (px, py) {
+                            sum += lighting[py][px] * (kx * ky);
+                            weight_sum += kx * ky;
+                            continue;
+                        }
+
+                        let color_distance = (color - tonemapped(lighting[qy][qx])).length_squared();
+                        let albedo_distance = (albedo - guides.albedo[qy][qx]).length_squared();
+                        let normal_weight = normal.dot(&guides.normal[qy][qx]).max(0.0).powf(self.normal_sigma);
+
+                        let weight = kx
+                            * ky
+                            * normal_weight
+                            * (-color_distance / (color_sigma * color_sigma).max(1e-8)).exp()
+                            * (-albedo_distance / (self.albedo_sigma * self.albedo_sigma)).exp()
+                            * (-(depth - q_depth).abs() / depth_tolerance.max(1e-6)).exp();
+
+                        sum += lighting[qy][qx] * weight;
+                        weight_sum += weight;
+                    }
+                }
+
+                if weight_sum > 0.0 {
+                    filtered[py][px] = sum / weight_sum;
+                }
+            }
+        }
+
+        filtered
+    }
+}
+
+fn local_mean(lighting: &[Vec<Vec3>], solid: &[Vec<bool>], x: usize, y: usize) -> Vec3 {
+    let mut sum = Vec3::zero();
+    let mut count = 0;
+
+    for qy in y.saturating_sub(1)..(y + 2).min(lighting.len()) {
+        for qx in x.saturating_sub(1)..(x + 2).min(lighting[qy].len()) {
+            if solid[qy][qx] {
+                sum += lighting[qy][qx];
+                count += 1;
+            }
+        }
+    }
+
+    sum / count as Float
+}
+
+/// Compresses HDR values before comparing them, so bright pixels don't dominate the color weight
+fn tonemapped(color: Vec3) -> Vec3 {
+    let compress = |value: Float| value.max(0.0) / (1.0 + value.max(0.0));
+    Vec3::new(compress(color.x), compress(color.y), compress(color.z))
+}
@@ -0,0 +1,791 @@
+// Distributed rendering over TCP. A coordinator sends each worker connection the render
+// settings, the camera and the scene file along with the files it refers to, then hands out
+// the tiles of a still, or the frames of an animation, one at a time and puts the results
+// together. Work held by a worker that fails or falls silent goes to the next free worker.
+//
+// Every message is a header line, `keyword key=value ... bytes=N`, followed by N bytes:
+//
+//   worker      -> coordinator   hello version=1
+//   coordinator -> worker        settings width=800 height=600 samples=16 ...
+//                                camera             (a scene file camera line)
+//                                asset              (path, newline, file contents)
+//                                scene builtin=demo, or scene (scene file text)
+//                                work x=0 y=0 width=32 height=32 [frame=3]
+//                                done
+//   worker      -> coordinator   busy               (every second while rendering)
+//                                result primary_rays=... (the tile, as `TileData::encode`)
+//                                error              (why the job can't be rendered)
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::animation::Animation;
+use crate::aov::Aov;
+use crate::camera::Camera;
+use crate::checkpoint::TileData;
+use crate::debug::DebugMode;
+use crate::demo;
+use crate::occlusion::AmbientOcclusion;
+use crate::raytracer::{RenderOutput, Raytracer};
+use crate::sampling::{AdaptiveSampling, Filter};
+use crate::scene::Scene;
+use crate::scene_file;
+use crate::stats::{Counter, Recorder, RenderStats};
+use crate::tiles::Rect;
+use crate::vec3::Float;
+
+const PROTOCOL_VERSION: u32 = 1;
+
+/// Workers report this often while rendering, so a silent worker can be told from a busy one
+const HEARTBEAT: Duration = Duration::from_secs(1);
+
+/// Connections are retried this often, both before the coordinator is up and after a failure
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Payloads larger than this are refused as corrupt
+const MAX_PAYLOAD: usize = 1 << 30;
+
+/// What every worker is sent before its first piece of work
+pub struct Job {
+    settings: String,
+    camera: String,
+    /// Scene file text; `None` renders the built-in demo scene
+    scene: Option<String>,
+    /// Files the scene refers to, by the path written in the scene file
+    assets: Vec<(String, Vec<u8>)>,
+    pass_count: usize,
+}
+
+impl Job {
+    /// The job of rendering the scene file at `scene_path`, or the demo scene, through
+    /// `camera` with `raytracer`'s settings. Environment maps the scene uses are read now
+    /// and sent along, so workers need no shared files; their paths must lie inside the
+    /// scene file's directory.
+    pub fn new(raytracer: &Raytracer, camera: &Camera, scene_path: Option<&str>) -> Result<Job, String> {
+        let mut assets = Vec::new();
+        let scene = match scene_path {
+            Some(path) => {
+                let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+                let base_dir = Path::new(path).parent().unwrap_or(Path::new("."));
+                for file in scene_file::referenced_files(&text) {
+                    if !is_relative_inside(&file) {
+                        return Err(format!("{}: '{}' must be a path inside the scene's directory", path, file));
+                    }
+                    let asset_path = base_dir.join(&file);
+                    let data = std::fs::read(&asset_path).map_err(|e| format!("{}: {}", asset_path.display(), e))?;
+                    assets.push((file, data));
+                }
+                Some(text)
+            }
+            None => None,
+        };
+
+        Ok(Job {
+            settings: settings_line(raytracer, camera),
+            camera: scene_file::camera_to_line(camera),
+            scene,
+            assets,
+            pass_count: raytracer.passes().len(),
+        })
+    }
+
+    fn send(&self, stream: &mut TcpStream) -> io::Result<()> {
+        send(stream, &self.settings, &[])?;
+        send(stream, "camera", self.camera.as_bytes())?;
+        for (path, data) in &self.assets {
+            let mut payload = format!("{}\n", path).into_bytes();
+            payload.extend_from_slice(data);
+            send(stream, "asset", &payload)?;
+        }
+        match &self.scene {
+            Some(text) => send(stream, "scene", text.as_bytes()),
+            None => send(stream, "scene builtin=demo", &[]),
+        }
+    }
+}
+
+/// Settings that decide pixel values. The display transform and denoiser are applied by the
+/// coordinator, which asks for the denoiser's guide passes.
+fn settings_line(raytracer: &Raytracer, camera: &Camera) -> String {
+    let mut line = format!(
+        "settings width={} height={} aspect={} max_depth={} samples={} filter={} seed={} depth_range={} packets={}",
+        raytracer.width,
+        raytracer.height,
+        camera.aspect_ratio(),
+        raytracer.max_depth,
+        raytracer.samples_per_pixel,
+        raytracer.filter.name(),
+        raytracer.seed,
+        raytracer.debug_depth_range,
+        raytracer.packets,
+    );
+
+    if let Some(adaptive) = raytracer.adaptive {
+        line += &format!(" adaptive={},{},{}", adaptive.min_samples, adaptive.max_samples, adaptive.threshold);
+    }
+    if let Some(occlusion) = raytracer.ambient_occlusion {
+        line += &format!(" ao={},{}", occlusion.samples, occlusion.distance);
+    }
+    if let Some(mode) = raytracer.debug_mode {
+        line += &format!(" debug={}", mode.name());
+    }
+    let passes = raytracer.passes();
+    if !passes.is_empty() {
+        let names: Vec<&str> = passes.iter().map(|aov| aov.name()).collect();
+        line += &format!(" aovs={}", names.join(","));
+    }
+
+    line
+}
+
+/// A piece of work: a tile of a still, or a whole frame of an animation
+#[derive(Debug, Clone, Copy)]
+struct WorkItem {
+    frame: Option<usize>,
+    rect: Rect,
+}
+
+/// A finished piece of work with when it was handed out, or why a worker can't render the
+/// job at all
+type Event = Result<(WorkItem, TileData, RenderStats, Instant), String>;
+
+/// Work not yet handed out, shared by the connections
+struct Queue {
+    pending: VecDeque<WorkItem>,
+    finished: bool,
+    /// Connections still being sent the job or holding work, by worker address. They are
+    /// shut down when the render ends, so their threads don't wait out the read timeout.
+    /// Idle connections aren't listed; they are told they're done instead.
+    busy: HashMap<SocketAddr, TcpStream>,
+}
+
+/// Listens for workers and farms renders out to them
+pub struct Coordinator {
+    listener: TcpListener,
+    timeout: Duration,
+    connect_timeout: Duration,
+}
+
+impl Coordinator {
+    /// Listens on `address`, e.g. "0.0.0.0:7878"; port 0 picks a free port
+    pub fn bind(address: &str) -> Result<Coordinator, String> {
+        let listener = TcpListener::bind(address).map_err(|e| format!("{}: {}", address, e))?;
+        Ok(Coordinator { listener, timeout: Duration::from_secs(30), connect_timeout: Duration::from_secs(300) })
+    }
+
+    /// A worker that sends nothing for `timeout` is dropped and its work handed to another.
+    /// Busy workers report every second.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// A render fails once no worker has been connected for `timeout`, before the first
+    /// connects or after all have dropped
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.listener.local_addr().map_err(|e| e.to_string())
+    }
+
+    /// Renders a still on the connected workers, one tile at a time, and returns it as
+    /// `Raytracer::render_output` would
+    pub fn render(&self, raytracer: &Raytracer, job: &Job) -> Result<RenderOutput, String> {
+        if raytracer.debug_mode == Some(DebugMode::HitCount) {
+            return Err("the hitcount visualization can't be split into tiles".to_string());
+        }
+
+        let started = Instant::now();
+        let items: Vec<WorkItem> = raytracer
+            .region()
+            .tiles(raytracer.tile_size, raytracer.tile_order, raytracer.seed)
+            .into_iter()
+            .map(|rect| WorkItem { frame: None, rect })
+            .collect();
+        let total = items.len();
+        let progress_step = total.div_ceil(10).max(1);
+
+        let mut tiles = Vec::with_capacity(total);
+        let mut stats = RenderStats::default();
+        self.distribute(job, items, |_, tile, tile_stats, _| {
+            tiles.push(tile);
+            stats.add(&tile_stats);
+            if raytracer.log_progress && tiles.len().is_multiple_of(progress_step) {
+                println!("Tile {} of {}", tiles.len(), total);
+            }
+        })?;
+
+        stats.render_time = started.elapsed();
+        Ok(raytracer.assemble(tiles, stats, started))
+    }
+
+    /// Renders `frames` of the job's animation on the connected workers, one frame each,
+    /// and hands every finished frame to `on_frame` in the order they finish
+    pub fn render_sequence(
+        &self,
+        raytracer: &Raytracer,
+        job: &Job,
+        frames: Range<usize>,
+        mut on_frame: impl FnMut(usize, RenderOutput),
+    ) -> Result<(), String> {
+        if raytracer.debug_mode == Some(DebugMode::HitCount) {
+            return Err("the hitcount visualization can't be rendered remotely".to_string());
+        }
+
+        let rect = raytracer.region();
+        let items = frames.map(|frame| WorkItem { frame: Some(frame), rect }).collect();
+        self.distribute(job, items, |item, tile, mut stats, started| {
+            let frame = item.frame.unwrap_or(0);
+            if raytracer.log_progress {
+                println!("Frame {} done", frame + 1);
+            }
+            stats.render_time = started.elapsed();
+            on_frame(frame, raytracer.assemble(vec![tile], stats, started));
+        })
+    }
+
+    /// Hands out `items` to whichever workers connect until every item has a result, which
+    /// goes to `on_result` on the calling thread along with when the item was handed out.
+    /// Fails if a worker can't render the job, or if no worker is connected for the connect
+    /// timeout.
+    fn distribute(
+        &self,
+        job: &Job,
+        items: Vec<WorkItem>,
+        mut on_result: impl FnMut(WorkItem, TileData, RenderStats, Instant),
+    ) -> Result<(), String> {
+        let total = items.len();
+        let queue = Mutex::new(Queue { pending: items.into(), finished: false, busy: HashMap::new() });
+        let ready = Condvar::new();
+        let connected = AtomicUsize::new(0);
+        let (events, results) = mpsc::channel();
+        self.listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+        println!("Waiting for workers on {}", self.local_addr()?);
+
+        let (queue, ready, connected) = (&queue, &ready, &connected);
+        std::thread::scope(|scope| {
+            let mut completed = 0;
+            let mut outcome = Ok(());
+            let mut unattended_since = Instant::now();
+
+            while completed < total {
+                loop {
+                    match self.listener.accept() {
+                        Ok((stream, address)) => {
+                            let clone = match stream.try_clone() {
+                                Ok(clone) => clone,
+                                Err(error) => {
+                                    eprintln!("Failed to accept worker {}: {}", address, error);
+                                    continue;
+                                }
+                            };
+                            lock(queue).busy.insert(address, clone);
+                            connected.fetch_add(1, Ordering::Relaxed);
+                            let events = events.clone();
+                            let timeout = self.timeout;
+                            scope.spawn(move || {
+                                serve_worker(stream, address, job, queue, ready, events, timeout);
+                                connected.fetch_sub(1, Ordering::Relaxed);
+                            });
+                        }
+                        Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(error) => {
+                            eprintln!("Failed to accept a worker: {}", error);
+                            break;
+                        }
+                    }
+                }
+
+                match results.recv_timeout(Duration::from_millis(50)) {
+                    Ok(Ok((item, tile, stats, started))) => {
+                        completed += 1;
+                        on_result(item, tile, stats, started);
+                    }
+                    Ok(Err(message)) => {
+                        outcome = Err(format!("a worker can't render the job: {}", message));
+                        break;
+                    }
+                    Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => {}
+                }
+
+                if connected.load(Ordering::Relaxed) > 0 {
+                    unattended_since = Instant::now();
+                } else if unattended_since.elapsed() >= self.connect_timeout {
+                    outcome = Err(format!("no worker connected for {}s", self.connect_timeout.as_secs()));
+                    break;
+                }
+            }
+
+            let mut queue = lock(queue);
+            queue.finished = true;
+            for stream in queue.busy.values() {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+            queue.busy.clear();
+            drop(queue);
+            ready.notify_all();
+            outcome
+        })
+    }
+}
+
+fn lock(queue: &Mutex<Queue>) -> std::sync::MutexGuard<'_, Queue> {
+    queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Feeds one worker connection until the queue runs dry. Work in progress when the
+/// connection fails goes back to the front of the queue.
+fn serve_worker(
+    mut stream: TcpStream,
+    address: SocketAddr,
+    job: &Job,
+    queue: &Mutex<Queue>,
+    ready: &Condvar,
+    events: Sender<Event>,
+    timeout: Duration,
+) {
+    let mut current = None;
+    let result = (|| -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(timeout))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+
+        let hello = receive(&mut reader)?;
+        if hello.keyword != "hello" || hello.value::<u32>("version")? != PROTOCOL_VERSION {
+            return Err(protocol_error(format!("expected 'hello version={}'", PROTOCOL_VERSION)));
+        }
+        println!("Worker {} connected", address);
+        job.send(&mut stream)?;
+
+        loop {
+            let item = {
+                let mut queue = lock(queue);
+                queue.busy.remove(&address);
+                loop {
+                    if let Some(item) = queue.pending.pop_front() {
+                        queue.busy.insert(address, stream.try_clone()?);
+                        break Some(item);
+                    }
+                    if queue.finished {
+                        break None;
+                    }
+                    queue = ready.wait(queue).unwrap_or_else(|poisoned| poisoned.into_inner());
+                }
+            };
+            let Some(item) = item else {
+                return send(&mut stream, "done", &[]);
+            };
+            current = Some(item);
+            let started = Instant::now();
+
+            let rect = item.rect;
+            let mut header = format!("work x={} y={} width={} height={}", rect.x, rect.y, rect.width, rect.height);
+            if let Some(frame) = item.frame {
+                header += &format!(" frame={}", frame);
+            }
+            send(&mut stream, &header, &[])?;
+
+            let message = loop {
+                let message = receive(&mut reader)?;
+                if message.keyword != "busy" {
+                    break message;
+                }
+            };
+            match message.keyword.as_str() {
+                "result" => {
+                    let tile = TileData::decode(&message.payload, job.pass_count)
+                        .filter(|tile| tile.rect == rect)
+                        .ok_or_else(|| protocol_error("malformed tile".to_string()))?;
+                    let mut stats = RenderStats::default();
+                    for counter in Counter::ALL {
+                        stats.set_count(counter, message.value(counter.name())?);
+                    }
+                    current = None;
+                    // Idle before reporting, so the last result can't get this connection
+                    // shut down before it is told it's done
+                    lock(queue).busy.remove(&address);
+                    // The coordinator only stops listening once all work is done
+                    let _ = events.send(Ok((item, tile, stats, started)));
+                }
+                "error" => {
+                    let _ = events.send(Err(String::from_utf8_lossy(&message.payload).into_owned()));
+                    return Ok(());
+                }
+                other => return Err(protocol_error(format!("unexpected '{}'", other))),
+            }
+        }
+    })();
+
+    let finished = {
+        let mut queue = lock(queue);
+        queue.busy.remove(&address);
+        if let Some(item) = current {
+            queue.pending.push_front(item);
+            ready.notify_all();
+        }
+        queue.finished
+    };
+    match result {
+        Ok(()) => {}
+        // Once the render is over, connections fail because they were shut down
+        Err(_) if finished => {}
+        Err(error) => {
+            let reason = match error.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => format!("silent for {}s", timeout.as_secs()),
+                _ => error.to_string(),
+            };
+            let reassigned = if current.is_some() { "; its work goes to another worker" } else { "" };
+            eprintln!("Worker {} dropped: {}{}", address, reason, reassigned);
+        }
+    }
+}
+
+/// Renders work from the coordinator at `address` over `connections` connections at once,
+/// usually one per core, until the coordinator has nothing left. Until then, connections
+/// that can't be made or fail are retried every second.
+pub fn run_worker(address: &str, connections: usize) -> Result<(), String> {
+    let done = AtomicBool::new(false);
+    let done = &done;
+
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..connections.max(1))
+            .map(|_| scope.spawn(move || work_for(address, done)))
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+            .collect::<Result<Vec<()>, String>>()
+            .map(|_| ())
+    })
+}
+
+/// Why a worker connection ended before the coordinator said it was done
+enum WorkerError {
+    /// Reconnecting may help
+    Connection(io::Error),
+    /// The job can't be rendered here; retrying won't help
+    Job(String),
+}
+
+impl From<io::Error> for WorkerError {
+    fn from(error: io::Error) -> Self {
+        WorkerError::Connection(error)
+    }
+}
+
+fn work_for(address: &str, done: &AtomicBool) -> Result<(), String> {
+    let mut waiting = false;
+
+    while !done.load(Ordering::Relaxed) {
+        match TcpStream::connect(address) {
+            Ok(stream) => {
+                waiting = false;
+                match serve_coordinator(stream) {
+                    Ok(()) => {
+                        done.store(true, Ordering::Relaxed);
+                        return Ok(());
+                    }
+                    Err(WorkerError::Job(message)) => return Err(message),
+                    Err(WorkerError::Connection(error)) => eprintln!("Lost the coordinator at {}: {}", address, error),
+                }
+            }
+            Err(error) if !waiting => {
+                println!("Waiting for the coordinator at {} ({})", address, error);
+                waiting = true;
+            }
+            Err(_) => {}
+        }
+        std::thread::sleep(RECONNECT_DELAY);
+    }
+
+    Ok(())
+}
+
+/// The job as a worker rebuilds it
+struct WorkerJob {
+    raytracer: Raytracer,
+    scene: Scene,
+    camera: Camera,
+    animation: Option<Animation>,
+    /// Holds the received assets; removed when the job is dropped
+    _assets: Option<AssetDir>,
+}
+
+/// A temporary directory for the files sent with a job
+struct AssetDir(PathBuf);
+
+impl Drop for AssetDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Receives the job, then renders work until the coordinator says it's done
+fn serve_coordinator(mut stream: TcpStream) -> Result<(), WorkerError> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    send(&mut stream, &format!("hello version={}", PROTOCOL_VERSION), &[])?;
+
+    let mut job = match receive_job(&mut reader)? {
+        Ok(job) => job,
+        Err(message) => {
+            // Tell the coordinator, which can't finish without this worker's help either
+            let _ = send(&mut stream, "error", message.as_bytes());
+            return Err(WorkerError::Job(message));
+        }
+    };
+
+    loop {
+        let message = receive(&mut reader)?;
+        match message.keyword.as_str() {
+            "work" => {
+                let rect = Rect::new(message.value("x")?, message.value("y")?, message.value("width")?, message.value("height")?);
+                if rect.is_empty() || rect.intersect(&Rect::new(0, 0, job.raytracer.width, job.raytracer.height)) != rect {
+                    return Err(protocol_error(format!("tile {:?} lies outside the image", rect)).into());
+                }
+                if let Some(frame) = message.optional::<usize>("frame")? {
+                    let Some(animation) = &job.animation else {
+                        return Err(WorkerError::Job("frame requested but the scene has no animation".to_string()));
+                    };
+                    animation.apply_over_shutter(animation.frame_time(frame), &mut job.scene, &mut job.camera);
+                }
+
+                let (tile, stats) = render_with_heartbeat(&job, rect, &mut stream)?;
+                let counts: Vec<String> = Counter::ALL
+                    .iter()
+                    .map(|&counter| format!("{}={}", counter.name(), stats.count(counter)))
+                    .collect();
+                send(&mut stream, &format!("result {}", counts.join(" ")), &tile.encode())?;
+            }
+            "done" => return Ok(()),
+            other => return Err(protocol_error(format!("unexpected '{}'", other)).into()),
+        }
+    }
+}
+
+/// Renders a tile on another thread, sending `busy` every second until it's done
+fn render_with_heartbeat(job: &WorkerJob, rect: Rect, stream: &mut TcpStream) -> Result<(TileData, RenderStats), WorkerError> {
+    std::thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+        let render = scope.spawn(move || {
+            let recorder = Recorder::start(false);
+            let tile = job.raytracer.render_tile(&job.scene, &job.camera, rect);
+            let _ = sender.send((tile, recorder.finish()));
+        });
+
+        loop {
+            match receiver.recv_timeout(HEARTBEAT) {
+                Ok(result) => return Ok(result),
+                Err(RecvTimeoutError::Timeout) => send(stream, "busy", &[])?,
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(match render.join() {
+                        Err(panic) => WorkerError::Job(format!("render failed: {}", panic_message(&panic))),
+                        Ok(()) => WorkerError::Job("render failed".to_string()),
+                    });
+                }
+            }
+        }
+    })
+}
+
+fn panic_message(panic: &Box<dyn std::any::Any + Send>) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "panic".to_string())
+}
+
+/// Reads the job's messages. The outer error is the connection's; the inner one means the
+/// job itself is unusable.
+fn receive_job(reader: &mut impl BufRead) -> io::Result<Result<WorkerJob, String>> {
+    let settings = receive(reader)?;
+    let camera = receive(reader)?;
+    if settings.keyword != "settings" || camera.keyword != "camera" {
+        return Err(protocol_error("expected the job's settings and camera".to_string()));
+    }
+
+    let mut assets = Vec::new();
+    let scene = loop {
+        let message = receive(reader)?;
+        match message.keyword.as_str() {
+            "asset" => assets.push(message.payload),
+            "scene" => break message,
+            other => return Err(protocol_error(format!("unexpected '{}' in the job", other))),
+        }
+    };
+
+    Ok(build_job(&settings, &camera.payload, assets, &scene))
+}
+
+fn build_job(settings: &Message, camera: &[u8], assets: Vec<Vec<u8>>, scene: &Message) -> Result<WorkerJob, String> {
+    let raytracer = parse_settings(settings)?;
+    let aspect_ratio: Float = settings.value("aspect").map_err(|e| e.to_string())?;
+    let camera = scene_file::parse_camera_line(&String::from_utf8_lossy(camera), aspect_ratio)?;
+
+    let asset_dir = if assets.is_empty() { None } else { Some(write_assets(assets)?) };
+    let (scene, animation) = match scene.fields.get("builtin").map(String::as_str) {
+        Some("demo") => (demo::create_scene(), None),
+        Some(other) => return Err(format!("unknown built-in scene '{}'", other)),
+        None => {
+            let text = String::from_utf8(scene.payload.clone()).map_err(|_| "scene is not UTF-8".to_string())?;
+            let base_dir = asset_dir.as_ref().map_or(Path::new("."), |dir| dir.0.as_path());
+            let file = scene_file::parse(&text, aspect_ratio, base_dir)?;
+            (file.scene, file.animation)
+        }
+    };
+
+    Ok(WorkerJob { raytracer, scene, camera, animation, _assets: asset_dir })
+}
+
+fn parse_settings(settings: &Message) -> Result<Raytracer, String> {
+    let error = |e: io::Error| e.to_string();
+    let name = |key: &str| settings.fields.get(key).map(String::as_str);
+
+    let filter = name("filter").unwrap_or("box");
+    let filter = Filter::from_name(filter).ok_or_else(|| format!("unknown filter '{}'", filter))?;
+    let debug_mode = match name("debug") {
+        Some(mode) => Some(DebugMode::from_name(mode).ok_or_else(|| format!("unknown debug mode '{}'", mode))?),
+        None => None,
+    };
+    let aovs = match name("aovs") {
+        Some(list) => Aov::parse_list(list)?,
+        None => Vec::new(),
+    };
+    let adaptive = match name("adaptive") {
+        Some(value) => {
+            let values = floats(value, 3)?;
+            Some(AdaptiveSampling::new(values[0] as u32, values[1] as u32, values[2]))
+        }
+        None => None,
+    };
+    let ambient_occlusion = match name("ao") {
+        Some(value) => {
+            let values = floats(value, 2)?;
+            Some(AmbientOcclusion::new(values[0] as u32, values[1]))
+        }
+        None => None,
+    };
+
+    let mut raytracer = Raytracer::new(settings.value("width").map_err(error)?, settings.value("height").map_err(error)?)
+        .with_max_depth(settings.value("max_depth").map_err(error)?)
+        .with_samples(settings.value("samples").map_err(error)?)
+        .with_filter(filter)
+        .with_seed(settings.value("seed").map_err(error)?)
+        .with_adaptive_sampling(adaptive)
+        .with_ambient_occlusion(ambient_occlusion)
+        .with_debug_mode(debug_mode)
+        .with_aovs(aovs)
+        .with_packets(settings.value("packets").map_err(error)?);
+    raytracer.debug_depth_range = settings.value("depth_range").map_err(error)?;
+    raytracer.log_progress = false;
+    Ok(raytracer)
+}
+
+fn floats(text: &str, count: usize) -> Result<Vec<Float>, String> {
+    let values: Vec<Float> = text
+        .split(',')
+        .map(|value| value.parse().map_err(|_| format!("invalid number '{}'", value)))
+        .collect::<Result<_, _>>()?;
+    if values.len() != count {
+        return Err(format!("'{}' should have {} values", text, count));
+    }
+    Ok(values)
+}
+
+fn write_assets(assets: Vec<Vec<u8>>) -> Result<AssetDir, String> {
+    static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+    let name = format!("raytracer-worker-{}-{}", std::process::id(), NEXT_DIR.fetch_add(1, Ordering::Relaxed));
+    let dir = AssetDir(std::env::temp_dir().join(name));
+
+    for asset in assets {
+        let split = asset.iter().position(|&byte| byte == b'\n').ok_or("malformed asset")?;
+        let path = String::from_utf8_lossy(&asset[..split]).into_owned();
+        if !is_relative_inside(&path) {
+            return Err(format!("refusing asset path '{}'", path));
+        }
+
+        let full_path = dir.0.join(&path);
+        let write = || -> io::Result<()> {
+            std::fs::create_dir_all(full_path.parent().unwrap_or(&dir.0))?;
+            std::fs::write(&full_path, &asset[split + 1..])
+        };
+        write().map_err(|e| format!("{}: {}", full_path.display(), e))?;
+    }
+
+    Ok(dir)
+}
+
+/// A relative path that stays inside the directory it's relative to
+fn is_relative_inside(path: &str) -> bool {
+    let path = Path::new(path);
+    path.components().next().is_some() && path.components().all(|component| matches!(component, Component::Normal(_)))
+}
+
+struct Message {
+    keyword: String,
+    fields: HashMap<String, String>,
+    payload: Vec<u8>,
+}
+
+impl Message {
+    fn optional<T: std::str::FromStr>(&self, key: &str) -> io::Result<Option<T>> {
+        match self.fields.get(key) {
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| protocol_error(format!("invalid {}='{}' in '{}'", key, value, self.keyword))),
+            None => Ok(None),
+        }
+    }
+
+    fn value<T: std::str::FromStr>(&self, key: &str) -> io::Result<T> {
+        self.optional(key)?
+            .ok_or_else(|| protocol_error(format!("'{}' is missing {}", self.keyword, key)))
+    }
+}
+
+fn protocol_error(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn send(stream: &mut TcpStream, header: &str, payload: &[u8]) -> io::Result<()> {
+    let mut message = format!("{} bytes={}\n", header, payload.len()).into_bytes();
+    message.extend_from_slice(payload);
+    stream.write_all(&message)
+}
+
+fn receive(reader: &mut impl BufRead) -> io::Result<Message> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+    }
+
+    let mut tokens = line.split_whitespace();
+    let keyword = tokens.next().ok_or_else(|| protocol_error("empty message".to_string()))?.to_string();
+    let fields: HashMap<String, String> = tokens
+        .filter_map(|token| token.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+
+    let mut message = Message { keyword, fields, payload: Vec::new() };
+    let length: usize = message.value("bytes")?;
+    if length > MAX_PAYLOAD {
+        return Err(protocol_error(format!("{} byte message is too large", length)));
+    }
+    // Grows with the data that arrives rather than trusting the header up front
+    reader.take(length as u64).read_to_end(&mut message.payload)?;
+    if message.payload.len() < length {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed mid-message"));
+    }
+    Ok(message)
+}
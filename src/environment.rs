@@ -0,0 +1,483 @@
+// Direction-dependent backgrounds: solid colors, gradients, a procedural sky and
+// equirectangular HDR environment maps. They are seen by camera and reflection rays that
+// escape the scene, and can light diffuse surfaces through their irradiance.
+
+use crate::vec3::consts::PI;
+
+use crate::vec3::{Float, Vec3};
+use crate::checkpoint::{Fingerprint, Fingerprinted};
+
+#[derive(Debug, Clone)]
+pub enum Environment {
+    Solid(Vec3),
+    /// Blends from `horizon` up to `zenith`; everything below the horizon is `ground`
+    Gradient { zenith: Vec3, horizon: Vec3, ground: Vec3 },
+    Sky(Sky),
+    Map(EnvironmentMap),
+}
+
+impl Environment {
+    /// Radiance arriving from `direction` (need not be normalized)
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        let direction = direction.normalize();
+
+        match self {
+            Environment::Solid(color) => *color,
+            Environment::Gradient { zenith, horizon, ground } => {
+                if direction.y < 0.0 {
+                    *ground
+                } else {
+                    *horizon + (*zenith - *horizon) * direction.y
+                }
+            }
+            Environment::Sky(sky) => sky.radiance(direction),
+            Environment::Map(map) => map.radiance(direction),
+        }
+    }
+}
+
+impl Fingerprinted for Environment {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        match self {
+            Environment::Solid(color) => fingerprint.add("solid").add(color),
+            Environment::Gradient { zenith, horizon, ground } => {
+                fingerprint.add("gradient").add(zenith).add(horizon).add(ground)
+            }
+            Environment::Sky(sky) => fingerprint.add("sky").add(sky),
+            Environment::Map(map) => fingerprint.add("map").add(map),
+        };
+    }
+}
+
+/// Preetham et al. analytic daylight model ("A Practical Analytic Model for Daylight", 1999)
+/// with a sun disk. Below the horizon it returns the ground color.
+#[derive(Debug, Clone)]
+pub struct Sky {
+    sun_direction: Vec3,
+    pub intensity: Float,
+    pub ground: Vec3,
+    /// Perez coefficients A-E for luminance Y and chromaticities x and y
+    perez: [[Float; 5]; 3],
+    /// Zenith Y, x, y divided by the Perez function at the zenith
+    zenith: [Float; 3],
+}
+
+const SKY_SCALE: Float = 0.05;          // Maps the model's kcd/m^2 to roughly unit radiance
+const SUN_ANGULAR_RADIUS: Float = 0.5;  // Degrees; larger than the real sun so it shows up
+const SUN_RADIANCE: Float = 40.0;       // Relative to `intensity`
+
+impl Sky {
+    /// `turbidity` ranges from 2 (very clear) to about 10 (hazy)
+    pub fn new(sun_direction: Vec3, turbidity: Float) -> Self {
+        let sun_direction = sun_direction.normalize();
+        let t = turbidity.clamp(1.7, 10.0);
+
+        let perez = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+
+        let theta_sun = sun_direction.y.clamp(0.0, 1.0).acos();
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_y = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+
+        let (s1, s2, s3) = (theta_sun, theta_sun * theta_sun, theta_sun * theta_sun * theta_sun);
+        let zenith_x = t * t * (0.00166 * s3 - 0.00375 * s2 + 0.00209 * s1)
+            + t * (-0.02903 * s3 + 0.06377 * s2 - 0.03202 * s1 + 0.00394)
+            + (0.11693 * s3 - 0.21196 * s2 + 0.06052 * s1 + 0.25886);
+        let zenith_y_chroma = t * t * (0.00275 * s3 - 0.00610 * s2 + 0.00317 * s1)
+            + t * (-0.04214 * s3 + 0.08970 * s2 - 0.04153 * s1 + 0.00516)
+            + (0.15346 * s3 - 0.26756 * s2 + 0.06670 * s1 + 0.26688);
+
+        let zenith = [
+            zenith_y / perez_function(&perez[0], 0.0, theta_sun),
+            zenith_x / perez_function(&perez[1], 0.0, theta_sun),
+            zenith_y_chroma / perez_function(&perez[2], 0.0, theta_sun),
+        ];
+
+        Sky {
+            sun_direction,
+            intensity: 1.0,
+            ground: Vec3::new(0.3, 0.3, 0.3),
+            perez,
+            zenith,
+        }
+    }
+
+    pub fn with_intensity(mut self, intensity: Float) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn with_ground(mut self, ground: Vec3) -> Self {
+        self.ground = ground;
+        self
+    }
+
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        if direction.y < 0.0 {
+            return self.ground * self.intensity;
+        }
+
+        let cos_gamma = direction.dot(&self.sun_direction).clamp(-1.0, 1.0);
+        let sun = if cos_gamma > SUN_ANGULAR_RADIUS.to_radians().cos() && self.sun_direction.y > 0.0 {
+            self.sun_color() * SUN_RADIANCE
+        } else {
+            Vec3::zero()
+        };
+
+        (self.sky(direction) + sun) * self.intensity
+    }
+
+    /// Sky without the sun disk. Used for lighting, where the tiny, very bright disk would
+    /// need far more samples than the sky itself.
+    pub fn sky_radiance(&self, direction: Vec3) -> Vec3 {
+        if direction.y < 0.0 {
+            return self.ground * self.intensity;
+        }
+
+        self.sky(direction) * self.intensity
+    }
+
+    fn sky(&self, direction: Vec3) -> Vec3 {
+        // Keep theta just above the horizon, where 1 / cos(theta) blows up
+        let theta = direction.y.max(0.01).acos();
+        let gamma = direction.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+
+        let luminance = self.zenith[0] * perez_function(&self.perez[0], theta, gamma);
+        let x = self.zenith[1] * perez_function(&self.perez[1], theta, gamma);
+        let y = self.zenith[2] * perez_function(&self.perez[2], theta, gamma);
+
+        xyy_to_rgb(x, y, luminance * SKY_SCALE).clamp(0.0, Float::MAX)
+    }
+
+    /// Warm at low sun elevations, white at noon
+    fn sun_color(&self) -> Vec3 {
+        let elevation = self.sun_direction.y.clamp(0.0, 1.0);
+        let warmth = (1.0 - elevation).powi(3);
+        Vec3::new(1.0, 1.0 - 0.4 * warmth, 1.0 - 0.75 * warmth)
+    }
+}
+
+impl Fingerprinted for Sky {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint
+            .add(&self.sun_direction)
+            .add(&self.intensity)
+            .add(&self.ground)
+            .add(&self.perez)
+            .add(&self.zenith);
+    }
+}
+
+fn perez_function(coefficients: &[Float; 5], theta: Float, gamma: Float) -> Float {
+    let [a, b, c, d, e] = *coefficients;
+    let cos_gamma = gamma.cos();
+    (1.0 + a * (b / theta.cos().max(0.01)).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+/// CIE xyY to linear sRGB (Rec. 709 primaries, D65 white)
+fn xyy_to_rgb(x: Float, y: Float, luminance: Float) -> Vec3 {
+    if y <= 0.0 {
+        return Vec3::zero();
+    }
+
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+
+    Vec3::new(
+        3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z,
+        -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z,
+        0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z,
+    )
+}
+
+/// Latitude-longitude HDR image. The image centre looks down -Z; `rotation` turns the map
+/// around the Y axis in degrees.
+#[derive(Debug, Clone)]
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+    pub rotation: Float,
+    pub intensity: Float,
+}
+
+impl EnvironmentMap {
+    /// Loads a Radiance `.hdr` (RGBE) file
+    pub fn load(path: &str) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        let (width, height, pixels) = read_hdr(&data).map_err(|e| format!("{}: {}", path, e))?;
+
+        Ok(EnvironmentMap {
+            width,
+            height,
+            pixels,
+            rotation: 0.0,
+            intensity: 1.0,
+        })
+    }
+
+    pub fn with_rotation(mut self, degrees: Float) -> Self {
+        self.rotation = degrees;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: Float) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Bilinearly filtered radiance, wrapping around horizontally
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        let longitude = direction.x.atan2(-direction.z) - self.rotation.to_radians();
+        let u = (longitude / (2.0 * PI) + 0.5).rem_euclid(1.0);
+        let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+
+        let fx = u * self.width as Float - 0.5;
+        let fy = (v * self.height as Float - 0.5).clamp(0.0, (self.height - 1) as Float);
+        let (x0, y0) = (fx.floor(), fy.floor());
+        let (tx, ty) = (fx - x0, fy - y0);
+
+        let column = |x: Float| (x as i64).rem_euclid(self.width as i64) as usize;
+        let row = |y: Float| (y as usize).min(self.height - 1);
+        let texel = |x: Float, y: Float| self.pixels[row(y) * self.width + column(x)];
+
+        let top = texel(x0, y0) * (1.0 - tx) + texel(x0 + 1.0, y0) * tx;
+        let bottom = texel(x0, y0 + 1.0) * (1.0 - tx) + texel(x0 + 1.0, y0 + 1.0) * tx;
+        (top * (1.0 - ty) + bottom * ty) * self.intensity
+    }
+}
+
+impl Fingerprinted for EnvironmentMap {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint
+            .add(&self.width)
+            .add(&self.height)
+            .add(&self.pixels[..])
+            .add(&self.rotation)
+            .add(&self.intensity);
+    }
+}
+
+/// Decodes Radiance RGBE data with flat or run-length encoded scanlines. Only the standard
+/// `-Y height +X width` orientation is supported.
+pub fn read_hdr(data: &[u8]) -> Result<(usize, usize, Vec<Vec3>), String> {
+    let mut position = 0;
+    let mut next_line = || -> Result<String, String> {
+        let end = data[position..]
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .ok_or("truncated header")?;
+        let line = String::from_utf8_lossy(&data[position..position + end]).to_string();
+        position += end + 1;
+        Ok(line)
+    };
+
+    if !next_line()?.starts_with("#?") {
+        return Err("not a Radiance .hdr file".to_string());
+    }
+
+    let resolution = loop {
+        let line = next_line()?;
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return Err(format!("unsupported {}", line));
+        }
+        if line.starts_with("-Y") || line.starts_with("+Y") {
+            break line;
+        }
+    };
+
+    let parts: Vec<&str> = resolution.split_whitespace().collect();
+    let (height, width) = match parts.as_slice() {
+        ["-Y", height, "+X", width] => (
+            height.parse::<usize>().map_err(|_| "invalid height")?,
+            width.parse::<usize>().map_err(|_| "invalid width")?,
+        ),
+        _ => return Err(format!("unsupported orientation '{}'", resolution)),
+    };
+    if width == 0 || height == 0 {
+        return Err("empty image".to_string());
+    }
+
+    let data = &data[position..];
+    let mut offset = 0;
+    let mut pixels = Vec::with_capacity(width * height);
+    let mut scanline = vec![[0u8; 4]; width];
+    let truncated = || "truncated pixel data".to_string();
+
+    for _ in 0..height {
+        let header = data.get(offset..offset + 4).ok_or_else(truncated)?;
+        let run_length = (8..0x8000).contains(&width)
+            && header[0] == 2
+            && header[1] == 2
+            && ((header[2] as usize) << 8 | header[3] as usize) == width;
+
+        if run_length {
+            offset += 4;
+            for component in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = *data.get(offset).ok_or_else(truncated)? as usize;
+                    offset += 1;
+                    let length = if count > 128 { count - 128 } else { count };
+                    if length == 0 || x + length > width {
+                        return Err("corrupt run-length scanline".to_string());
+                    }
+
+                    if count > 128 {
+                        let value = *data.get(offset).ok_or_else(truncated)?;
+                        offset += 1;
+                        for pixel in &mut scanline[x..x + length] {
+                            pixel[component] = value;
+                        }
+                    } else {
+                        let values = data.get(offset..offset + length).ok_or_else(truncated)?;
+                        offset += length;
+                        for (pixel, &value) in scanline[x..x + length].iter_mut().zip(values) {
+                            pixel[component] = value;
+                        }
+                    }
+                    x += length;
+                }
+            }
+        } else {
+            for pixel in scanline.iter_mut() {
+                let bytes = data.get(offset..offset + 4).ok_or_else(truncated)?;
+                pixel.copy_from_slice(bytes);
+                offset += 4;
+            }
+        }
+
+        pixels.extend(scanline.iter().map(|&rgbe| from_rgbe(rgbe)));
+    }
+
+    Ok((width, height, pixels))
+}
+
+fn from_rgbe([r, g, b, e]: [u8; 4]) -> Vec3 {
+    if e == 0 {
+        return Vec3::zero();
+    }
+    let scale = Float::powi(2.0, e as i32 - 136);
+    Vec3::new((r as Float + 0.5) * scale, (g as Float + 0.5) * scale, (b as Float + 0.5) * scale)
+}
+
+/// Diffuse lighting from an environment, stored as 9 spherical harmonic coefficients
+/// (Ramamoorthi and Hanrahan, "An Efficient Representation for Irradiance Environment Maps")
+#[derive(Debug, Clone)]
+pub struct Irradiance {
+    coefficients: [Vec3; 9],
+}
+
+const PROJECTION_RESOLUTION: usize = 64; // Longitude steps; latitude uses half as many
+
+impl Irradiance {
+    /// Projects the environment's radiance (without a sky's sun disk) onto the SH basis
+    pub fn from_environment(environment: &Environment) -> Self {
+        let mut coefficients = [Vec3::zero(); 9];
+        let columns = PROJECTION_RESOLUTION;
+        let rows = PROJECTION_RESOLUTION / 2;
+
+        for row in 0..rows {
+            let theta = (row as Float + 0.5) / rows as Float * PI;
+            let solid_angle = (2.0 * PI / columns as Float) * (PI / rows as Float) * theta.sin();
+
+            for column in 0..columns {
+                let phi = (column as Float + 0.5) / columns as Float * 2.0 * PI;
+                let direction = Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+
+                let radiance = match environment {
+                    Environment::Sky(sky) => sky.sky_radiance(direction),
+                    _ => environment.radiance(direction),
+                };
+
+                for (coefficient, basis) in coefficients.iter_mut().zip(sh_basis(direction)) {
+                    *coefficient += radiance * (basis * solid_angle);
+                }
+            }
+        }
+
+        Irradiance { coefficients }
+    }
+
+    /// Irradiance divided by pi: the light a white Lambertian surface facing `normal` reflects
+    pub fn diffuse(&self, normal: Vec3) -> Vec3 {
+        const BAND_SCALE: [Float; 9] = [1.0, 2.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0, 0.25, 0.25, 0.25, 0.25, 0.25];
+
+        let mut result = Vec3::zero();
+        for ((coefficient, basis), scale) in self.coefficients.iter().zip(sh_basis(normal)).zip(BAND_SCALE) {
+            result += *coefficient * (basis * scale);
+        }
+        result.max(&Vec3::zero())
+    }
+}
+
+/// Real spherical harmonics up to band 2 for a unit direction
+fn sh_basis(d: Vec3) -> [Float; 9] {
+    [
+        0.282_095,
+        0.488_603 * d.y,
+        0.488_603 * d.z,
+        0.488_603 * d.x,
+        1.092_548 * d.x * d.y,
+        1.092_548 * d.y * d.z,
+        0.315_392 * (3.0 * d.z * d.z - 1.0),
+        1.092_548 * d.x * d.z,
+        0.546_274 * (d.x * d.x - d.y * d.y),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &[u8] = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n";
+
+    /// Two run-length encoded scanlines of 8 pixels: the first mixes runs and literals,
+    /// the second is a single run per component
+    fn encoded() -> Vec<u8> {
+        let mut data = HEADER.to_vec();
+        data.extend([2, 2, 0, 8]);
+        data.extend([128 + 8, 128]); // r: run of 8
+        data.extend([4, 0, 64, 128, 192, 128 + 4, 255]); // g: 4 literals, then a run of 4
+        data.extend([128 + 8, 0]); // b: run of 8
+        data.extend([128 + 8, 129]); // e: 2^(129 - 136) = 1/128
+        data.extend([2, 2, 0, 8]);
+        for value in [0, 0, 0, 0] {
+            data.extend([128 + 8, value]);
+        }
+        data
+    }
+
+    #[test]
+    fn run_length_scanlines_decode() {
+        let (width, height, pixels) = read_hdr(&encoded()).unwrap();
+        assert_eq!((width, height, pixels.len()), (8, 2, 16));
+
+        let green = [0.5, 64.5, 128.5, 192.5, 255.5, 255.5, 255.5, 255.5];
+        for (pixel, green) in pixels.iter().zip(green) {
+            assert!(pixel.approx_eq(&(Vec3::new(128.5, green, 0.5) / 128.0), 1e-6), "{:?}", pixel);
+        }
+        assert!(pixels[8..].iter().all(|pixel| *pixel == Vec3::zero()));
+    }
+
+    #[test]
+    fn truncated_and_overlong_runs_are_rejected() {
+        let data = encoded();
+        for length in [HEADER.len() + 2, HEADER.len() + 10, data.len() - 1] {
+            assert_eq!(read_hdr(&data[..length]).unwrap_err(), "truncated pixel data", "{} bytes", length);
+        }
+
+        // A run of 9 in an 8 pixel scanline
+        let mut overlong = data.clone();
+        overlong[HEADER.len() + 4] = 128 + 9;
+        assert_eq!(read_hdr(&overlong).unwrap_err(), "corrupt run-length scanline");
+
+        // A run of 5 after the 4 green literals
+        let mut overlong = data;
+        overlong[HEADER.len() + 11] = 128 + 5;
+        assert_eq!(read_hdr(&overlong).unwrap_err(), "corrupt run-length scanline");
+    }
+}
@@ -1,159 +1,397 @@
 // Geometry primitives for raytracing
 
-use crate::vec3::Vec3;
-use crate::ray::Ray;
+use crate::vec3::{Float, Point3, Vec3};
+use crate::ray::{Ray, RayPacket};
+use crate::simd::FloatX4;
 use crate::material::Material;
+use crate::transform::Transform;
+use crate::stats::{self, Counter};
+use crate::checkpoint::{Fingerprint, Fingerprinted};
 
-const EPSILON: f32 = 0.001;
+/// Rays closer to parallel than this cosine miss planes. Being an angle, it doesn't depend
+/// on the scene's scale.
+const MIN_PLANE_COSINE: Float = 0.001;
+
+/// Rounding error of a hit point relative to the magnitudes that went into it. Rotated
+/// instances need about 2 epsilon; the rest is headroom.
+const HIT_ERROR_SCALE: Float = 8.0 * Float::EPSILON;
 
 #[derive(Debug, Clone)]
 pub struct HitRecord {
-    pub point: Vec3,
+    pub point: Point3,
+    /// Hit point in the object's own space, so textures stick to transformed objects
+    pub local_point: Point3,
     pub normal: Vec3,
-    pub t: f32,
+    pub t: Float,
+    pub u: Float,
+    pub v: Float,
+    pub object_id: usize,
     pub material: Material,
 }
 
 impl HitRecord {
-    pub fn new(point: Vec3, normal: Vec3, t: f32, ray: &Ray, material: Material) -> Self {
+    pub fn new(point: Point3, normal: Vec3, t: Float, ray: &Ray, material: Material) -> Self {
         let front_face = ray.direction.dot(&normal) < 0.0;
         let normal = if front_face { normal } else { -normal };
 
         HitRecord {
             point,
+            local_point: point,
             normal,
             t,
+            u: 0.0,
+            v: 0.0,
+            object_id: 0,
             material,
         }
     }
+
+    pub fn with_uv(mut self, u: Float, v: Float) -> Self {
+        self.u = u;
+        self.v = v;
+        self
+    }
+
+    /// How far rays leaving this hit start off the surface. The hit point's rounding error
+    /// grows with its coordinates and with the distance it was found at, so the offset
+    /// scales with both instead of being a fixed epsilon.
+    pub fn offset(&self) -> Float {
+        (self.point.to_vec3().abs().max_component() + self.t) * HIT_ERROR_SCALE
+    }
+
+    /// Ray leaving the surface toward `direction`. Its origin is pushed off the surface
+    /// along the normal, to the side the ray heads for, so it can't hit the surface again.
+    pub fn spawn_ray(&self, direction: Vec3) -> Ray {
+        let offset = self.normal * self.offset();
+        let origin = if direction.dot(&self.normal) < 0.0 { self.point - offset } else { self.point + offset };
+        Ray::new(origin, direction)
+    }
 }
 
-pub trait Hittable {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
+/// Anything rays can hit. Its fingerprint must cover everything that changes what rays see,
+/// so checkpoints of a changed scene aren't resumed.
+pub trait Hittable: Fingerprinted + Send + Sync {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord>;
+
+    /// Natural pivot for rotating and scaling the object
+    fn center(&self) -> Point3 {
+        Point3::origin()
+    }
+
+    fn material_mut(&mut self) -> Option<&mut Material> {
+        None
+    }
+
+    fn transform_mut(&mut self) -> Option<&mut Transform> {
+        None
+    }
+
+    /// Sets where a transformed object is at shutter close; returns false if it has no transform
+    fn set_end_transform(&mut self, _end: Option<Transform>) -> bool {
+        false
+    }
+
+    /// Distance to the closest hit for each ray of a packet, or infinity where a lane misses
+    /// or only hits beyond that lane's `t_max`. The default traces the lanes one at a time.
+    fn hit_packet(&self, packet: &RayPacket, t_min: Float, t_max: FloatX4) -> FloatX4 {
+        let t_max = t_max.to_array();
+        FloatX4::from_array(std::array::from_fn(|lane| {
+            self.hit(&packet.rays[lane], t_min, t_max[lane]).map_or(Float::INFINITY, |hit| hit.t)
+        }))
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Cube {
-    pub min: Vec3,
-    pub max: Vec3,
+    pub min: Point3,
+    pub max: Point3,
     pub material: Material,
+    /// Displacement between shutter open and close, for motion blur
+    pub motion: Vec3,
 }
 
 impl Cube {
-    pub fn new(center: Vec3, size: Vec3, material: Material) -> Self {
+    pub fn new(center: Point3, size: Vec3, material: Material) -> Self {
         let half_size = size * 0.5;
         Cube {
             min: center - half_size,
             max: center + half_size,
             material,
+            motion: Vec3::zero(),
         }
     }
+
+    pub fn with_motion(mut self, motion: Vec3) -> Self {
+        self.motion = motion;
+        self
+    }
+}
+
+impl Fingerprinted for Cube {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add("cube").add(&self.min).add(&self.max).add(&self.material).add(&self.motion);
+    }
 }
 
 impl Hittable for Cube {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let mut t_min = t_min;
-        let mut t_max = t_max;
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        stats::count(Counter::CubeTests);
+
+        // Intersect in the cube's frame at the ray's time, then move the hit back
+        let offset = self.motion * ray.time;
+        // The direction is already unit length, so skip the normalization in Ray::new
+        let moved_ray = Ray { origin: ray.origin - offset, ..ray.clone() };
+        let ray = &moved_ray;
+
+        let mut t_enter = t_min;
+        let mut t_exit = t_max;
         let mut hit_normal = Vec3::new(0.0, 0.0, 0.0);
+        let mut hit_axis = 0;
+
+        // Slab distances for all three axes at once
+        let inv_dir = ray.direction.recip();
+        let slab_min = (self.min - ray.origin) * inv_dir;
+        let slab_max = (self.max - ray.origin) * inv_dir;
 
         // Check each pair of planes (x, y, z)
         for axis in 0..3 {
-            let ray_dir = match axis {
-                0 => ray.direction.x,
-                1 => ray.direction.y,
-                _ => ray.direction.z,
-            };
-
-            let ray_origin = match axis {
-                0 => ray.origin.x,
-                1 => ray.origin.y,
-                _ => ray.origin.z,
-            };
-
-            let min_val = match axis {
-                0 => self.min.x,
-                1 => self.min.y,
-                _ => self.min.z,
-            };
-
-            let max_val = match axis {
-                0 => self.max.x,
-                1 => self.max.y,
-                _ => self.max.z,
-            };
-
-            let inv_dir = 1.0 / ray_dir;
-            let mut t0 = (min_val - ray_origin) * inv_dir;
-            let mut t1 = (max_val - ray_origin) * inv_dir;
-
-            if inv_dir < 0.0 {
+            let (mut t0, mut t1) = (slab_min[axis], slab_max[axis]);
+
+            if inv_dir[axis] < 0.0 {
                 std::mem::swap(&mut t0, &mut t1);
             }
 
-            if t0 > t_min {
-                t_min = t0;
+            if t0 > t_enter {
+                t_enter = t0;
+                hit_axis = axis;
                 hit_normal = Vec3::new(0.0, 0.0, 0.0);
-                match axis {
-                    0 => hit_normal.x = if inv_dir < 0.0 { 1.0 } else { -1.0 },
-                    1 => hit_normal.y = if inv_dir < 0.0 { 1.0 } else { -1.0 },
-                    _ => hit_normal.z = if inv_dir < 0.0 { 1.0 } else { -1.0 },
-                }
+                hit_normal[axis] = if inv_dir[axis] < 0.0 { 1.0 } else { -1.0 };
             }
 
-            if t1 < t_max {
-                t_max = t1;
+            if t1 < t_exit {
+                t_exit = t1;
             }
 
-            if t_max < t_min {
+            if t_exit < t_enter {
                 return None;
             }
         }
 
-        let t = if t_min > EPSILON { t_min } else { t_max };
+        // A ray starting inside the cube hits it on the way out
+        let t = if t_enter > t_min { t_enter } else { t_exit };
 
-        if t < EPSILON {
-            return None;
+        let local_point = ray.at(t);
+        let (u, v) = self.face_uv(local_point, hit_axis);
+
+        let mut hit = HitRecord::new(local_point + offset, hit_normal, t, ray, self.material.clone()).with_uv(u, v);
+        hit.local_point = local_point;
+        Some(hit)
+    }
+
+    /// The slab test of `hit`, one ray per lane
+    fn hit_packet(&self, packet: &RayPacket, t_min: Float, t_max: FloatX4) -> FloatX4 {
+        stats::add(Counter::CubeTests, 4);
+
+        let offset = self.motion * packet.time;
+        let mut enter = FloatX4::splat(t_min);
+        let mut exit = t_max;
+
+        for axis in 0..3 {
+            let origin = packet.origin[axis] - FloatX4::splat(offset[axis]);
+            let inv_dir = packet.inv_direction[axis];
+            let t0 = (FloatX4::splat(self.min[axis]) - origin) * inv_dir;
+            let t1 = (FloatX4::splat(self.max[axis]) - origin) * inv_dir;
+
+            // NaN distances leave enter and exit unchanged, as the comparisons in `hit` do
+            let negative = inv_dir.lt(FloatX4::splat(0.0));
+            enter = FloatX4::select(negative, t1, t0).max(enter);
+            exit = FloatX4::select(negative, t0, t1).min(exit);
         }
 
-        let point = ray.at(t);
+        let t = FloatX4::select(FloatX4::splat(t_min).lt(enter), enter, exit);
+        let misses = exit.lt(enter);
+        FloatX4::select(misses, FloatX4::splat(Float::INFINITY), t)
+    }
 
-        Some(HitRecord::new(point, hit_normal, t, ray, self.material.clone()))
+    fn center(&self) -> Point3 {
+        self.min.lerp(&self.max, 0.5)
+    }
+
+    fn material_mut(&mut self) -> Option<&mut Material> {
+        Some(&mut self.material)
+    }
+}
+
+impl Cube {
+    /// Maps a point on the face perpendicular to `axis` to [0, 1] texture coordinates
+    fn face_uv(&self, point: Point3, axis: usize) -> (Float, Float) {
+        let size = self.max - self.min;
+        let local = point - self.min;
+
+        match axis {
+            0 => (local.z / size.z, local.y / size.y),
+            1 => (local.x / size.x, local.z / size.z),
+            _ => (local.x / size.x, local.y / size.y),
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Plane {
-    pub point: Vec3,
+    pub point: Point3,
     pub normal: Vec3,
     pub material: Material,
+    /// Displacement between shutter open and close, for motion blur
+    pub motion: Vec3,
 }
 
 impl Plane {
-    pub fn new(point: Vec3, normal: Vec3, material: Material) -> Self {
+    pub fn new(point: Point3, normal: Vec3, material: Material) -> Self {
         Plane {
             point,
             normal: normal.normalize(),
             material,
+            motion: Vec3::zero(),
         }
     }
+
+    pub fn with_motion(mut self, motion: Vec3) -> Self {
+        self.motion = motion;
+        self
+    }
+}
+
+impl Fingerprinted for Plane {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add("plane").add(&self.point).add(&self.normal).add(&self.material).add(&self.motion);
+    }
 }
 
 impl Hittable for Plane {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        stats::count(Counter::PlaneTests);
+
         let denom = self.normal.dot(&ray.direction);
         
-        if denom.abs() < EPSILON {
+        if denom.abs() < MIN_PLANE_COSINE {
             return None; // Ray is parallel to plane
         }
         
-        let t = (self.point - ray.origin).dot(&self.normal) / denom;
+        let offset = self.motion * ray.time;
+        let t = (self.point + offset - ray.origin).dot(&self.normal) / denom;
         
         if t < t_min || t > t_max {
             return None;
         }
         
         let point = ray.at(t);
-        Some(HitRecord::new(point, self.normal, t, ray, self.material.clone()))
+        let (u, v) = self.plane_uv(point - offset);
+        let mut hit = HitRecord::new(point, self.normal, t, ray, self.material.clone()).with_uv(u, v);
+        hit.local_point = point - offset;
+        Some(hit)
+    }
+
+    fn hit_packet(&self, packet: &RayPacket, t_min: Float, t_max: FloatX4) -> FloatX4 {
+        stats::add(Counter::PlaneTests, 4);
+
+        let point = self.point + self.motion * packet.time;
+        let (mut denom, mut distance) = (FloatX4::splat(0.0), FloatX4::splat(0.0));
+        for axis in 0..3 {
+            let normal = FloatX4::splat(self.normal[axis]);
+            denom = denom + packet.direction[axis] * normal;
+            distance = distance + (FloatX4::splat(point[axis]) - packet.origin[axis]) * normal;
+        }
+        let t = distance / denom;
+
+        let parallel = denom.abs().lt(FloatX4::splat(MIN_PLANE_COSINE));
+        let outside = t.lt(FloatX4::splat(t_min)) | t_max.lt(t);
+        FloatX4::select(parallel | outside, FloatX4::splat(Float::INFINITY), t)
+    }
+
+    fn center(&self) -> Point3 {
+        self.point
+    }
+
+    fn material_mut(&mut self) -> Option<&mut Material> {
+        Some(&mut self.material)
+    }
+}
+
+impl Plane {
+    /// Tiles the plane with unit-sized [0, 1] texture coordinates around `point`
+    fn plane_uv(&self, point: Point3) -> (Float, Float) {
+        let helper = if self.normal.x.abs() > 0.9 { Vec3::up() } else { Vec3::new(1.0, 0.0, 0.0) };
+        let tangent = helper.cross(&self.normal).normalize();
+        let bitangent = self.normal.cross(&tangent);
+        let local = point - self.point;
+
+        let u = local.dot(&tangent);
+        let v = local.dot(&bitangent);
+        (u - u.floor(), v - v.floor())
+    }
+}
+
+/// Any hittable placed in the world through a `Transform`
+pub struct Instance {
+    pub object: Box<dyn Hittable>,
+    pub transform: Transform,
+    /// Transform at shutter close; rays in between see a blend of the two
+    pub end_transform: Option<Transform>,
+}
+
+impl Instance {
+    pub fn new(object: Box<dyn Hittable>, transform: Transform) -> Self {
+        Instance { object, transform, end_transform: None }
+    }
+
+    fn transform_at(&self, time: Float) -> Transform {
+        match &self.end_transform {
+            Some(end) => self.transform.lerp(end, time),
+            None => self.transform,
+        }
+    }
+}
+
+impl Fingerprinted for Instance {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add("instance").add(&self.object).add(&self.transform).add(&self.end_transform);
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        let transform = self.transform_at(ray.time);
+        let local_direction = transform.vector_to_local(ray.direction);
+        let local_scale = local_direction.length();
+        if local_scale == 0.0 {
+            return None;
+        }
+
+        // The local ray is re-normalized, so distances along it scale by `local_scale`
+        let local_ray = Ray::new(transform.point_to_local(ray.origin), local_direction).with_time(ray.time);
+        let mut hit = self.object.hit(&local_ray, t_min * local_scale, t_max * local_scale)?;
+
+        hit.point = transform.point_to_world(hit.point);
+        hit.normal = transform.normal_to_world(hit.normal);
+        hit.t /= local_scale;
+        Some(hit)
+    }
+
+    fn center(&self) -> Point3 {
+        self.transform.point_to_world(self.object.center())
+    }
+
+    fn material_mut(&mut self) -> Option<&mut Material> {
+        self.object.material_mut()
+    }
+
+    fn transform_mut(&mut self) -> Option<&mut Transform> {
+        Some(&mut self.transform)
+    }
+
+    fn set_end_transform(&mut self, end: Option<Transform>) -> bool {
+        self.end_transform = end;
+        true
     }
 }
 
@@ -179,20 +417,95 @@ impl HittableList {
     pub fn add<T: Hittable + 'static>(&mut self, object: T) {
         self.objects.push(Box::new(object));
     }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Box<dyn Hittable>> {
+        self.objects.get_mut(index)
+    }
+
+    /// Transform of object `index`. Objects without one are first wrapped in an
+    /// identity `Instance` pivoting around their center.
+    pub fn transform_of(&mut self, index: usize) -> Option<&mut Transform> {
+        let object = self.objects.get_mut(index)?;
+
+        if object.transform_mut().is_none() {
+            let pivot = object.center();
+            let inner = std::mem::replace(object, Box::new(HittableList::new()));
+            *object = Box::new(Instance::new(inner, Transform::identity().with_pivot(pivot)));
+        }
+
+        object.transform_mut()
+    }
+
+    /// Closest hit for each ray of a packet. Every object is tested against all four rays at
+    /// once; only the nearest object is intersected again per ray to fill in the `HitRecord`.
+    pub fn hit_packet_records(&self, packet: &RayPacket, t_min: Float, t_max: Float) -> [Option<HitRecord>; 4] {
+        let (_, nearest) = self.closest_in_packet(packet, t_min, FloatX4::splat(t_max));
+
+        std::array::from_fn(|lane| {
+            let object_id = nearest[lane]?;
+            let mut hit_record = self.objects[object_id].hit(&packet.rays[lane], t_min, t_max)?;
+            hit_record.object_id = object_id;
+            Some(hit_record)
+        })
+    }
+
+    /// Closest distance per lane and the index of the object found there. On equal distances
+    /// the later object wins, as in `hit`.
+    fn closest_in_packet(&self, packet: &RayPacket, t_min: Float, t_max: FloatX4) -> (FloatX4, [Option<usize>; 4]) {
+        let mut closest = t_max;
+        let mut nearest = [None; 4];
+
+        for (object_id, object) in self.objects.iter().enumerate() {
+            let t = object.hit_packet(packet, t_min, closest);
+            let hits = t.lt(FloatX4::splat(Float::INFINITY)).and_not(closest.lt(t));
+            let lanes = hits.bitmask();
+            if lanes == 0 {
+                continue;
+            }
+
+            closest = FloatX4::select(hits, t, closest);
+            for (lane, nearest) in nearest.iter_mut().enumerate() {
+                if lanes & (1 << lane) != 0 {
+                    *nearest = Some(object_id);
+                }
+            }
+        }
+
+        (closest, nearest)
+    }
+}
+
+impl Fingerprinted for HittableList {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add(&self.objects[..]);
+    }
+}
+
+impl Default for HittableList {
+    fn default() -> Self {
+        HittableList::new()
+    }
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
         let mut closest_hit: Option<HitRecord> = None;
         let mut closest_so_far = t_max;
 
-        for object in &self.objects {
-            if let Some(hit_record) = object.hit(ray, t_min, closest_so_far) {
+        for (object_id, object) in self.objects.iter().enumerate() {
+            if let Some(mut hit_record) = object.hit(ray, t_min, closest_so_far) {
                 closest_so_far = hit_record.t;
+                hit_record.object_id = object_id;
                 closest_hit = Some(hit_record);
             }
         }
 
         closest_hit
     }
+
+    fn hit_packet(&self, packet: &RayPacket, t_min: Float, t_max: FloatX4) -> FloatX4 {
+        let (closest, nearest) = self.closest_in_packet(packet, t_min, t_max);
+        let closest = closest.to_array();
+        FloatX4::from_array(std::array::from_fn(|lane| nearest[lane].map_or(Float::INFINITY, |_| closest[lane])))
+    }
 }
@@ -0,0 +1,64 @@
+//! Clean Raytracer: a CPU raytracer with cubes, planes, Phong shading, shadows and
+//! reflections, plus headless output to PNG, OpenEXR and Radiance .hdr.
+//!
+//! Build a [`Scene`], point a [`Camera`] at it and render with a [`Raytracer`]:
+//!
+//! ```no_run
+//! use raytracer_clean::{Camera, Color, Cube, Light, Material, Plane, Point3, Raytracer, Scene, Vec3};
+//! use raytracer_clean::output::{self, ExrPrecision};
+//!
+//! let mut scene = Scene::new();
+//! scene.add_plane(Plane::new(Point3::new(0.0, -1.0, 0.0), Vec3::up(), Material::new()));
+//! scene.add_cube(Cube::new(Point3::origin(), Vec3::new(1.0, 1.0, 1.0), Material::new().with_color(Color::new(0.9, 0.2, 0.2))));
+//! scene.add_light(Light::new(Point3::new(-3.0, 5.0, 2.0), Color::white(), 1.0));
+//!
+//! let camera = Camera::new(Point3::new(3.0, 2.0, 4.0), Point3::origin(), Vec3::up(), 45.0, 4.0 / 3.0);
+//! let raytracer = Raytracer::new(400, 300).with_samples(4);
+//! let render = raytracer.render_output(&scene, &camera);
+//! output::save_render(&render.image, "render.png", &raytracer.display_transform(), ExrPrecision::Half, &[]);
+//! ```
+//!
+//! The most used types are re-exported here; the modules hold the rest.
+
+pub mod vec3;
+pub mod simd;
+pub mod ray;
+pub mod material;
+pub mod geometry;
+pub mod light;
+pub mod camera;
+pub mod scene;
+pub mod raytracer;
+pub mod debug;
+pub mod output;
+pub mod sampling;
+pub mod scene_file;
+pub mod transform;
+pub mod animation;
+pub mod tonemap;
+pub mod environment;
+pub mod occlusion;
+pub mod aov;
+pub mod denoise;
+pub mod benchmark;
+pub mod stats;
+pub mod demo;
+pub mod tiles;
+pub mod checkpoint;
+pub mod distributed;
+#[cfg(feature = "viewer")]
+pub mod viewer;
+
+pub use vec3::{Color, Float, Point3, Vec3};
+pub use ray::Ray;
+pub use material::Material;
+pub use geometry::{Cube, HitRecord, Hittable, HittableList, Instance, Plane};
+pub use light::Light;
+pub use camera::{Camera, Projection};
+pub use scene::Scene;
+pub use raytracer::{RenderOutput, Raytracer};
+pub use transform::Transform;
+pub use tonemap::ToneMapping;
+pub use output::Rgba8;
+pub use stats::RenderStats;
+pub use tiles::{Crop, Rect, TileOrder};
@@ -1,16 +1,17 @@
 // Lighting system for raytracing
 
-use crate::vec3::Vec3;
+use crate::vec3::{Color, Float, Point3, Vec3};
+use crate::checkpoint::{Fingerprint, Fingerprinted};
 
 #[derive(Debug, Clone)]
 pub struct Light {
-    pub position: Vec3,
-    pub color: Vec3,
-    pub intensity: f32,
+    pub position: Point3,
+    pub color: Color,
+    pub intensity: Float,
 }
 
 impl Light {
-    pub fn new(position: Vec3, color: Vec3, intensity: f32) -> Self {
+    pub fn new(position: Point3, color: Color, intensity: Float) -> Self {
         Light {
             position,
             color,
@@ -18,13 +19,19 @@ impl Light {
         }
     }
 
-    pub fn get_direction_from(&self, point: Vec3) -> Vec3 {
+    pub fn get_direction_from(&self, point: Point3) -> Vec3 {
         (self.position - point).normalize()
     }
 
-    pub fn get_effective_color(&self, point: Vec3) -> Vec3 {
-        let distance = (self.position - point).length();
+    pub fn get_effective_color(&self, point: Point3) -> Color {
+        let distance = self.position.distance(&point);
         let attenuation = 1.0 / (1.0 + 0.1 * distance + 0.01 * distance * distance);
         self.color * self.intensity * attenuation
     }
 }
+
+impl Fingerprinted for Light {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add(&self.position).add(&self.color).add(&self.intensity);
+    }
+}
@@ -1,100 +1,597 @@
-use raylib::prelude::*;
-
-mod vec3;
-mod ray;
-mod material;
-mod geometry;
-mod light;
-mod camera;
-mod scene;
-mod raytracer;
-
-use vec3::Vec3;
-use material::Material;
-use geometry::{Cube, Plane};
-use light::Light;
-use camera::Camera;
-use scene::Scene;
-use raytracer::Raytracer;
-
-const SCREEN_WIDTH: i32 = 800;
-const SCREEN_HEIGHT: i32 = 600;
+// Command-line front end: renders headlessly to a file, runs benchmarks, or opens the
+// interactive viewer when built with the `viewer` feature
+
+use raytracer_clean::{benchmark, demo, output, scene_file};
+use raytracer_clean::vec3::Float;
+use raytracer_clean::camera::Projection;
+use raytracer_clean::raytracer::Raytracer;
+use raytracer_clean::debug::DebugMode;
+use raytracer_clean::sampling::{AdaptiveSampling, Filter};
+use raytracer_clean::output::{ExrChannel, ExrPrecision};
+use raytracer_clean::tonemap::{ToneMapper, ToneMapping};
+use raytracer_clean::occlusion::AmbientOcclusion;
+use raytracer_clean::aov::Aov;
+use raytracer_clean::denoise::Denoiser;
+use raytracer_clean::stats::StatsFormat;
+use raytracer_clean::tiles::{Crop, TileOrder};
+use raytracer_clean::distributed::{self, Coordinator, Job};
+use std::time::Duration;
+
+const DEFAULT_WIDTH: u32 = 800;
+const DEFAULT_HEIGHT: u32 = 600;
+
+/// The lit render: Phong shading with shadows and mirror reflections. The other integrators
+/// are the debug visualizations.
+const WHITTED: &str = "whitted";
+
+/// Flag, value placeholder (empty for switches) and description
+type OptionHelp = (&'static str, &'static str, &'static str);
+
+/// Options that only mean something together with another one
+const REQUIRES: &[(&str, &str)] = &[
+    ("--focus-distance", "--aperture"),
+    ("--blade-rotation", "--blades"),
+    ("--min-samples", "--adaptive"),
+    ("--max-samples", "--adaptive"),
+    ("--noise-threshold", "--adaptive"),
+    ("--ao-distance", "--ao"),
+    ("--denoise-iterations", "--denoise"),
+    ("--frames", "--sequence"),
+    ("--worker-timeout", "--coordinator"),
+    ("--connect-timeout", "--coordinator"),
+];
+
+/// Options that write a single render's output, which sequences don't have
+const SINGLE_RENDER_ONLY: &[&str] = &["--heatmap", "--stats"];
+
+/// Every option, by section
+const OPTIONS: &[(&str, &[OptionHelp])] = &[
+    ("General", &[
+        ("--help", "", "Print this help and exit"),
+        ("--headless", "", "Render to --output and exit instead of opening the viewer"),
+        ("--threads", "N", "Render threads (default: all cores)"),
+        ("--stats", "FORMAT", "Print render statistics after a headless render"),
+    ]),
+    ("Scene and camera", &[
+        ("--scene", "FILE", "Scene file to render (default: the built-in demo scene)"),
+        ("--camera", "FIELDS", "Camera in scene-file syntax, e.g. \"position=0,2,5 target=0,0,0 fov=40\""),
+        ("--projection", "NAME", "Camera projection"),
+        ("--aperture", "SIZE", "Lens aperture for depth of field"),
+        ("--focus-distance", "D", "Distance in focus (default: the camera's)"),
+        ("--focus-pixel", "X,Y", "Focus on whatever is visible at an image pixel"),
+        ("--blades", "N", "Polygonal aperture with N blades, at least 3"),
+        ("--blade-rotation", "DEG", "Rotation of the aperture polygon"),
+    ]),
+    ("Image and quality", &[
+        ("--width", "PIXELS", "Image width (default: 800)"),
+        ("--height", "PIXELS", "Image height (default: 600)"),
+        ("--samples", "N", "Samples per pixel (default: 1)"),
+        ("--max-depth", "N", "Most rays per camera path, the camera ray included (default: 5)"),
+        ("--integrator", "NAME", "What to render (default: whitted)"),
+        ("--debug", "NAME", "Same as --integrator with a debug visualization"),
+        ("--seed", "N", "Sampling seed (default: 0)"),
+        ("--filter", "NAME", "Pixel reconstruction filter (default: box)"),
+        ("--adaptive", "", "Add samples where the pixel is still noisy"),
+        ("--min-samples", "N", "Adaptive samples per batch and minimum, at least 2 (default: 4)"),
+        ("--max-samples", "N", "Adaptive sample limit (default: 64)"),
+        ("--noise-threshold", "E", "Adaptive noise target (default: 0.01)"),
+        ("--ao", "N", "Ambient occlusion with N rays per shading point"),
+        ("--ao-distance", "D", "Ambient occlusion distance (default: 2)"),
+        ("--denoise", "STRENGTH", "Denoise the beauty image"),
+        ("--denoise-iterations", "N", "Denoiser passes, 1 to 12 (default: 5)"),
+        ("--packets", "", "Trace single-sample primary rays in 4-ray packets"),
+    ]),
+    ("Regions and tiles", &[
+        ("--region", "X,Y,W,H", "Render only this rectangle of pixels; the rest stays black"),
+        ("--crop-window", "X0,Y0,X1,Y1", "Render only this part, as fractions of the image size"),
+        ("--tile-size", "PIXELS", "Size of the square tiles handed to threads (default: 32)"),
+        ("--tile-order", "NAME", "Order tiles are started in (default: scanline)"),
+        ("--checkpoint", "FILE", "Save finished tiles to FILE and resume from the tiles in it"),
+    ]),
+    ("Output", &[
+        ("--output", "FILE", "Output file for --headless (default: render.png)"),
+        ("--format", "EXT", "Output format, replacing the extension of --output"),
+        ("--exr-float", "", "Write 32-bit float EXR channels instead of half floats"),
+        ("--exr-depth", "", "Add a Z channel to EXR output"),
+        ("--aovs", "LIST", "Render passes to write, comma-separated, or 'all'"),
+        ("--heatmap", "FILE", "Write the samples per pixel as a heat map"),
+        ("--tonemap", "NAME", "Tone mapper (default: the scene's, or aces)"),
+        ("--exposure", "STOPS", "Exposure adjustment"),
+        ("--linear", "", "Write linear values instead of sRGB"),
+        ("--no-dither", "", "Quantize without dithering"),
+    ]),
+    ("Animation", &[
+        ("--sequence", "PATTERN", "Render the scene's animation to numbered files, e.g. frames/f_####.png"),
+        ("--frames", "START:END", "Frame range for --sequence (default: all)"),
+    ]),
+    ("Distributed rendering", &[
+        ("--coordinator", "ADDRESS", "Listen on ADDRESS, e.g. 0.0.0.0:7878, and render through the workers that connect"),
+        ("--worker", "ADDRESS", "Render for the coordinator at ADDRESS, on --threads connections"),
+        ("--worker-timeout", "SECONDS", "Reassign the work of workers silent this long (default: 30)"),
+        ("--connect-timeout", "SECONDS", "Give up when no worker is connected this long (default: 300)"),
+    ]),
+    ("Benchmarks", &[
+        ("--benchmark", "", "Time intersection and rendering of the scene"),
+        ("--benchmark-suite", "", "Run the fixed benchmark suite"),
+        ("--benchmark-save", "FILE", "Save suite results"),
+        ("--benchmark-compare", "FILE", "Compare suite results with a saved run"),
+    ]),
+];
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        print_help();
+        return;
+    }
+    if let Err(message) = check_args(&args) {
+        eprintln!("{}. Run with --help to list the options.", message);
+        std::process::exit(1);
+    }
 
-    let (mut rl, thread) = raylib::init()
-        .size(SCREEN_WIDTH, SCREEN_HEIGHT)
-        .title("Clean Raytracer")
-        .build();
-
-    let raytracer = Raytracer::new(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
-    let scene = create_scene();
-    
-    // Camera positioned to view cube from above and at an angle to see 2 sides
-    let camera = Camera::new(
-        Vec3::new(3.0, 4.0, 2.0),      // Position: higher and to the side
-        Vec3::new(0.0, -0.5, -3.0),    // Look at: the cube center
-        Vec3::up(),
-        45.0,
-        SCREEN_WIDTH as f32 / SCREEN_HEIGHT as f32,
+    let coordinator = arg_value(&args, "--coordinator");
+    let output_path = output_path(&args);
+    let output_path = output_path.as_str();
+    let exr_precision = if args.iter().any(|arg| arg == "--exr-float") { ExrPrecision::Float } else { ExrPrecision::Half };
+
+    let debug_mode = match arg_value(&args, "--integrator").or(arg_value(&args, "--debug")) {
+        Some(WHITTED) | None => None,
+        Some(name) => Some(DebugMode::from_name(name).unwrap_or_else(|| {
+            let names: Vec<&str> = choices("--integrator").unwrap_or_default();
+            eprintln!("Unknown integrator '{}'. Available: {}", name, names.join(", "));
+            std::process::exit(1);
+        })),
+    };
+
+    let width = arg_value(&args, "--width").map_or(DEFAULT_WIDTH, |value| parse_at_least(value, "--width", 1));
+    let height = arg_value(&args, "--height").map_or(DEFAULT_HEIGHT, |value| parse_at_least(value, "--height", 1));
+    let max_depth = arg_value(&args, "--max-depth").map_or(5, |value| parse_at_least(value, "--max-depth", 0));
+    let threads = arg_value(&args, "--threads").map_or_else(
+        || std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+        |value| parse_at_least(value, "--threads", 1),
     );
+    if let Some(address) = arg_value(&args, "--worker") {
+        if let Err(message) = distributed::run_worker(address, threads) {
+            eprintln!("Worker failed: {}", message);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let samples = arg_value(&args, "--samples").map_or(1, |value| parse_at_least(value, "--samples", 1));
+    let seed = arg_value(&args, "--seed").map_or(0, |value| parse_or_exit(value, "--seed"));
+
+    let filter = match arg_value(&args, "--filter") {
+        Some(name) => Filter::from_name(name).unwrap_or_else(|| {
+            let names: Vec<&str> = Filter::ALL.iter().map(|filter| filter.name()).collect();
+            eprintln!("Unknown filter '{}'. Available: {}", name, names.join(", "));
+            std::process::exit(1);
+        }),
+        None => Filter::Box,
+    };
+
+    let adaptive = if args.iter().any(|arg| arg == "--adaptive") {
+        // The noise estimate needs at least two samples per batch
+        let min_samples = arg_value(&args, "--min-samples").map_or(4, |value| parse_at_least(value, "--min-samples", 2));
+        let max_samples = arg_value(&args, "--max-samples").map_or(64, |value| parse_at_least(value, "--max-samples", 1));
+        if max_samples < min_samples {
+            eprintln!("--max-samples ({}) must be at least --min-samples ({})", max_samples, min_samples);
+            std::process::exit(1);
+        }
+        let threshold = arg_value(&args, "--noise-threshold").map_or(0.01, |value| parse_float_at_least(value, "--noise-threshold", 0.0));
+        Some(AdaptiveSampling::new(min_samples, max_samples, threshold))
+    } else {
+        None
+    };
+
+    let ambient_occlusion = arg_value(&args, "--ao").map(|value| {
+        let distance = arg_value(&args, "--ao-distance").map_or(2.0, |value| parse_float_at_least(value, "--ao-distance", 0.0));
+        AmbientOcclusion::new(parse_at_least(value, "--ao", 1), distance)
+    });
+
+    let aovs = match arg_value(&args, "--aovs") {
+        Some(list) => Aov::parse_list(list).unwrap_or_else(|message| {
+            let names: Vec<&str> = Aov::ALL.iter().map(|aov| aov.name()).collect();
+            eprintln!("Invalid --aovs: {}. Available: all, {}", message, names.join(", "));
+            std::process::exit(1);
+        }),
+        None => Vec::new(),
+    };
+
+    let denoiser = arg_value(&args, "--denoise").map(|value| {
+        let iterations = arg_value(&args, "--denoise-iterations").map_or(5, |value| parse_at_least(value, "--denoise-iterations", 1));
+        if iterations > Denoiser::MAX_ITERATIONS {
+            eprintln!("--denoise-iterations must be at most {}, got {}", Denoiser::MAX_ITERATIONS, iterations);
+            std::process::exit(1);
+        }
+        Denoiser::new(parse_float_at_least(value, "--denoise", 0.0)).with_iterations(iterations)
+    });
+
+    let stats_format = arg_value(&args, "--stats").map(|name| {
+        StatsFormat::from_name(name).unwrap_or_else(|| {
+            let names: Vec<&str> = StatsFormat::ALL.iter().map(|format| format.name()).collect();
+            eprintln!("Unknown stats format '{}'. Available: {}", name, names.join(", "));
+            std::process::exit(1);
+        })
+    });
+
+    let crop = match (arg_value(&args, "--region"), arg_value(&args, "--crop-window")) {
+        (Some(_), Some(_)) => {
+            eprintln!("Use either --region or --crop-window, not both");
+            std::process::exit(1);
+        }
+        (Some(value), None) => Some(Crop::parse_pixels(value)),
+        (None, Some(value)) => Some(Crop::parse_normalized(value)),
+        (None, None) => None,
+    };
+    let crop = crop.map(|crop| {
+        let crop = crop.unwrap_or_else(|message| {
+            eprintln!("Invalid crop: {}", message);
+            std::process::exit(1);
+        });
+        if crop.rect(width, height).is_empty() {
+            eprintln!("The crop lies outside the {}x{} image", width, height);
+            std::process::exit(1);
+        }
+        crop
+    });
+
+    let tile_size = arg_value(&args, "--tile-size").map_or(32, |value| parse_at_least(value, "--tile-size", 1));
+    let tile_order = match arg_value(&args, "--tile-order") {
+        Some(name) => TileOrder::from_name(name).unwrap_or_else(|| {
+            let names: Vec<&str> = TileOrder::ALL.iter().map(|order| order.name()).collect();
+            eprintln!("Unknown tile order '{}'. Available: {}", name, names.join(", "));
+            std::process::exit(1);
+        }),
+        None => TileOrder::Scanline,
+    };
+
+    let raytracer = Raytracer::new(width, height)
+        .with_samples(samples)
+        .with_max_depth(max_depth)
+        .with_threads(threads)
+        .with_crop(crop)
+        .with_tiles(tile_size, tile_order)
+        .with_checkpoint(arg_value(&args, "--checkpoint").map(String::from))
+        .with_filter(filter)
+        .with_seed(seed)
+        .with_adaptive_sampling(adaptive)
+        .with_ambient_occlusion(ambient_occlusion)
+        .with_aovs(aovs)
+        .with_denoiser(denoiser)
+        .with_packets(args.iter().any(|arg| arg == "--packets"))
+        .with_phase_timing(stats_format.is_some())
+        .with_debug_mode(debug_mode);
+    let aspect_ratio = width as Float / height as Float;
+
+    let default_camera = demo::default_camera(aspect_ratio);
+
+    let (mut scene, mut camera, animation, tone_mapping) = match arg_value(&args, "--scene") {
+        Some(path) => match scene_file::load(path, aspect_ratio) {
+            Ok(file) => (file.scene, file.camera.unwrap_or(default_camera), file.animation, file.tone_mapping),
+            Err(message) => {
+                eprintln!("Failed to load scene: {}", message);
+                std::process::exit(1);
+            }
+        },
+        None => (demo::create_scene(), default_camera, None, None),
+    };
+
+    if let Some(line) = arg_value(&args, "--camera") {
+        camera = scene_file::parse_camera_line(line, aspect_ratio).unwrap_or_else(|message| {
+            eprintln!("Invalid --camera: {}", message);
+            std::process::exit(1);
+        });
+    }
+
+    // Display transform flags override the scene file's 'tonemap' entry
+    let mut tone_mapping = tone_mapping.unwrap_or_else(ToneMapping::new);
+    if let Some(name) = arg_value(&args, "--tonemap") {
+        let tone_mapper = ToneMapper::from_name(name).unwrap_or_else(|| {
+            let names: Vec<&str> = ToneMapper::ALL.iter().map(|mapper| mapper.name()).collect();
+            eprintln!("Unknown tone mapper '{}'. Available: {}", name, names.join(", "));
+            std::process::exit(1);
+        });
+        tone_mapping = tone_mapping.with_tone_mapper(tone_mapper);
+    }
+    if let Some(value) = arg_value(&args, "--exposure") {
+        tone_mapping = tone_mapping.with_exposure(parse_float(value, "--exposure"));
+    }
+    if args.iter().any(|arg| arg == "--linear") {
+        tone_mapping = tone_mapping.with_srgb(false);
+    }
+    if args.iter().any(|arg| arg == "--no-dither") {
+        tone_mapping = tone_mapping.with_dither(false);
+    }
+    let raytracer = raytracer.with_tone_mapping(tone_mapping);
+
+    if let Some(name) = arg_value(&args, "--projection") {
+        let projection = Projection::from_name(name).unwrap_or_else(|| {
+            let names: Vec<&str> = Projection::ALL.iter().map(|projection| projection.name()).collect();
+            eprintln!("Unknown projection '{}'. Available: {}", name, names.join(", "));
+            std::process::exit(1);
+        });
+        camera = camera.with_projection(projection);
+    }
+
+    if let Some(value) = arg_value(&args, "--aperture") {
+        let focus_distance = arg_value(&args, "--focus-distance")
+            .map_or(camera.focus_distance, |value| parse_float(value, "--focus-distance"));
+        camera = camera.with_aperture(parse_float_at_least(value, "--aperture", 0.0), focus_distance);
+    }
+
+    if let Some(value) = arg_value(&args, "--blades") {
+        let rotation = arg_value(&args, "--blade-rotation").map_or(0.0, |value| parse_float(value, "--blade-rotation"));
+        camera = camera.with_aperture_blades(parse_at_least(value, "--blades", 3), rotation);
+    }
+
+    if let Some(value) = arg_value(&args, "--focus-pixel") {
+        let (x, y) = value
+            .split_once(',')
+            .map(|(x, y)| (parse_float(x, "--focus-pixel"), parse_float(y, "--focus-pixel")))
+            .unwrap_or_else(|| {
+                eprintln!("--focus-pixel expects X,Y");
+                std::process::exit(1);
+            });
+        match raytracer.autofocus(&mut camera, &scene, x, y) {
+            Some(distance) => println!("Focus distance: {:.3}", distance),
+            None => println!("Nothing to focus on at pixel ({}, {})", x, y),
+        }
+    }
+
+    let coordinator = coordinator.map(|address| {
+        if args.iter().any(|arg| arg == "--checkpoint") {
+            eprintln!("--checkpoint only works for local renders");
+            std::process::exit(1);
+        }
+        let timeout = arg_value(&args, "--worker-timeout").map_or(30, |value| parse_at_least(value, "--worker-timeout", 1));
+        let connect_timeout =
+            arg_value(&args, "--connect-timeout").map_or(300, |value| parse_at_least(value, "--connect-timeout", 1));
+        let coordinator = Coordinator::bind(address).unwrap_or_else(|message| {
+            eprintln!("Failed to listen for workers: {}", message);
+            std::process::exit(1);
+        });
+        let job = Job::new(&raytracer, &camera, arg_value(&args, "--scene")).unwrap_or_else(|message| {
+            eprintln!("Failed to prepare the scene for workers: {}", message);
+            std::process::exit(1);
+        });
+        let coordinator = coordinator
+            .with_timeout(Duration::from_secs(timeout))
+            .with_connect_timeout(Duration::from_secs(connect_timeout));
+        (coordinator, job)
+    });
 
-    let image_buffer = raytracer.render(&scene, &camera);
-    println!("Rendering complete!");
+    if let Some(pattern) = arg_value(&args, "--sequence") {
+        let Some(animation) = animation else {
+            eprintln!("--sequence needs a scene file with an 'animation' entry");
+            std::process::exit(1);
+        };
 
-    while !rl.window_should_close() {
-        let mut d = rl.begin_drawing(&thread);
-        d.clear_background(Color::BLACK);
+        let frames = match arg_value(&args, "--frames") {
+            Some(value) => {
+                let Some((start, end)) = value.split_once(':') else {
+                    eprintln!("--frames expects START:END");
+                    std::process::exit(1);
+                };
+                let (start, end): (usize, usize) = (parse_or_exit(start, "--frames"), parse_or_exit(end, "--frames"));
+                if start > end {
+                    eprintln!("--frames expects START:END with START <= END, got {}", value);
+                    std::process::exit(1);
+                }
+                let Some(end) = end.checked_add(1) else {
+                    eprintln!("--frames END is too large: {}", end);
+                    std::process::exit(1);
+                };
+                start..end
+            }
+            None => 0..animation.frame_count(),
+        };
+
+        let save_frame = |frame, image: Vec<Vec<_>>| {
+            let path = output::frame_path(pattern, frame);
+            output::save_render(&image, &path, &raytracer.display_transform(), exr_precision, &[]);
+        };
+        match &coordinator {
+            Some((coordinator, job)) => {
+                let result = coordinator.render_sequence(&raytracer, job, frames, |frame, render| save_frame(frame, render.image));
+                exit_on_error(result);
+            }
+            None => raytracer.render_sequence(&mut scene, &mut camera, &animation, frames, save_frame),
+        }
+        return;
+    }
+
+    println!("{}", scene_file::camera_to_line(&camera));
+
+    if args.iter().any(|arg| arg == "--benchmark") {
+        benchmark::run(&raytracer, &scene, &camera);
+        return;
+    }
+
+    if args.iter().any(|arg| arg == "--benchmark-suite") {
+        let baseline = arg_value(&args, "--benchmark-compare").map(|path| {
+            benchmark::load(path).unwrap_or_else(|message| {
+                eprintln!("Failed to load benchmark results: {}", message);
+                std::process::exit(1);
+            })
+        });
+        let results = benchmark::suite(baseline.as_ref());
+        if let Some(path) = arg_value(&args, "--benchmark-save") {
+            match benchmark::save(&results, path) {
+                Ok(()) => println!("Saved {}", path),
+                Err(message) => eprintln!("Failed to save benchmark results: {}", message),
+            }
+        }
+        return;
+    }
 
-        for y in 0..SCREEN_HEIGHT {
-            for x in 0..SCREEN_WIDTH {
-                let pixel = image_buffer[y as usize][x as usize];
-                d.draw_pixel(x, y, pixel);
+    // Without the viewer, or with a coordinator, every render goes to a file
+    #[cfg(feature = "viewer")]
+    if coordinator.is_none() && !args.iter().any(|arg| arg == "--headless") {
+        raytracer_clean::viewer::run(raytracer, &scene, camera);
+        return;
+    }
+
+    let render = match &coordinator {
+        Some((coordinator, job)) => exit_on_error(coordinator.render(&raytracer, job)),
+        None => raytracer.render_output(&scene, &camera),
+    };
+    let display_transform = raytracer.display_transform();
+
+    // EXR output carries the passes as layers; other formats get one file per pass
+    let depth = args.iter().any(|arg| arg == "--exr-depth").then(|| raytracer.render_depth(&scene, &camera));
+    let mut extra_channels: Vec<ExrChannel> = depth.iter().map(|values| ExrChannel { name: "Z", values }).collect();
+    let layers = if output::is_exr(output_path) {
+        render.aovs.exr_planes()
+    } else {
+        output::save_aovs(&render.aovs, output_path, &display_transform, exr_precision, raytracer.debug_depth_range);
+        Vec::new()
+    };
+    extra_channels.extend(layers.iter().map(|(name, values)| ExrChannel { name, values }));
+    output::save_render(&render.image, output_path, &display_transform, exr_precision, &extra_channels);
+
+    if let Some(heat_map_path) = arg_value(&args, "--heatmap") {
+        output::save_image(&raytracer.sample_count_heat_map(&render.sample_counts), heat_map_path);
+    }
+    if let Some(format) = stats_format {
+        println!("{}", render.stats.format(format));
+    }
+}
+
+fn print_help() {
+    println!("Usage: raytracer_clean [OPTIONS]");
+    println!();
+    println!("Renders the demo scene, or a scene file, in an interactive viewer. With --headless the");
+    println!("image is written to a file instead; builds without the viewer feature always do that.");
+
+    for (section, options) in OPTIONS {
+        println!();
+        println!("{}:", section);
+        for &(flag, value, description) in options.iter() {
+            let usage = if value.is_empty() { flag.to_string() } else { format!("{} {}", flag, value) };
+            match choices(flag) {
+                Some(names) => println!("  {:<28} {}\n  {:<28} One of: {}", usage, description, "", names.join(", ")),
+                None => println!("  {:<28} {}", usage, description),
             }
         }
+    }
+}
+
+/// The accepted names for options that pick from a list
+fn choices(flag: &str) -> Option<Vec<&'static str>> {
+    let names = match flag {
+        "--integrator" | "--debug" => {
+            let modes = DebugMode::ALL.iter().map(|mode| mode.name());
+            if flag == "--debug" { modes.collect() } else { std::iter::once(WHITTED).chain(modes).collect() }
+        }
+        "--filter" => Filter::ALL.iter().map(|filter| filter.name()).collect(),
+        "--tonemap" => ToneMapper::ALL.iter().map(|mapper| mapper.name()).collect(),
+        "--projection" => Projection::ALL.iter().map(|projection| projection.name()).collect(),
+        "--tile-order" => TileOrder::ALL.iter().map(|order| order.name()).collect(),
+        "--stats" => StatsFormat::ALL.iter().map(|format| format.name()).collect(),
+        "--format" => output::FORMATS.to_vec(),
+        "--aovs" => Aov::ALL.iter().map(|aov| aov.name()).collect(),
+        _ => return None,
+    };
+    Some(names)
+}
+
+/// Rejects unknown options, stray values, options missing their value and options that
+/// don't go together
+fn check_args(args: &[String]) -> Result<(), String> {
+    let mut index = 0;
+    while index < args.len() {
+        let arg = &args[index];
+        let option = OPTIONS.iter().flat_map(|(_, options)| options.iter()).find(|(flag, _, _)| flag == arg);
+        match option {
+            Some((_, "", _)) => {}
+            Some(_) if index + 1 < args.len() => index += 1,
+            Some((_, value, _)) => return Err(format!("{} expects {}", arg, value)),
+            None if arg.starts_with('-') => return Err(format!("Unknown option '{}'", arg)),
+            None => return Err(format!("Unexpected argument '{}'", arg)),
+        }
+        index += 1;
+    }
+
+    let has = |flag: &str| args.iter().any(|arg| arg == flag);
+    if let Some((option, needed)) = REQUIRES.iter().find(|(option, needed)| has(option) && !has(needed)) {
+        return Err(format!("{} needs {}", option, needed));
+    }
+    if let Some(option) = SINGLE_RENDER_ONLY.iter().find(|option| has(option) && has("--sequence")) {
+        return Err(format!("{} doesn't work with --sequence", option));
+    }
+    Ok(())
+}
+
+/// `--output`, with its extension replaced by `--format` if given. Exits on formats
+/// `output::save_render` can't write.
+fn output_path(args: &[String]) -> String {
+    let path = arg_value(args, "--output").unwrap_or("render.png");
+    let path = match arg_value(args, "--format") {
+        Some(format) => {
+            let name_start = path.rfind(['/', '\\']).map_or(0, |separator| separator + 1);
+            let stem = match path[name_start..].rfind('.') {
+                Some(dot) => &path[..name_start + dot],
+                None => path,
+            };
+            format!("{}.{}", stem, format.to_ascii_lowercase())
+        }
+        None => path.to_string(),
+    };
+
+    if !output::is_supported(&path) {
+        eprintln!("Unsupported output format for '{}'. Available: {}", path, output::FORMATS.join(", "));
+        std::process::exit(1);
+    }
+    path
+}
+
+/// Returns the value following `flag` on the command line, if any
+fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|index| args.get(index + 1))
+        .map(|value| value.as_str())
+}
+
+fn parse_or_exit<T: std::str::FromStr>(value: &str, flag: &str) -> T {
+    value.parse().unwrap_or_else(|_| {
+        eprintln!("Invalid value '{}' for {}", value, flag);
+        std::process::exit(1);
+    })
+}
 
+fn exit_on_error<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|message| {
+        eprintln!("Distributed render failed: {}", message);
+        std::process::exit(1);
+    })
+}
+
+fn parse_at_least<T: std::str::FromStr + PartialOrd + std::fmt::Display>(value: &str, flag: &str, min: T) -> T {
+    let parsed = parse_or_exit(value, flag);
+    if parsed < min {
+        eprintln!("{} must be at least {}, got {}", flag, min, value);
+        std::process::exit(1);
+    }
+    parsed
+}
+
+/// A finite floating-point value
+fn parse_float(value: &str, flag: &str) -> Float {
+    let parsed: Float = parse_or_exit(value, flag);
+    if !parsed.is_finite() {
+        eprintln!("{} must be a finite number, got {}", flag, value);
+        std::process::exit(1);
     }
+    parsed
 }
 
-fn create_scene() -> Scene {
-    let mut scene = Scene::new();
-    
-    // Change background to white
-    scene.set_background_color(Vec3::new(1.0, 1.0, 1.0));
-    
-    // Add light gray floor
-    let floor_material = Material::new()
-        .with_color(Vec3::new(0.7, 0.7, 0.7))  // Light gray
-        .with_roughness(0.9);
-    
-    scene.add_plane(Plane::new(
-        Vec3::new(0.0, -2.0, 0.0),    // Position
-        Vec3::up(),                   // Normal pointing up
-        floor_material,
-    ));
-    
-    let checkerboard_material = Material::new()
-        .with_checkerboard(
-            1.0,
-            Vec3::new(1.0, 0.0, 1.0), // Magenta
-            Vec3::new(0.0, 0.0, 0.0)  // Black
-        )
-        .with_roughness(0.3)
-        .with_specular(0.5)
-        .with_reflectivity(0.2);
-    
-    // Only one cube now
-    scene.add_cube(Cube::new(
-        Vec3::new(0.0, -0.5, -3.0),
-        Vec3::new(1.5, 1.5, 1.5),
-        checkerboard_material,
-    ));
-
-    scene.add_light(Light::new(
-        Vec3::new(-3.0, 5.0, 2.0),
-        Vec3::new(1.0, 1.0, 0.9),
-        1.0,
-    ));
-    
-    scene
+fn parse_float_at_least(value: &str, flag: &str, min: Float) -> Float {
+    let parsed = parse_float(value, flag);
+    if parsed < min {
+        eprintln!("{} must be at least {}, got {}", flag, min, value);
+        std::process::exit(1);
+    }
+    parsed
 }
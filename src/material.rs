@@ -1,71 +1,81 @@
 // Material system for raytracing
 
-use crate::vec3::Vec3;
+use crate::vec3::{Color, Float, Point3};
+use crate::checkpoint::{Fingerprint, Fingerprinted};
 
 #[derive(Debug, Clone)]
 pub enum TextureType {
     SolidColor,
-    Checkerboard { scale: f32, color1: Vec3, color2: Vec3 },
+    Checkerboard { scale: Float, color1: Color, color2: Color },
 }
 
 #[derive(Debug, Clone)]
 pub struct Material {
-    pub color: Vec3,
+    /// Written to the material ID pass; 0 for materials that weren't given one
+    pub id: u32,
+    pub color: Color,
     pub texture: TextureType,
-    pub specular: f32,
-    pub roughness: f32,
-    pub reflectivity: f32,
-    pub emission: Vec3,
+    pub specular: Float,
+    pub roughness: Float,
+    pub reflectivity: Float,
+    pub emission: Color,
 }
 
 impl Material {
     pub fn new() -> Self {
         Material {
-            color: Vec3::new(0.7, 0.7, 0.7),
+            id: 0,
+            color: Color::new(0.7, 0.7, 0.7),
             texture: TextureType::SolidColor,
             specular: 0.1,
             roughness: 0.8,
             reflectivity: 0.0,
-            emission: Vec3::zero(),
+            emission: Color::black(),
         }
     }
 
-    pub fn with_checkerboard(mut self, scale: f32, color1: Vec3, color2: Vec3) -> Self {
+    pub fn with_id(mut self, id: u32) -> Self {
+        self.id = id;
+        self
+    }
+
+    pub fn with_checkerboard(mut self, scale: Float, color1: Color, color2: Color) -> Self {
         self.texture = TextureType::Checkerboard { scale, color1, color2 };
         self
     }
 
-    pub fn with_color(mut self, color: Vec3) -> Self {
+    pub fn with_color(mut self, color: Color) -> Self {
         self.color = color;
         self
     }
 
-    pub fn with_specular(mut self, specular: f32) -> Self {
+    pub fn with_specular(mut self, specular: Float) -> Self {
         self.specular = specular.clamp(0.0, 1.0);
         self
     }
 
-    pub fn with_roughness(mut self, roughness: f32) -> Self {
+    pub fn with_roughness(mut self, roughness: Float) -> Self {
         self.roughness = roughness.clamp(0.0, 1.0);
         self
     }
 
-    pub fn with_reflectivity(mut self, reflectivity: f32) -> Self {
+    pub fn with_reflectivity(mut self, reflectivity: Float) -> Self {
         self.reflectivity = reflectivity.clamp(0.0, 1.0);
         self
     }
 
-    pub fn emitted(&self) -> Vec3 {
+    pub fn emitted(&self) -> Color {
         self.emission
     }
 
-    pub fn get_color_at_point(&self, point: &Vec3) -> Vec3 {
+    /// Texture lookup at a point in the object's own frame
+    pub fn get_color_at_point(&self, point: &Point3) -> Color {
         match &self.texture {
             TextureType::SolidColor => self.color,
             TextureType::Checkerboard { scale, color1, color2 } => {
-                let x_check = (point.x * scale).floor() as i32;
-                let y_check = (point.y * scale).floor() as i32;
-                let z_check = (point.z * scale).floor() as i32;
+                let x_check = (point.x() * scale).floor() as i32;
+                let y_check = (point.y() * scale).floor() as i32;
+                let z_check = (point.z() * scale).floor() as i32;
 
                 if (x_check + y_check + z_check) % 2 == 0 {
                     *color1
@@ -76,3 +86,33 @@ impl Material {
         }
     }
 }
+
+impl Fingerprinted for TextureType {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        match self {
+            TextureType::SolidColor => fingerprint.add("solid"),
+            TextureType::Checkerboard { scale, color1, color2 } => {
+                fingerprint.add("checkerboard").add(scale).add(color1).add(color2)
+            }
+        };
+    }
+}
+
+impl Fingerprinted for Material {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint
+            .add(&self.id)
+            .add(&self.color)
+            .add(&self.texture)
+            .add(&self.specular)
+            .add(&self.roughness)
+            .add(&self.reflectivity)
+            .add(&self.emission);
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material::new()
+    }
+}
@@ -0,0 +1,51 @@
+// Ambient occlusion: how much of the hemisphere above a point is open within a distance
+
+use crate::vec3::Float;
+use crate::checkpoint::{Fingerprint, Fingerprinted};
+use crate::geometry::HitRecord;
+use crate::scene::Scene;
+use crate::sampling::{self, Rng};
+use crate::stats::{self, Counter};
+
+#[derive(Debug, Clone, Copy)]
+pub struct AmbientOcclusion {
+    /// Hemisphere rays per shading point
+    pub samples: u32,
+    /// Occluders further away than this don't darken the point
+    pub distance: Float,
+}
+
+impl AmbientOcclusion {
+    pub fn new(samples: u32, distance: Float) -> Self {
+        AmbientOcclusion {
+            samples: samples.max(1),
+            distance: distance.max(0.001),
+        }
+    }
+
+    /// Fraction of cosine-weighted hemisphere rays around the hit's normal that escape
+    /// within `distance`: 1 in the open, toward 0 in creases and contact areas
+    pub fn visibility(&self, scene: &Scene, hit: &HitRecord, time: Float, rng: &mut Rng) -> Float {
+        let normal = hit.normal;
+        let (tangent, bitangent) = normal.orthonormal_basis();
+
+        let open = sampling::stratified_2d(self.samples, rng)
+            .into_iter()
+            .filter(|&(u, v)| {
+                let (x, y, z) = sampling::cosine_hemisphere(u, v);
+                let direction = tangent * x + bitangent * y + normal * z;
+                let ray = hit.spawn_ray(direction).with_time(time);
+                stats::count(Counter::OcclusionRays);
+                scene.hit(&ray, 0.0, self.distance).is_none()
+            })
+            .count();
+
+        open as Float / self.samples as Float
+    }
+}
+
+impl Fingerprinted for AmbientOcclusion {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add(&self.samples).add(&self.distance);
+    }
+}
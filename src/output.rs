@@ -0,0 +1,984 @@
+// Image output for headless rendering: 8-bit PNG, BMP and QOI, plus OpenEXR and Radiance
+// .hdr for linear HDR radiance
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::vec3::{to_f32, Float, Vec3};
+use crate::tonemap::ToneMapping;
+use crate::aov::AovBuffers;
+
+/// Sample type for OpenEXR channels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExrPrecision {
+    Half,
+    Float,
+}
+
+/// File extensions `save_render` writes: 8-bit formats, then the HDR formats
+pub const FORMATS: [&str; 5] = ["png", "bmp", "qoi", "exr", "hdr"];
+
+/// An 8-bit display pixel, as written to PNG, BMP and QOI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rgba8 {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Rgba8 {
+    pub const BLACK: Rgba8 = Rgba8::new(0, 0, 0, 255);
+
+    pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Rgba8 { r, g, b, a }
+    }
+}
+
+/// An extra single-value-per-pixel channel written into an EXR next to R, G and B
+pub struct ExrChannel<'a> {
+    pub name: &'a str,
+    pub values: &'a [Vec<f32>],
+}
+
+/// Saves a linear HDR render. `.exr` and `.hdr` keep the full range; any other extension
+/// goes through `tone_mapping` to 8 bits.
+pub fn save_render(
+    image: &[Vec<Vec3>],
+    path: &str,
+    tone_mapping: &ToneMapping,
+    precision: ExrPrecision,
+    extra_channels: &[ExrChannel],
+) {
+    let result = match extension(path).as_deref() {
+        Some("exr") => write_exr(image, path, precision, extra_channels),
+        Some("hdr") => File::create(path).and_then(|file| write_hdr(image, &mut BufWriter::new(file))),
+        _ => return save_image(&tone_mapping.apply(image), path),
+    };
+
+    match result {
+        Ok(()) => println!("Saved {}", path),
+        Err(error) => eprintln!("Failed to save {}: {}", path, error),
+    }
+}
+
+/// Whether `path` ends in one of `FORMATS`
+pub fn is_supported(path: &str) -> bool {
+    extension(path).is_some_and(|extension| FORMATS.contains(&extension.as_str()))
+}
+
+/// Whether `path` is an OpenEXR file, which holds render passes as layers of the beauty image
+pub fn is_exr(path: &str) -> bool {
+    extension(path).as_deref() == Some("exr")
+}
+
+/// Saves each render pass as its own file, `<stem>_<pass>.<ext>` next to `path`. HDR formats
+/// keep the raw values. For 8-bit formats light passes go through `tone_mapping` and the rest
+/// are shown like the debug modes.
+pub fn save_aovs(aovs: &AovBuffers, path: &str, tone_mapping: &ToneMapping, precision: ExrPrecision, depth_range: Float) {
+    let raw = matches!(extension(path).as_deref(), Some("exr") | Some("hdr"));
+
+    for (aov, image) in &aovs.passes {
+        let pass_path = match path.rfind('.') {
+            Some(dot) => format!("{}_{}{}", &path[..dot], aov.name(), &path[dot..]),
+            None => format!("{}_{}", path, aov.name()),
+        };
+
+        if raw || aov.is_radiance() {
+            save_render(image, &pass_path, tone_mapping, precision, &[]);
+        } else {
+            let visualized: Vec<Vec<Vec3>> = image
+                .iter()
+                .map(|row| row.iter().map(|&value| aov.visualize(value, depth_range)).collect())
+                .collect();
+            save_render(&visualized, &pass_path, &ToneMapping::none(), precision, &[]);
+        }
+    }
+}
+
+/// Writes the image buffer to disk; the format follows the file extension (png, bmp, qoi)
+pub fn save_image(image_buffer: &[Vec<Rgba8>], path: &str) {
+    let result = match extension(path).as_deref() {
+        Some("png") => std::fs::write(path, encode_png(image_buffer)),
+        Some("bmp") => std::fs::write(path, encode_bmp(image_buffer)),
+        Some("qoi") => std::fs::write(path, encode_qoi(image_buffer)),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "not an 8-bit image format")),
+    };
+
+    match result {
+        Ok(()) => println!("Saved {}", path),
+        Err(error) => eprintln!("Failed to save {}: {}", path, error),
+    }
+}
+
+/// Clamps a display color to [0, 1] and quantizes it, for debug images such as heat maps
+pub fn vec3_to_color(color: Vec3) -> Rgba8 {
+    let color = color.clamp(0.0, 1.0);
+    Rgba8::new((color.x * 255.0) as u8, (color.y * 255.0) as u8, (color.z * 255.0) as u8, 255)
+}
+
+/// Path for frame `frame` of a sequence: a run of '#' in `pattern` becomes the zero-padded
+/// frame number (`frames/cube_####.png` -> `frames/cube_0007.png`). Without '#', the number
+/// is appended before the extension.
+pub fn frame_path(pattern: &str, frame: usize) -> String {
+    if let Some(start) = pattern.find('#') {
+        let width = pattern[start..].chars().take_while(|&c| c == '#').count();
+        return format!("{}{:0width$}{}", &pattern[..start], frame, &pattern[start + width..], width = width);
+    }
+
+    match pattern.rfind('.') {
+        Some(dot) => format!("{}_{:04}{}", &pattern[..dot], frame, &pattern[dot..]),
+        None => format!("{}_{:04}", pattern, frame),
+    }
+}
+
+fn extension(path: &str) -> Option<String> {
+    let name = path.rsplit(['/', '\\']).next()?;
+    name.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase())
+}
+
+fn image_size<T>(image: &[Vec<T>]) -> (usize, usize) {
+    (image.first().map_or(0, |row| row.len()), image.len())
+}
+
+/// Scanline OpenEXR without compression, one scanline per chunk. Channels are stored in
+/// alphabetical order as the format requires.
+fn write_exr(image: &[Vec<Vec3>], path: &str, precision: ExrPrecision, extra_channels: &[ExrChannel]) -> io::Result<()> {
+    let (width, height) = image_size(image);
+
+    let plane = |component: fn(&Vec3) -> Float| -> Vec<Vec<f32>> {
+        image.iter().map(|row| row.iter().map(|pixel| to_f32(component(pixel))).collect()).collect()
+    };
+    let (red, green, blue) = (plane(|c| c.x), plane(|c| c.y), plane(|c| c.z));
+
+    let mut channels = vec![
+        ExrChannel { name: "R", values: &red },
+        ExrChannel { name: "G", values: &green },
+        ExrChannel { name: "B", values: &blue },
+    ];
+    channels.extend(extra_channels.iter().map(|channel| ExrChannel { name: channel.name, values: channel.values }));
+    channels.sort_by(|a, b| a.name.cmp(b.name));
+
+    let (pixel_type, sample_size) = match precision {
+        ExrPrecision::Half => (1i32, 2),
+        ExrPrecision::Float => (2, 4),
+    };
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+
+    let mut channel_list = Vec::new();
+    for channel in &channels {
+        channel_list.extend_from_slice(channel.name.as_bytes());
+        channel_list.push(0);
+        channel_list.extend_from_slice(&pixel_type.to_le_bytes());
+        channel_list.extend_from_slice(&[0, 0, 0, 0]); // pLinear and reserved
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channel_list.push(0);
+
+    let mut window = Vec::new();
+    for value in [0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&value.to_le_bytes());
+    }
+
+    exr_attribute(&mut header, "channels", "chlist", &channel_list);
+    exr_attribute(&mut header, "compression", "compression", &[0]);
+    exr_attribute(&mut header, "dataWindow", "box2i", &window);
+    exr_attribute(&mut header, "displayWindow", "box2i", &window);
+    exr_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    exr_attribute(&mut header, "pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+    exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    exr_attribute(&mut header, "screenWindowWidth", "float", &1.0f32.to_le_bytes());
+    header.push(0);
+
+    let line_size = width * channels.len() * sample_size;
+    let first_chunk = header.len() + height * 8;
+    for y in 0..height {
+        let offset = (first_chunk + y * (8 + line_size)) as u64;
+        header.extend_from_slice(&offset.to_le_bytes());
+    }
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&header)?;
+
+    let mut line = Vec::with_capacity(line_size);
+    for y in 0..height {
+        line.clear();
+        for channel in &channels {
+            for &value in &channel.values[y] {
+                match precision {
+                    ExrPrecision::Half => line.extend_from_slice(&f32_to_half(value).to_le_bytes()),
+                    ExrPrecision::Float => line.extend_from_slice(&value.to_le_bytes()),
+                }
+            }
+        }
+
+        file.write_all(&(y as i32).to_le_bytes())?;
+        file.write_all(&(line_size as i32).to_le_bytes())?;
+        file.write_all(&line)?;
+    }
+
+    file.flush()
+}
+
+fn exr_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+/// IEEE 754 half float, rounding to nearest. Values beyond the half range become infinity.
+fn f32_to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // Infinity stays infinity, NaN stays NaN
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    if half_exponent <= 0 {
+        // Subnormal half, or zero if too small
+        if half_exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - half_exponent) as u32;
+        let rounded = (mantissa + (1 << (shift - 1))) >> shift;
+        return sign | rounded as u16;
+    }
+
+    let rounded = ((half_exponent as u32) << 10 | (mantissa >> 13)) + ((mantissa >> 12) & 1);
+    sign | rounded.min(0x7c00) as u16
+}
+
+/// Radiance RGBE with run-length encoded scanlines
+fn write_hdr(image: &[Vec<Vec3>], file: &mut impl Write) -> io::Result<()> {
+    let (width, height) = image_size(image);
+
+    write!(file, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", height, width)?;
+
+    let run_length = (8..0x8000).contains(&width);
+    let mut components: Vec<Vec<u8>> = (0..4).map(|_| Vec::with_capacity(width)).collect();
+
+    for row in image {
+        let pixels: Vec<[u8; 4]> = row.iter().map(|&color| to_rgbe(color)).collect();
+
+        if !run_length {
+            for pixel in &pixels {
+                file.write_all(pixel)?;
+            }
+            continue;
+        }
+
+        file.write_all(&[2, 2, (width >> 8) as u8, (width & 0xff) as u8])?;
+        for (i, component) in components.iter_mut().enumerate() {
+            component.clear();
+            component.extend(pixels.iter().map(|pixel| pixel[i]));
+            write_rle(file, component)?;
+        }
+    }
+
+    file.flush()
+}
+
+fn to_rgbe(color: Vec3) -> [u8; 4] {
+    let color = color.max(&Vec3::zero());
+    let brightest = color.max_component();
+    if brightest < 1e-32 || !brightest.is_finite() {
+        return [0, 0, 0, 0];
+    }
+
+    // brightest = mantissa * 2^exponent with mantissa in [0.5, 1)
+    let exponent = brightest.log2().floor() as i32 + 1;
+    let scale = 256.0 / Float::powi(2.0, exponent);
+
+    [
+        (color.x * scale).min(255.0) as u8,
+        (color.y * scale).min(255.0) as u8,
+        (color.z * scale).min(255.0) as u8,
+        (exponent + 128).clamp(0, 255) as u8,
+    ]
+}
+
+/// One component of a scanline: runs of 3+ equal bytes as (128 + count, value), everything
+/// else as (count, bytes...)
+fn write_rle(file: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    let mut start = 0;
+
+    while start < data.len() {
+        let run = data[start..].iter().take(127).take_while(|&&byte| byte == data[start]).count();
+        if run >= 3 {
+            file.write_all(&[128 + run as u8, data[start]])?;
+            start += run;
+            continue;
+        }
+
+        // Literal bytes up to the next run of 3 or the 128 byte limit
+        let mut end = start;
+        while end < data.len() && end - start < 128 {
+            if end + 2 < data.len() && data[end] == data[end + 1] && data[end] == data[end + 2] {
+                break;
+            }
+            end += 1;
+        }
+        file.write_all(&[(end - start) as u8])?;
+        file.write_all(&data[start..end])?;
+        start = end;
+    }
+
+    Ok(())
+}
+
+/// 8-bit RGBA PNG. Each row is filtered with whichever PNG filter leaves the smallest
+/// differences, then compressed with fixed-code deflate.
+fn encode_png(image: &[Vec<Rgba8>]) -> Vec<u8> {
+    let (width, height) = image_size(image);
+
+    let mut filtered = Vec::with_capacity(height * (width * 4 + 1));
+    let mut previous = vec![0u8; width * 4];
+    for row in image {
+        let current: Vec<u8> = row.iter().flat_map(|pixel| [pixel.r, pixel.g, pixel.b, pixel.a]).collect();
+        let mut best: Option<(u8, Vec<u8>, u32)> = None;
+        for filter in 0..5 {
+            let line = png_filter(filter, &current, &previous);
+            let cost = line.iter().map(|&byte| (byte as i8).unsigned_abs() as u32).sum();
+            if best.as_ref().is_none_or(|&(_, _, best_cost)| cost < best_cost) {
+                best = Some((filter, line, cost));
+            }
+        }
+        if let Some((filter, line, _)) = best {
+            filtered.push(filter);
+            filtered.extend_from_slice(&line);
+        }
+        previous = current;
+    }
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 6, 0, 0, 0]); // 8 bits, RGBA, deflate, adaptive filters, no interlace
+
+    let mut data = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+    png_chunk(&mut data, b"IHDR", &header);
+    png_chunk(&mut data, b"IDAT", &zlib_compress(&filtered));
+    png_chunk(&mut data, b"IEND", &[]);
+    data
+}
+
+/// Row `current` as differences from the prediction of PNG filter `filter` (0 none, 1 sub,
+/// 2 up, 3 average, 4 Paeth)
+fn png_filter(filter: u8, current: &[u8], previous: &[u8]) -> Vec<u8> {
+    (0..current.len())
+        .map(|i| {
+            let left = if i >= 4 { current[i - 4] } else { 0 };
+            let up = previous[i];
+            let up_left = if i >= 4 { previous[i - 4] } else { 0 };
+            let prediction = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                _ => paeth(left, up, up_left),
+            };
+            current[i].wrapping_sub(prediction)
+        })
+        .collect()
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let (to_left, to_up, to_up_left) =
+        ((estimate - left as i16).abs(), (estimate - up as i16).abs(), (estimate - up_left as i16).abs());
+    if to_left <= to_up && to_left <= to_up_left {
+        left
+    } else if to_up <= to_up_left {
+        up
+    } else {
+        up_left
+    }
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    let start = out.len() + 4;
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// A zlib stream of one fixed-code deflate block
+fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    stream.extend_from_slice(&deflate(data));
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+const DEFLATE_WINDOW: usize = 1 << 15;
+const DEFLATE_HASH_SIZE: usize = 1 << 15;
+/// Earlier matches tried per position; more compresses a little better, slower
+const DEFLATE_MAX_CHAIN: usize = 64;
+const DEFLATE_MAX_MATCH: usize = 258;
+
+const LENGTH_BASES: [u16; 29] =
+    [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA_BITS: [u32; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u32; 30] =
+    [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// Deflate with the fixed Huffman codes: repeats found through hash chains become
+/// length/distance pairs, everything else literals
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut out = BitWriter::default();
+    out.bits(1, 1); // Last block
+    out.bits(1, 2); // Fixed codes
+
+    let hash = |position: usize| {
+        ((data[position] as usize) << 10 ^ (data[position + 1] as usize) << 5 ^ data[position + 2] as usize)
+            & (DEFLATE_HASH_SIZE - 1)
+    };
+    // The last position with each hash, and for each position the one before it
+    let mut head = vec![usize::MAX; DEFLATE_HASH_SIZE];
+    let mut previous = vec![usize::MAX; DEFLATE_WINDOW];
+    let insert = |head: &mut [usize], previous: &mut [usize], position: usize| {
+        if position + 3 <= data.len() {
+            previous[position % DEFLATE_WINDOW] = head[hash(position)];
+            head[hash(position)] = position;
+        }
+    };
+
+    let mut position = 0;
+    while position < data.len() {
+        let (mut length, mut distance) = (0, 0);
+        if position + 3 <= data.len() {
+            let longest = (data.len() - position).min(DEFLATE_MAX_MATCH);
+            let mut candidate = head[hash(position)];
+            for _ in 0..DEFLATE_MAX_CHAIN {
+                if candidate == usize::MAX || position - candidate > DEFLATE_WINDOW {
+                    break;
+                }
+                let matched =
+                    data[candidate..].iter().zip(&data[position..position + longest]).take_while(|(a, b)| a == b).count();
+                if matched > length {
+                    (length, distance) = (matched, position - candidate);
+                    if matched == longest {
+                        break;
+                    }
+                }
+                candidate = previous[candidate % DEFLATE_WINDOW];
+            }
+        }
+
+        if length >= 3 {
+            out.length_distance(length, distance);
+            for skipped in position..position + length {
+                insert(&mut head, &mut previous, skipped);
+            }
+            position += length;
+        } else {
+            out.symbol(data[position] as u16);
+            insert(&mut head, &mut previous, position);
+            position += 1;
+        }
+    }
+
+    out.symbol(256); // End of block
+    out.finish()
+}
+
+/// Writes deflate bit fields, least significant bit first
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, count: u32) {
+        self.buffer |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes go most significant bit first
+    fn code(&mut self, code: u32, length: u32) {
+        self.bits(code.reverse_bits() >> (32 - length), length);
+    }
+
+    /// A literal/length symbol in the fixed code
+    fn symbol(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn length_distance(&mut self, length: usize, distance: usize) {
+        let index = LENGTH_BASES.iter().rposition(|&base| base as usize <= length).unwrap_or(0);
+        self.symbol(257 + index as u16);
+        self.bits((length - LENGTH_BASES[index] as usize) as u32, LENGTH_EXTRA_BITS[index]);
+
+        let index = DISTANCE_BASES.iter().rposition(|&base| base as usize <= distance).unwrap_or(0);
+        self.code(index as u32, 5);
+        self.bits((distance - DISTANCE_BASES[index] as usize) as u32, DISTANCE_EXTRA_BITS[index]);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/// 24-bit uncompressed BMP, rows bottom to top
+fn encode_bmp(image: &[Vec<Rgba8>]) -> Vec<u8> {
+    let (width, height) = image_size(image);
+    let row_size = (width * 3).div_ceil(4) * 4;
+    let pixel_bytes = row_size * height;
+
+    let mut data = Vec::with_capacity(54 + pixel_bytes);
+    data.extend_from_slice(b"BM");
+    data.extend_from_slice(&((54 + pixel_bytes) as u32).to_le_bytes());
+    data.extend_from_slice(&[0; 4]);
+    data.extend_from_slice(&54u32.to_le_bytes());
+    data.extend_from_slice(&40u32.to_le_bytes());
+    data.extend_from_slice(&(width as i32).to_le_bytes());
+    data.extend_from_slice(&(height as i32).to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes()); // Planes
+    data.extend_from_slice(&24u16.to_le_bytes()); // Bits per pixel
+    data.extend_from_slice(&0u32.to_le_bytes()); // No compression
+    data.extend_from_slice(&(pixel_bytes as u32).to_le_bytes());
+    data.extend_from_slice(&[0; 16]); // Resolution and palette
+
+    for row in image.iter().rev() {
+        let end = data.len() + row_size;
+        data.extend(row.iter().flat_map(|pixel| [pixel.b, pixel.g, pixel.r]));
+        data.resize(end, 0);
+    }
+    data
+}
+
+/// QOI, the "Quite OK Image" format: runs, an index of recently seen colors, and small
+/// differences from the previous pixel
+fn encode_qoi(image: &[Vec<Rgba8>]) -> Vec<u8> {
+    let (width, height) = image_size(image);
+
+    let mut data = b"qoif".to_vec();
+    data.extend_from_slice(&(width as u32).to_be_bytes());
+    data.extend_from_slice(&(height as u32).to_be_bytes());
+    data.extend_from_slice(&[4, 0]); // RGBA, sRGB
+
+    let mut index = [Rgba8::default(); 64];
+    let mut previous = Rgba8::BLACK;
+    let mut run = 0u8;
+    let pixels: Vec<Rgba8> = image.iter().flatten().copied().collect();
+
+    for (i, &pixel) in pixels.iter().enumerate() {
+        if pixel == previous {
+            run += 1;
+            if run == 62 || i + 1 == pixels.len() {
+                data.push(0xc0 | (run - 1));
+                run = 0;
+            }
+            continue;
+        }
+        if run > 0 {
+            data.push(0xc0 | (run - 1));
+            run = 0;
+        }
+
+        let slot = (pixel.r as usize * 3 + pixel.g as usize * 5 + pixel.b as usize * 7 + pixel.a as usize * 11) % 64;
+        if index[slot] == pixel {
+            data.push(slot as u8);
+        } else {
+            index[slot] = pixel;
+            if pixel.a == previous.a {
+                let dr = pixel.r.wrapping_sub(previous.r) as i8;
+                let dg = pixel.g.wrapping_sub(previous.g) as i8;
+                let db = pixel.b.wrapping_sub(previous.b) as i8;
+                let (dr_dg, db_dg) = (dr.wrapping_sub(dg), db.wrapping_sub(dg));
+
+                if [dr, dg, db].iter().all(|d| (-2..=1).contains(d)) {
+                    data.push(0x40 | ((dr + 2) as u8) << 4 | ((dg + 2) as u8) << 2 | (db + 2) as u8);
+                } else if (-32..=31).contains(&dg) && (-8..=7).contains(&dr_dg) && (-8..=7).contains(&db_dg) {
+                    data.push(0x80 | (dg + 32) as u8);
+                    data.push(((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8);
+                } else {
+                    data.extend_from_slice(&[0xfe, pixel.r, pixel.g, pixel.b]);
+                }
+            } else {
+                data.extend_from_slice(&[0xff, pixel.r, pixel.g, pixel.b, pixel.a]);
+            }
+        }
+        previous = pixel;
+    }
+
+    data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads deflate bit fields, least significant bit first
+    struct BitReader<'a> {
+        data: &'a [u8],
+        position: usize,
+    }
+
+    impl BitReader<'_> {
+        fn bits(&mut self, count: u32) -> u32 {
+            let mut value = 0;
+            for i in 0..count {
+                let byte = self.data[self.position / 8];
+                value |= ((byte >> (self.position % 8)) as u32 & 1) << i;
+                self.position += 1;
+            }
+            value
+        }
+
+        /// A Huffman code of `length` bits, most significant bit first
+        fn code(&mut self, length: u32) -> u32 {
+            (0..length).fold(0, |code, _| code << 1 | self.bits(1))
+        }
+
+        /// A literal/length symbol in the fixed code
+        fn symbol(&mut self) -> u32 {
+            let code = self.code(7);
+            if code <= 0x17 {
+                return code + 256;
+            }
+            let code = code << 1 | self.bits(1);
+            match code {
+                0x30..=0xbf => code - 0x30,
+                0xc0..=0xc7 => code - 0xc0 + 280,
+                _ => (code << 1 | self.bits(1)) - 0x190 + 144,
+            }
+        }
+    }
+
+    /// Inflates stored and fixed-code blocks, the ones a reference decoder needs here
+    fn inflate(data: &[u8]) -> Vec<u8> {
+        let mut input = BitReader { data, position: 0 };
+        let mut out = Vec::new();
+
+        loop {
+            let last = input.bits(1) == 1;
+            match input.bits(2) {
+                0 => {
+                    input.position = input.position.div_ceil(8) * 8;
+                    let length = input.bits(16) as usize;
+                    assert_eq!(input.bits(16) as usize, !length & 0xffff);
+                    let start = input.position / 8;
+                    out.extend_from_slice(&data[start..start + length]);
+                    input.position += length * 8;
+                }
+                1 => loop {
+                    let symbol = input.symbol() as usize;
+                    if symbol < 256 {
+                        out.push(symbol as u8);
+                        continue;
+                    }
+                    if symbol == 256 {
+                        break;
+                    }
+                    let index = symbol - 257;
+                    let length = LENGTH_BASES[index] as usize + input.bits(LENGTH_EXTRA_BITS[index]) as usize;
+                    let index = input.code(5) as usize;
+                    let distance = DISTANCE_BASES[index] as usize + input.bits(DISTANCE_EXTRA_BITS[index]) as usize;
+                    for _ in 0..length {
+                        out.push(out[out.len() - distance]);
+                    }
+                },
+                kind => panic!("unexpected block type {}", kind),
+            }
+            if last {
+                return out;
+            }
+        }
+    }
+
+    /// Bytes with some structure to match, and some noise
+    fn test_data(length: usize) -> Vec<u8> {
+        let mut state = 0x1234_5678u32;
+        let noise: Vec<u8> = (0..4096)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 24) as u8
+            })
+            .collect();
+        (0..length)
+            .map(|i| match (i / 1000) % 3 {
+                0 => noise[i % noise.len()],
+                1 => (i % 7) as u8,
+                _ => b"abcabcabd"[i % 9],
+            })
+            .collect()
+    }
+
+    fn image(width: u8, height: u8) -> Vec<Vec<Rgba8>> {
+        (0..height)
+            .map(|y| (0..width).map(|x| Rgba8::new(x * 40, y * 60, x ^ y, 255 - x)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414f_a339);
+
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        // Long enough for both sums to wrap around the modulus
+        assert_eq!(adler32(&[0xff; 6000]), 0xa497_59ea);
+    }
+
+    #[test]
+    fn reference_inflate_reads_stored_blocks() {
+        // A stored block followed by a fixed-code block holding just the end-of-block symbol
+        let stored = [0x00, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c', 0x03, 0x00];
+        assert_eq!(inflate(&stored), b"abc");
+    }
+
+    #[test]
+    fn deflate_round_trips() {
+        for length in [0, 1, 2, 3, 258, 259, 5000, 40_000] {
+            let data = test_data(length);
+            assert_eq!(inflate(&deflate(&data)), data, "{} bytes", length);
+        }
+
+        // Runs longer than the longest match, and repeats at the far end of the window
+        let mut data = vec![7u8; 1000];
+        data.extend(test_data(DEFLATE_WINDOW - 10));
+        data.extend_from_within(1000..2000);
+        let compressed = deflate(&data);
+        assert!(compressed.len() < data.len());
+        assert_eq!(inflate(&compressed), data);
+
+        let stream = zlib_compress(&data);
+        assert_eq!(u16::from_be_bytes([stream[0], stream[1]]) % 31, 0, "zlib header check bits");
+        let (body, checksum) = stream[2..].split_at(stream.len() - 6);
+        assert_eq!(inflate(body), data);
+        assert_eq!(checksum, adler32(&data).to_be_bytes());
+    }
+
+    #[test]
+    fn png_chunks_and_rows_decode() {
+        let image = image(5, 4);
+        let png = encode_png(&image);
+        assert_eq!(&png[..8], &[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n']);
+
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (checked, crc) = rest[4..].split_at(4 + length);
+            assert_eq!(crc32(checked).to_be_bytes(), crc[..4]);
+            chunks.push((&checked[..4], &checked[4..]));
+            rest = &crc[4..];
+        }
+        let kinds: Vec<&[u8]> = chunks.iter().map(|(kind, _)| *kind).collect();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 5, 0, 0, 0, 4, 8, 6, 0, 0, 0]);
+
+        let filtered = inflate(&chunks[1].1[2..chunks[1].1.len() - 4]);
+        let mut previous = vec![0u8; 20];
+        for (row, line) in image.iter().zip(filtered.chunks(21)) {
+            let mut current = vec![0u8; 20];
+            for i in 0..20 {
+                let left = if i >= 4 { current[i - 4] } else { 0 };
+                let up_left = if i >= 4 { previous[i - 4] } else { 0 };
+                let prediction = match line[0] {
+                    0 => 0,
+                    1 => left,
+                    2 => previous[i],
+                    3 => ((left as u16 + previous[i] as u16) / 2) as u8,
+                    _ => paeth(left, previous[i], up_left),
+                };
+                current[i] = line[1 + i].wrapping_add(prediction);
+            }
+            let expected: Vec<u8> = row.iter().flat_map(|pixel| [pixel.r, pixel.g, pixel.b, pixel.a]).collect();
+            assert_eq!(current, expected);
+            previous = current;
+        }
+    }
+
+    #[test]
+    fn bmp_rows_are_padded_and_bottom_up() {
+        let image = image(3, 2);
+        let bmp = encode_bmp(&image);
+
+        // 3 pixels take 9 bytes, padded to 12
+        assert_eq!(bmp.len(), 54 + 2 * 12);
+        assert_eq!(u32::from_le_bytes(bmp[2..6].try_into().unwrap()), bmp.len() as u32);
+        assert_eq!(i32::from_le_bytes(bmp[22..26].try_into().unwrap()), 2);
+
+        for (stored, row) in bmp[54..].chunks(12).zip(image.iter().rev()) {
+            let expected: Vec<u8> = row.iter().flat_map(|pixel| [pixel.b, pixel.g, pixel.r]).collect();
+            assert_eq!(&stored[..9], expected.as_slice());
+            assert_eq!(&stored[9..], &[0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn qoi_uses_runs_and_the_color_index() {
+        let (red, blue) = (Rgba8::new(200, 10, 10, 255), Rgba8::new(10, 10, 200, 255));
+        let mut row = vec![Rgba8::BLACK; 70];
+        row.extend([red, blue, red]);
+        let qoi = encode_qoi(&[row]);
+
+        assert_eq!(&qoi[..14], &[b'q', b'o', b'i', b'f', 0, 0, 0, 73, 0, 0, 0, 1, 4, 0]);
+        assert_eq!(&qoi[qoi.len() - 8..], &[0, 0, 0, 0, 0, 0, 0, 1]);
+
+        let red_slot = (200 * 3 + 10 * 5 + 10 * 7 + 255 * 11) % 64;
+        let expected = [
+            0xc0 | 61, // 62 black pixels: the longest run
+            0xc0 | 7,  // The other 8
+            0xfe, 200, 10, 10,
+            0xfe, 10, 10, 200,
+            red_slot as u8, // Red again, from the index
+        ];
+        assert_eq!(&qoi[14..qoi.len() - 8], &expected);
+    }
+
+    #[test]
+    fn half_floats_round_and_saturate() {
+        assert_eq!(f32_to_half(0.0), 0x0000);
+        assert_eq!(f32_to_half(-0.0), 0x8000);
+        assert_eq!(f32_to_half(1.0), 0x3c00);
+        assert_eq!(f32_to_half(-2.0), 0xc000);
+        assert_eq!(f32_to_half(1.0 + 1.0 / 1024.0), 0x3c01);
+        assert_eq!(f32_to_half(65504.0), 0x7bff);
+
+        // Denormals: the smallest, the largest, and values that flush to zero
+        assert_eq!(f32_to_half(2.0f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_half(-(2.0f32.powi(-24))), 0x8001);
+        assert_eq!(f32_to_half(2.0f32.powi(-14) - 2.0f32.powi(-24)), 0x03ff);
+        assert_eq!(f32_to_half(2.0f32.powi(-14)), 0x0400);
+        assert_eq!(f32_to_half(2.0f32.powi(-26)), 0x0000);
+        assert_eq!(f32_to_half(1e-30), 0x0000);
+
+        // Overflow, also through rounding up past the largest half
+        assert_eq!(f32_to_half(65520.0), 0x7c00);
+        assert_eq!(f32_to_half(1e10), 0x7c00);
+        assert_eq!(f32_to_half(-1e10), 0xfc00);
+        assert_eq!(f32_to_half(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_half(f32::NEG_INFINITY), 0xfc00);
+
+        let nan = f32_to_half(f32::NAN);
+        assert_eq!(nan & 0x7c00, 0x7c00);
+        assert_ne!(nan & 0x03ff, 0);
+    }
+
+    fn from_rgbe([r, g, b, e]: [u8; 4]) -> Vec3 {
+        let scale = Float::powi(2.0, e as i32 - 136);
+        Vec3::new(r as Float + 0.5, g as Float + 0.5, b as Float + 0.5) * scale
+    }
+
+    #[test]
+    fn rgbe_keeps_zero_and_small_values() {
+        assert_eq!(to_rgbe(Vec3::zero()), [0, 0, 0, 0]);
+        assert_eq!(to_rgbe(Vec3::new(-1.0, -2.0, 0.0)), [0, 0, 0, 0]);
+        assert_eq!(to_rgbe(Vec3::new(1e-35, 0.0, 0.0)), [0, 0, 0, 0]);
+        assert_eq!(to_rgbe(Vec3::new(1.0, 0.5, 0.0)), [128, 64, 0, 129]);
+        assert_eq!(to_rgbe(Vec3::new(0.5, 0.5, 0.5)), [128, 128, 128, 128]);
+
+        for value in [1e-30, 3e-9, 0.01, 0.75, 1.0, 1000.0, 1e30] {
+            let color = Vec3::new(value, value * 0.5, value * 0.25);
+            let rgbe = to_rgbe(color);
+            assert!(rgbe[0] >= 128, "{} isn't normalized: {:?}", value, rgbe);
+            let decoded = from_rgbe(rgbe);
+            assert!((decoded.x - color.x).abs() <= color.x / 128.0, "{} decodes to {:?}", value, decoded);
+        }
+    }
+
+    fn rle(data: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        write_rle(&mut encoded, data).unwrap();
+        encoded
+    }
+
+    #[test]
+    fn rle_scanlines_split_runs_and_literals() {
+        assert_eq!(rle(&[1, 1, 1, 1, 2, 3, 3, 4, 4, 4]), [128 + 4, 1, 3, 2, 3, 3, 128 + 3, 4]);
+        assert_eq!(rle(&[5, 5]), [2, 5, 5]);
+
+        // Runs stop at 127 and literals at 128
+        assert_eq!(rle(&[9; 200]), [128 + 127, 9, 128 + 73, 9]);
+        let literals: Vec<u8> = (0..=255).chain(0..44).collect();
+        let encoded = rle(&literals);
+        assert_eq!((encoded[0], encoded[129], encoded[258]), (128, 128, 44));
+        assert_eq!(encoded.len(), literals.len() + 3);
+    }
+
+    #[test]
+    fn hdr_files_read_back() {
+        // 8 pixels and up are run-length encoded, narrower images are stored flat
+        for width in [5, 8, 40] {
+            let image: Vec<Vec<Vec3>> = (0..3)
+                .map(|y| {
+                    (0..width)
+                        .map(|x| if x < width / 2 { Vec3::new(1.0, 0.5, 0.25) } else { Vec3::new(x as Float, y as Float, 0.1) })
+                        .collect()
+                })
+                .collect();
+            let mut file = Vec::new();
+            write_hdr(&image, &mut file).unwrap();
+
+            let (read_width, read_height, pixels) = crate::environment::read_hdr(&file).unwrap();
+            assert_eq!((read_width, read_height), (width, 3));
+            for (pixel, color) in pixels.iter().zip(image.iter().flatten()) {
+                assert_eq!(*pixel, from_rgbe(to_rgbe(*color)), "{} wide", width);
+            }
+        }
+    }
+}
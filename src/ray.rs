@@ -1,23 +1,60 @@
 // Ray structure for raytracing
 
-use crate::vec3::Vec3;
+use crate::vec3::{Float, Point3, Vec3};
+use crate::simd::FloatX4;
 
 #[derive(Debug, Clone)]
 pub struct Ray {
-    pub origin: Vec3,
+    pub origin: Point3,
     pub direction: Vec3,
+    /// Moment within the shutter interval, 0 = shutter open, 1 = shutter close
+    pub time: Float,
 }
 
 impl Ray {
-    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+    pub fn new(origin: Point3, direction: Vec3) -> Self {
         Ray {
             origin,
             direction: direction.normalize(),
+            time: 0.0,
         }
     }
 
+    pub fn with_time(mut self, time: Float) -> Self {
+        self.time = time;
+        self
+    }
+
     /// Gets a point along the ray at parameter t
-    pub fn at(&self, t: f32) -> Vec3 {
+    pub fn at(&self, t: Float) -> Point3 {
         self.origin + self.direction * t
     }
 }
+
+/// Four rays traced together. Components are stored lane by lane (all four origin x values,
+/// then y, ...) so one SIMD instruction handles the same step for every ray.
+pub struct RayPacket {
+    pub rays: [Ray; 4],
+    pub origin: [FloatX4; 3],
+    pub direction: [FloatX4; 3],
+    pub inv_direction: [FloatX4; 3],
+    /// Shared by all four rays; taken from the first
+    pub time: Float,
+}
+
+impl RayPacket {
+    pub fn new(rays: [Ray; 4]) -> Self {
+        let component = |axis: usize, of: fn(&Ray) -> Vec3| FloatX4::from_array(std::array::from_fn(|lane| of(&rays[lane])[axis]));
+        let origin = std::array::from_fn(|axis| component(axis, |ray| ray.origin.to_vec3()));
+        let direction = std::array::from_fn(|axis| component(axis, |ray| ray.direction));
+        let inv_direction = std::array::from_fn(|axis| component(axis, |ray| ray.direction.recip()));
+
+        RayPacket {
+            time: rays[0].time,
+            rays,
+            origin,
+            direction,
+            inv_direction,
+        }
+    }
+}
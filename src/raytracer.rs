@@ -1,16 +1,71 @@
 // Raytracing engine
 
-use crate::vec3::Vec3;
-use crate::ray::Ray;
+use crate::vec3::{to_f32, Float, Vec3};
+use crate::ray::{Ray, RayPacket};
 use crate::scene::Scene;
 use crate::camera::Camera;
 use crate::geometry::HitRecord;
-use raylib::prelude::*;
+use crate::debug::{self, DebugMode};
+use crate::animation::Animation;
+use crate::sampling::{self, AdaptiveSampling, Filter, PixelEstimate, Rng};
+use crate::output::{self, Rgba8};
+use crate::tonemap::ToneMapping;
+use crate::occlusion::AmbientOcclusion;
+use crate::aov::{Aov, AovBuffers, AovEstimate, AovSample, Shading};
+use crate::denoise::{Denoiser, Guides};
+use crate::stats::{self, Counter, Recorder, RenderStats};
+use crate::tiles::{Crop, Rect, TileOrder};
+use crate::checkpoint::{Checkpoint, Fingerprint, TileData};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::ops::Range;
+use std::sync::Mutex;
+use std::time::Instant;
 
 pub struct Raytracer {
     pub width: u32,
     pub height: u32,
     pub max_depth: i32,
+    pub samples_per_pixel: u32,
+    pub filter: Filter,
+    pub seed: u64,
+    pub adaptive: Option<AdaptiveSampling>,
+    pub ambient_occlusion: Option<AmbientOcclusion>,
+    pub debug_mode: Option<DebugMode>,
+    pub debug_depth_range: Float,
+    pub tone_mapping: ToneMapping,
+    /// Render passes filled alongside the beauty image
+    pub aovs: Vec<Aov>,
+    pub denoiser: Option<Denoiser>,
+    /// Trace single-sample pinhole renders four primary rays at a time
+    pub packets: bool,
+    /// Measure time spent tracing and shading, at some cost in speed
+    pub phase_timing: bool,
+    /// Tiles are rendered on this many threads
+    pub threads: usize,
+    /// Only this part of the image is rendered
+    pub crop: Option<Crop>,
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+    /// Finished tiles are saved to this file, and tiles found there are not rendered again
+    pub checkpoint: Option<String>,
+    pub log_progress: bool,
+}
+
+/// Pixels of one row of a tile, before they are added to the tile
+struct RenderedRow {
+    colors: Vec<Vec3>,
+    sample_counts: Vec<u32>,
+    /// Empty when no passes are rendered
+    aovs: Vec<AovEstimate>,
+}
+
+/// A finished still: the beauty image, samples taken per pixel, the requested passes and
+/// what it took to render
+pub struct RenderOutput {
+    pub image: Vec<Vec<Vec3>>,
+    pub sample_counts: Vec<Vec<u32>>,
+    pub aovs: AovBuffers,
+    pub stats: RenderStats,
 }
 
 impl Raytracer {
@@ -19,61 +74,783 @@ impl Raytracer {
             width,
             height,
             max_depth: 5,
+            samples_per_pixel: 1,
+            filter: Filter::Box,
+            seed: 0,
+            adaptive: None,
+            ambient_occlusion: None,
+            debug_mode: None,
+            debug_depth_range: 20.0,
+            tone_mapping: ToneMapping::new(),
+            aovs: Vec::new(),
+            denoiser: None,
+            packets: false,
+            phase_timing: false,
+            threads: 1,
+            crop: None,
+            tile_size: 32,
+            tile_order: TileOrder::Scanline,
+            checkpoint: None,
+            log_progress: true,
+        }
+    }
+
+    /// Quiet single-sample copy at 1/`scale` resolution, for interactive previews
+    pub fn preview(&self, scale: u32) -> Raytracer {
+        let scale = scale.max(1);
+        Raytracer {
+            width: (self.width / scale).max(2),
+            height: (self.height / scale).max(2),
+            max_depth: self.max_depth,
+            samples_per_pixel: 1,
+            filter: Filter::Box,
+            seed: self.seed,
+            adaptive: None,
+            ambient_occlusion: self.ambient_occlusion,
+            debug_mode: self.debug_mode,
+            debug_depth_range: self.debug_depth_range,
+            tone_mapping: self.tone_mapping,
+            aovs: Vec::new(),
+            denoiser: self.denoiser,
+            packets: self.packets,
+            phase_timing: false,
+            threads: self.threads,
+            crop: None,
+            tile_size: self.tile_size,
+            tile_order: self.tile_order,
+            checkpoint: None,
+            log_progress: false,
+        }
+    }
+
+    pub fn with_samples(mut self, samples_per_pixel: u32) -> Self {
+        self.samples_per_pixel = samples_per_pixel.max(1);
+        self
+    }
+
+    /// Most rays traced per camera path, the camera ray included; 0 renders black
+    pub fn with_max_depth(mut self, max_depth: i32) -> Self {
+        self.max_depth = max_depth.max(0);
+        self
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_adaptive_sampling(mut self, adaptive: Option<AdaptiveSampling>) -> Self {
+        self.adaptive = adaptive;
+        self
+    }
+
+    /// Darkens the ambient term by how occluded each shading point is
+    pub fn with_ambient_occlusion(mut self, ambient_occlusion: Option<AmbientOcclusion>) -> Self {
+        self.ambient_occlusion = ambient_occlusion;
+        self
+    }
+
+    pub fn with_debug_mode(mut self, mode: Option<DebugMode>) -> Self {
+        self.debug_mode = mode;
+        self
+    }
+
+    pub fn with_tone_mapping(mut self, tone_mapping: ToneMapping) -> Self {
+        self.tone_mapping = tone_mapping;
+        self
+    }
+
+    /// Fills the given passes in addition to the beauty image. Passes are left black in
+    /// debug modes.
+    pub fn with_aovs(mut self, aovs: Vec<Aov>) -> Self {
+        self.aovs = aovs;
+        self
+    }
+
+    /// Filters the beauty image before tone mapping, guided by the albedo, normal and depth
+    /// passes. Those are filled automatically; the other passes stay unfiltered.
+    pub fn with_denoiser(mut self, denoiser: Option<Denoiser>) -> Self {
+        self.denoiser = denoiser;
+        self
+    }
+
+    /// Primary rays of single-sample pinhole renders are traced as 4-ray SIMD packets. The
+    /// image is identical; other settings ignore it.
+    pub fn with_packets(mut self, packets: bool) -> Self {
+        self.packets = packets;
+        self
+    }
+
+    /// Renders on `threads` threads; the image doesn't depend on the count
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Renders only the pixels inside `crop`; the rest of the image stays black with no
+    /// samples
+    pub fn with_crop(mut self, crop: Option<Crop>) -> Self {
+        self.crop = crop;
+        self
+    }
+
+    /// Renders in `size`-pixel square tiles, started in `order`
+    pub fn with_tiles(mut self, size: u32, order: TileOrder) -> Self {
+        self.tile_size = size.max(1);
+        self.tile_order = order;
+        self
+    }
+
+    /// Saves each finished tile to `path` as it completes. A render with the same settings,
+    /// scene and camera resumes from the tiles already there, so an interrupted render
+    /// loses only the tiles in progress. The file is left in place afterwards.
+    pub fn with_checkpoint(mut self, path: Option<String>) -> Self {
+        self.checkpoint = path;
+        self
+    }
+
+    /// Splits the render time in `RenderStats` into tracing and shading. Every intersection
+    /// query is timed, which slows the render down a little.
+    pub fn with_phase_timing(mut self, phase_timing: bool) -> Self {
+        self.phase_timing = phase_timing;
+        self
+    }
+
+    /// Renders the image for display through the display transform
+    pub fn render(&self, scene: &Scene, camera: &Camera) -> Vec<Vec<Rgba8>> {
+        self.display_transform().apply(&self.render_hdr(scene, camera))
+    }
+
+    /// Tone mapping for the current mode. Debug colors are already display values and are
+    /// only quantized.
+    pub fn display_transform(&self) -> ToneMapping {
+        match self.debug_mode {
+            Some(_) => ToneMapping::none(),
+            None => self.tone_mapping,
         }
     }
 
-    pub fn render(&self, scene: &Scene, camera: &Camera) -> Vec<Vec<Color>> {
-        let mut image = vec![vec![Color::BLACK; self.width as usize]; self.height as usize];
+    /// Renders linear, unclamped radiance
+    pub fn render_hdr(&self, scene: &Scene, camera: &Camera) -> Vec<Vec<Vec3>> {
+        self.render_output(scene, camera).image
+    }
 
-        println!("Rendering {}x{} pixels", self.width, self.height);
+    /// Renders linear radiance along with the number of samples taken for each pixel, any
+    /// requested passes and the render's statistics
+    pub fn render_output(&self, scene: &Scene, camera: &Camera) -> RenderOutput {
+        let started = Instant::now();
+        let region = self.region();
+        let passes = self.passes();
 
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let u = x as f32 / (self.width - 1) as f32;
-                let v = (self.height - 1 - y) as f32 / (self.height - 1) as f32;
+        if self.debug_mode == Some(DebugMode::HitCount) {
+            let recorder = Recorder::start(self.phase_timing);
+            let image = self.render_hit_count(scene, camera, region);
+            let sample_counts = vec![vec![1u32; self.width as usize]; self.height as usize];
+            let aovs = AovBuffers::new(&passes, self.width, self.height);
+            return RenderOutput { image, sample_counts, aovs, stats: recorder.finish() };
+        }
 
-                let ray = camera.get_ray(u, v);
-                let color = self.ray_color(&ray, scene, self.max_depth);
+        if self.log_progress {
+            if region.area() == self.width as u64 * self.height as u64 {
+                println!("Rendering {}x{} pixels", self.width, self.height);
+            } else {
+                println!(
+                    "Rendering {}x{} pixels at ({}, {}) of {}x{}",
+                    region.width, region.height, region.x, region.y, self.width, self.height
+                );
+            }
+        }
 
-                image[y as usize][x as usize] = vec3_to_color(color);
+        let (tiles, stats) = self.render_tiles(scene, camera, region, &passes);
+        self.assemble(tiles, stats, started)
+    }
+
+    /// The passes every tile carries: the requested ones, plus the guides the denoiser needs
+    pub fn passes(&self) -> Vec<Aov> {
+        let mut passes = self.aovs.clone();
+        if self.denoiser().is_some() {
+            for guide in [Aov::Albedo, Aov::Normal, Aov::Depth] {
+                if !passes.contains(&guide) {
+                    passes.push(guide);
+                }
             }
+        }
+        passes
+    }
+
+    /// Debug images are never denoised
+    fn denoiser(&self) -> Option<Denoiser> {
+        self.denoiser.filter(|_| self.debug_mode.is_none())
+    }
+
+    /// Puts rendered tiles together into the finished render and denoises it. The tiles may
+    /// come from `render_tile` anywhere, as long as the settings, scene and camera are the
+    /// same. `stats` are the tiles' statistics; the wall time is measured from `started`.
+    pub fn assemble(&self, tiles: Vec<TileData>, mut stats: RenderStats, started: Instant) -> RenderOutput {
+        let passes = self.passes();
+        let mut image = vec![vec![Vec3::zero(); self.width as usize]; self.height as usize];
+        let mut sample_counts = vec![vec![0u32; self.width as usize]; self.height as usize];
+        let mut aovs = AovBuffers::new(&passes, self.width, self.height);
 
-            if y % 100 == 0 {
-                println!("Line {} of {}", y, self.height);
+        for tile in tiles {
+            let rect = tile.rect;
+            for (i, (x, y)) in (rect.y..rect.y + rect.height)
+                .flat_map(|y| (rect.x..rect.x + rect.width).map(move |x| (x as usize, y as usize)))
+                .enumerate()
+            {
+                image[y][x] = tile.colors[i];
+                sample_counts[y][x] = tile.sample_counts[i];
+                for ((_, pass), values) in aovs.passes.iter_mut().zip(&tile.passes) {
+                    pass[y][x] = values[i];
+                }
             }
         }
 
-        println!("Rendering complete!");
+        if self.log_progress && self.adaptive.is_some() {
+            let total: u64 = sample_counts.iter().flatten().map(|&count| count as u64).sum();
+            let pixels = self.region().area().max(1) as Float;
+            println!("Adaptive sampling: {:.2} samples per pixel on average", total as Float / pixels);
+        }
+
+        if let Some(denoiser) = self.denoiser() {
+            let guides = Guides {
+                albedo: aovs.get(Aov::Albedo),
+                normal: aovs.get(Aov::Normal),
+                depth: aovs.get(Aov::Depth),
+            };
+            denoiser.apply(&mut image, &guides);
+            aovs.passes.retain(|(aov, _)| self.aovs.contains(aov));
+        }
+
+        stats.wall_time = started.elapsed();
+        if self.log_progress {
+            println!(
+                "Rendering complete in {:.2}s ({} rays)",
+                stats.wall_time.as_secs_f64(),
+                stats.total_rays()
+            );
+        }
+        RenderOutput { image, sample_counts, aovs, stats }
+    }
+
+    /// The pixels to render: the crop, or the whole image
+    pub fn region(&self) -> Rect {
+        match self.crop {
+            Some(crop) => crop.rect(self.width, self.height),
+            None => Rect::new(0, 0, self.width, self.height),
+        }
+    }
+
+    /// Renders the tiles of `region` on `threads` threads, each taking the next tile nobody
+    /// has started. Tiles the checkpoint already holds are returned instead of rendered
+    /// again. Returns the tiles in no particular order, with the statistics of all threads.
+    fn render_tiles(&self, scene: &Scene, camera: &Camera, region: Rect, passes: &[Aov]) -> (Vec<TileData>, RenderStats) {
+        let started = Instant::now();
+        let packets = self.uses_packets(camera);
+        let (checkpoint, mut done) = self.open_checkpoint(scene, camera, passes);
+
+        let mut finished = vec![false; self.width as usize * self.height as usize];
+        for tile in &done {
+            for y in tile.rect.y..tile.rect.y + tile.rect.height {
+                let row = (y * self.width) as usize;
+                finished[row + tile.rect.x as usize..row + (tile.rect.x + tile.rect.width) as usize].fill(true);
+            }
+        }
+        let all_tiles = region.tiles(self.tile_size, self.tile_order, self.seed);
+        let tiles: Vec<Rect> = all_tiles
+            .iter()
+            .copied()
+            .filter(|tile| {
+                (tile.y..tile.y + tile.height).any(|y| {
+                    let row = (y * self.width) as usize;
+                    finished[row + tile.x as usize..row + (tile.x + tile.width) as usize].contains(&false)
+                })
+            })
+            .collect();
+        if self.log_progress && !done.is_empty() {
+            let path = checkpoint.as_ref().map_or("", |checkpoint| checkpoint.path());
+            println!("Resuming from {}: {} of {} tiles left", path, tiles.len(), all_tiles.len());
+        }
+
+        let checkpoint = Mutex::new(checkpoint);
+        let next_tile = AtomicUsize::new(0);
+        let completed_tiles = AtomicUsize::new(0);
+        let progress_step = tiles.len().div_ceil(10).max(1);
+
+        let work = || {
+            let recorder = Recorder::start(self.phase_timing);
+            let mut rendered = Vec::new();
+            loop {
+                let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                let Some(&rect) = tiles.get(index) else {
+                    break;
+                };
+
+                let tile = self.trace_tile(scene, camera, rect, packets, passes);
+                save_tile(&checkpoint, &tile);
+                rendered.push(tile);
+                // Tiles finish out of order on several threads, so count them as they finish
+                let completed = completed_tiles.fetch_add(1, Ordering::Relaxed) + 1;
+                if self.log_progress && completed.is_multiple_of(progress_step) {
+                    println!("Tile {} of {}", completed, tiles.len());
+                }
+            }
+            (rendered, recorder.finish())
+        };
+
+        // A single thread renders on the caller's, so nothing is spawned for previews
+        let threads = self.threads.clamp(1, tiles.len().max(1));
+        let results = if threads == 1 {
+            vec![work()]
+        } else {
+            std::thread::scope(|scope| {
+                let workers: Vec<_> = (0..threads).map(|_| scope.spawn(work)).collect();
+                workers
+                    .into_iter()
+                    .map(|worker| worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        let mut stats = RenderStats::default();
+        for (thread_tiles, thread_stats) in results {
+            done.extend(thread_tiles);
+            stats.add(&thread_stats);
+        }
+        stats.render_time = started.elapsed();
+        (done, stats)
+    }
+
+    /// The checkpoint and the tiles it holds. Without one, or if it can't be opened, the
+    /// render goes on without.
+    fn open_checkpoint(&self, scene: &Scene, camera: &Camera, passes: &[Aov]) -> (Option<Checkpoint>, Vec<TileData>) {
+        let Some(path) = &self.checkpoint else {
+            return (None, Vec::new());
+        };
+
+        match Checkpoint::open(path, self.fingerprint(scene, camera, passes), passes.len()) {
+            Ok((checkpoint, tiles)) => (Some(checkpoint), tiles),
+            Err(error) => {
+                eprintln!("Rendering without a checkpoint: {}", error);
+                (None, Vec::new())
+            }
+        }
+    }
+
+    /// Everything that decides the pixel values of a render, and the region rendered, so
+    /// tiles outside a changed crop aren't resumed; tiling, threads and packets don't change
+    /// them
+    fn fingerprint(&self, scene: &Scene, camera: &Camera, passes: &[Aov]) -> u64 {
+        Fingerprint::new()
+            .add(&self.width)
+            .add(&self.height)
+            .add(&self.region())
+            .add(&self.max_depth)
+            .add(&self.samples_per_pixel)
+            .add(&self.seed)
+            .add(&self.filter)
+            .add(&self.adaptive)
+            .add(&self.ambient_occlusion)
+            .add(&self.debug_mode)
+            .add(&self.debug_depth_range)
+            .add(passes)
+            .add(scene)
+            .add(camera)
+            .finish()
+    }
+
+    /// Renders the pixels of `rect` on the calling thread, with every pass of `passes`.
+    /// Tiles of the same render fit together exactly wherever they were rendered.
+    pub fn render_tile(&self, scene: &Scene, camera: &Camera, rect: Rect) -> TileData {
+        self.trace_tile(scene, camera, rect, self.uses_packets(camera), &self.passes())
+    }
+
+    /// Colors, sample counts and `passes` of the pixels in `rect`
+    fn trace_tile(&self, scene: &Scene, camera: &Camera, rect: Rect, packets: bool, passes: &[Aov]) -> TileData {
+        let pixels = rect.area() as usize;
+        let mut tile = TileData {
+            rect,
+            colors: Vec::with_capacity(pixels),
+            sample_counts: Vec::with_capacity(pixels),
+            passes: vec![Vec::with_capacity(pixels); passes.len()],
+        };
+
+        for y in rect.y..rect.y + rect.height {
+            let row = self.render_row(scene, camera, y, rect.x..rect.x + rect.width, packets, !passes.is_empty());
+            tile.colors.extend(row.colors);
+            tile.sample_counts.extend(row.sample_counts);
+            for (values, &aov) in tile.passes.iter_mut().zip(passes) {
+                values.extend(row.aovs.iter().map(|estimate| estimate.value(aov)));
+            }
+        }
+
+        tile
+    }
+
+    /// Colors and sample counts of pixels `xs` of row `y`, plus pass estimates when
+    /// `with_aovs` is set
+    fn render_row(&self, scene: &Scene, camera: &Camera, y: u32, xs: Range<u32>, packets: bool, with_aovs: bool) -> RenderedRow {
+        let width = xs.len();
+        let mut row = RenderedRow {
+            colors: vec![Vec3::zero(); width],
+            sample_counts: vec![1; width],
+            aovs: if with_aovs { vec![AovEstimate::new(); width] } else { Vec::new() },
+        };
+
+        if packets {
+            let aovs = with_aovs.then_some(row.aovs.as_mut_slice());
+            self.trace_row_packets(scene, camera, y, xs, &mut row.colors, aovs);
+            return row;
+        }
+
+        for (i, x) in xs.enumerate() {
+            let (color, samples) = match self.debug_mode {
+                Some(mode) => {
+                    let mut rng = Rng::for_pixel(self.seed, x, y);
+                    stats::count(Counter::PrimaryRays);
+                    (self.debug_color(&self.pixel_ray(camera, x, y), scene, mode, &mut rng), 1)
+                }
+                None if !with_aovs => self.sample_pixel(scene, camera, x, y, None),
+                None => self.sample_pixel(scene, camera, x, y, Some(&mut row.aovs[i])),
+            };
+
+            row.colors[i] = color;
+            row.sample_counts[i] = samples;
+        }
+
+        row
+    }
+
+    /// Renders `frames` of `animation`, posing the scene and camera at each frame's time,
+    /// and hands every finished HDR image to `on_frame` together with its frame number
+    pub fn render_sequence(
+        &self,
+        scene: &mut Scene,
+        camera: &mut Camera,
+        animation: &Animation,
+        frames: std::ops::Range<usize>,
+        mut on_frame: impl FnMut(usize, Vec<Vec<Vec3>>),
+    ) {
+        let total = animation.frame_count();
+
+        for frame in frames {
+            animation.apply_over_shutter(animation.frame_time(frame), scene, camera);
+            if self.log_progress {
+                println!("Frame {} of {}", frame + 1, total);
+            }
+            on_frame(frame, self.render_hdr(scene, camera));
+        }
+    }
+
+    /// Heat map of the samples taken per pixel, relative to the adaptive maximum
+    pub fn sample_count_heat_map(&self, sample_counts: &[Vec<u32>]) -> Vec<Vec<Rgba8>> {
+        let max_samples = match self.adaptive {
+            Some(adaptive) => adaptive.max_samples,
+            None => self.samples_per_pixel,
+        }
+        .max(1);
+
+        sample_counts
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|&count| output::vec3_to_color(debug::heat_map(count as Float / max_samples as Float)))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Heat map of primitive intersection tests per pixel in `region`, normalized to the
+    /// busiest pixel
+    fn render_hit_count(&self, scene: &Scene, camera: &Camera, region: Rect) -> Vec<Vec<Vec3>> {
+        let mut counts = vec![vec![0u32; self.width as usize]; self.height as usize];
+
+        for y in region.y..region.y + region.height {
+            for x in region.x..region.x + region.width {
+                let tests = stats::intersection_tests();
+                let ray = self.pixel_ray(camera, x, y);
+                stats::count(Counter::PrimaryRays);
+                self.ray_color(&ray, scene, self.max_depth, &mut Rng::for_pixel(self.seed, x, y));
+                counts[y as usize][x as usize] = (stats::intersection_tests() - tests) as u32;
+            }
+        }
+
+        let max_count = counts.iter().flatten().copied().max().unwrap_or(0).max(1);
+        if self.log_progress {
+            println!("Max intersection tests per pixel: {}", max_count);
+        }
+
+        let mut image = vec![vec![Vec3::zero(); self.width as usize]; self.height as usize];
+        for y in region.y..region.y + region.height {
+            for x in region.x..region.x + region.width {
+                let count = counts[y as usize][x as usize];
+                image[y as usize][x as usize] = debug::heat_map(count as Float / max_count as Float);
+            }
+        }
         image
     }
 
-    fn ray_color(&self, ray: &Ray, scene: &Scene, depth: i32) -> Vec3 {
+    /// Distance along the pinhole ray through each pixel centre to the first hit, or
+    /// infinity where the ray escapes. Written as the Z channel of EXR renders.
+    pub fn render_depth(&self, scene: &Scene, camera: &Camera) -> Vec<Vec<f32>> {
+        (0..self.height)
+            .map(|y| {
+                (0..self.width)
+                    .map(|x| {
+                        let ray = self.pixel_ray(camera, x, y);
+                        scene.hit(&ray, 0.0, Float::INFINITY).map_or(f32::INFINITY, |hit| to_f32(hit.t))
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Filtered average of stratified rays spread over the filter support. With adaptive
+    /// sampling, batches are added until the pixel's noise estimate drops below the threshold.
+    /// Returns the color and the number of samples taken; pass values go into `aovs`.
+    fn sample_pixel(
+        &self,
+        scene: &Scene,
+        camera: &Camera,
+        x: u32,
+        y: u32,
+        mut aovs: Option<&mut AovEstimate>,
+    ) -> (Vec3, u32) {
+        let mut rng = Rng::for_pixel(self.seed, x, y);
+
+        if self.is_single_sample(camera) {
+            let ray = self.pixel_ray(camera, x, y);
+            return (self.camera_sample(&ray, scene, &mut rng, aovs, 1.0, 0.0), 1);
+        }
+
+        let mut estimate = PixelEstimate::new();
+
+        match self.adaptive {
+            None => self.add_samples(&mut estimate, aovs, self.samples_per_pixel, &mut rng, scene, camera, x, y),
+            Some(adaptive) => {
+                self.add_samples(&mut estimate, aovs.as_deref_mut(), adaptive.min_samples, &mut rng, scene, camera, x, y);
+
+                while estimate.count < adaptive.max_samples && estimate.noise() > adaptive.threshold {
+                    let batch = adaptive.min_samples.min(adaptive.max_samples - estimate.count);
+                    self.add_samples(&mut estimate, aovs.as_deref_mut(), batch, &mut rng, scene, camera, x, y);
+                }
+            }
+        }
+
+        (estimate.color(), estimate.count)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn add_samples(
+        &self,
+        estimate: &mut PixelEstimate,
+        mut aovs: Option<&mut AovEstimate>,
+        count: u32,
+        rng: &mut Rng,
+        scene: &Scene,
+        camera: &Camera,
+        x: u32,
+        y: u32,
+    ) {
+        let radius = self.filter.radius();
+        let pixel_samples = sampling::stratified_2d(count, rng);
+        let mut lens_samples = sampling::stratified_2d(count, rng);
+        sampling::shuffle(&mut lens_samples, rng);
+        let time_samples = if camera.has_motion_blur() {
+            let mut times = sampling::stratified_1d(count, rng);
+            sampling::shuffle(&mut times, rng);
+            times
+        } else {
+            vec![0.0; count as usize]
+        };
+
+        let samples = pixel_samples.into_iter().zip(lens_samples).zip(time_samples);
+        for (((sx, sy), (lens_u, lens_v)), time) in samples {
+            let dx = (sx * 2.0 - 1.0) * radius;
+            let dy = (sy * 2.0 - 1.0) * radius;
+
+            let (u, v) = self.image_to_uv(x as Float + 0.5 + dx, y as Float + 0.5 + dy);
+            let ray = camera.get_ray_at_time(u, v, lens_u, lens_v, time);
+            let weight = self.filter.weight(dx, dy);
+            let color = self.camera_sample(&ray, scene, rng, aovs.as_deref_mut(), weight, dx * dx + dy * dy);
+            estimate.add(color, weight);
+        }
+    }
+
+    /// Beauty color of a camera ray. With `aovs`, the primary hit's pass values are added
+    /// with the sample's filter weight and squared distance from the pixel centre.
+    fn camera_sample(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        rng: &mut Rng,
+        aovs: Option<&mut AovEstimate>,
+        weight: Float,
+        distance_squared: Float,
+    ) -> Vec3 {
+        stats::count(Counter::PrimaryRays);
+        match aovs {
+            None => self.ray_color(ray, scene, self.max_depth, rng),
+            Some(aovs) => {
+                let hit = scene.hit(ray, 0.0, Float::INFINITY);
+                self.primary_color(ray, hit, scene, rng, aovs, weight, distance_squared)
+            }
+        }
+    }
+
+    /// `camera_sample` for a camera ray whose first hit is already known
+    #[allow(clippy::too_many_arguments)]
+    fn primary_color(
+        &self,
+        ray: &Ray,
+        hit: Option<HitRecord>,
+        scene: &Scene,
+        rng: &mut Rng,
+        aovs: &mut AovEstimate,
+        weight: Float,
+        distance_squared: Float,
+    ) -> Vec3 {
+        let (color, sample) = match hit {
+            Some(hit) if self.max_depth > 0 => {
+                let shading = self.calculate_lighting(&hit, ray, scene, self.max_depth, rng);
+                (shading.total(), AovSample::hit(&hit, &shading))
+            }
+            Some(_) => (Vec3::zero(), AovSample::miss(Vec3::zero())),
+            None => {
+                let background = scene.get_background_color(ray);
+                (background, AovSample::miss(background))
+            }
+        };
+
+        aovs.add(&sample, weight, distance_squared);
+        color
+    }
+
+    /// Single-sample pinhole pixels `xs` of row `y` traced four at a time; the result
+    /// matches `sample_pixel` exactly
+    fn trace_row_packets(
+        &self,
+        scene: &Scene,
+        camera: &Camera,
+        y: u32,
+        xs: Range<u32>,
+        row: &mut [Vec3],
+        mut aovs: Option<&mut [AovEstimate]>,
+    ) {
+        for start in xs.clone().step_by(4) {
+            // The last packet of a row repeats its final pixel to fill the lanes
+            let packet = RayPacket::new(std::array::from_fn(|lane| {
+                self.pixel_ray(camera, (start + lane as u32).min(xs.end - 1), y)
+            }));
+            let hits = scene.hit_packet(&packet, 0.0, Float::INFINITY);
+
+            for (lane, hit) in hits.into_iter().enumerate() {
+                let x = start + lane as u32;
+                if x >= xs.end {
+                    break;
+                }
+                let i = (x - xs.start) as usize;
+                stats::count(Counter::PrimaryRays);
+
+                let ray = &packet.rays[lane];
+                let mut rng = Rng::for_pixel(self.seed, x, y);
+                row[i] = match aovs.as_deref_mut() {
+                    None if self.max_depth <= 0 => Vec3::zero(),
+                    None => match hit {
+                        Some(hit) => self.calculate_lighting(&hit, ray, scene, self.max_depth, &mut rng).total(),
+                        None => scene.get_background_color(ray),
+                    },
+                    Some(aovs) => self.primary_color(ray, hit, scene, &mut rng, &mut aovs[i], 1.0, 0.0),
+                };
+            }
+        }
+    }
+
+    fn uses_packets(&self, camera: &Camera) -> bool {
+        self.packets && self.debug_mode.is_none() && self.is_single_sample(camera)
+    }
+
+    /// One pinhole ray per pixel, without lens or shutter sampling
+    fn is_single_sample(&self, camera: &Camera) -> bool {
+        self.samples_per_pixel <= 1 && self.adaptive.is_none() && camera.aperture <= 0.0 && !camera.has_motion_blur()
+    }
+
+    /// Pinhole ray through the centre of pixel (x, y)
+    fn pixel_ray(&self, camera: &Camera, x: u32, y: u32) -> Ray {
+        let (u, v) = self.image_to_uv(x as Float + 0.5, y as Float + 0.5);
+        camera.get_ray(u, v)
+    }
+
+    /// Focuses the camera's lens on whatever is visible at image pixel (x, y)
+    pub fn autofocus(&self, camera: &mut Camera, scene: &Scene, x: Float, y: Float) -> Option<Float> {
+        let (u, v) = self.image_to_uv(x + 0.5, y + 0.5);
+        camera.autofocus(scene, u, v)
+    }
+
+    /// Converts image coordinates (y down) to the camera's [0, 1] viewport coordinates (v up)
+    pub fn image_to_uv(&self, image_x: Float, image_y: Float) -> (Float, Float) {
+        (image_x / self.width as Float, 1.0 - image_y / self.height as Float)
+    }
+
+    fn debug_color(&self, ray: &Ray, scene: &Scene, mode: DebugMode, rng: &mut Rng) -> Vec3 {
+        match scene.hit(ray, 0.0, Float::INFINITY) {
+            Some(hit) if mode == DebugMode::AmbientOcclusion => {
+                let ambient_occlusion = self.ambient_occlusion.unwrap_or(AmbientOcclusion::new(16, 2.0));
+                let visibility = ambient_occlusion.visibility(scene, &hit, ray.time, rng);
+                Vec3::new(1.0, 1.0, 1.0) * visibility
+            }
+            Some(hit_record) => mode.shade(&hit_record, self.debug_depth_range),
+            None => mode.miss_color(),
+        }
+    }
+
+    fn ray_color(&self, ray: &Ray, scene: &Scene, depth: i32, rng: &mut Rng) -> Vec3 {
         if depth <= 0 {
             return Vec3::zero();
         }
 
-        if let Some(hit_record) = scene.hit(ray, 0.001, f32::INFINITY) {
-            self.calculate_lighting(&hit_record, ray, scene, depth)
+        if let Some(hit_record) = scene.hit(ray, 0.0, Float::INFINITY) {
+            self.calculate_lighting(&hit_record, ray, scene, depth, rng).total()
         } else {
             scene.get_background_color(ray)
         }
     }
 
-    fn calculate_lighting(&self, hit: &HitRecord, incident_ray: &Ray, scene: &Scene, depth: i32) -> Vec3 {
-        let mut color = Vec3::zero();
+    /// Shading at a hit, split into the contributions written to the light passes
+    fn calculate_lighting(&self, hit: &HitRecord, incident_ray: &Ray, scene: &Scene, depth: i32, rng: &mut Rng) -> Shading {
+        let mut shading = Shading {
+            emission: hit.material.emitted().to_vec3(),
+            ambient: Vec3::zero(),
+            diffuse: Vec3::zero(),
+            specular: Vec3::zero(),
+            reflection: Vec3::zero(),
+            shadow: 1.0,
+        };
 
-        let material_color = hit.material.get_color_at_point(&hit.point);
+        let material_color = hit.material.get_color_at_point(&hit.local_point).to_vec3();
 
-        color += hit.material.emitted();
-        color += scene.ambient_light * material_color;
+        shading.ambient = scene.ambient_at(hit.normal) * material_color;
+        if let Some(ambient_occlusion) = &self.ambient_occlusion {
+            shading.ambient *= ambient_occlusion.visibility(scene, hit, incident_ray.time, rng);
+        }
 
-        for (light, shadow_factor) in scene.get_lights_affecting_point(hit.point) {
+        let lights = scene.get_lights_affecting_point(hit, incident_ray.time);
+        if !lights.is_empty() {
+            let lit = lights.iter().filter(|(_, shadow_factor)| *shadow_factor >= 1.0).count();
+            shading.shadow = lit as Float / lights.len() as Float;
+        }
+
+        for (light, shadow_factor) in lights {
             let light_dir = light.get_direction_from(hit.point);
-            let light_color = light.get_effective_color(hit.point);
+            let light_color = light.get_effective_color(hit.point).to_vec3();
 
             let diffuse_strength = hit.normal.dot(&light_dir).max(0.0);
-            let diffuse = material_color * light_color * diffuse_strength * shadow_factor;
-            color += diffuse;
+            shading.diffuse += material_color * light_color * diffuse_strength * shadow_factor;
 
             if hit.material.specular > 0.0 && diffuse_strength > 0.0 {
                 let view_dir = (-incident_ray.direction).normalize();
@@ -82,27 +859,28 @@ impl Raytracer {
                 let spec_strength = view_dir.dot(&reflect_dir).max(0.0)
                     .powf((1.0 - hit.material.roughness) * 128.0);
 
-                let specular = light_color * hit.material.specular * spec_strength * shadow_factor;
-                color += specular;
+                shading.specular += light_color * hit.material.specular * spec_strength * shadow_factor;
             }
         }
 
         if hit.material.reflectivity > 0.0 && depth > 1 {
             let reflected = incident_ray.direction.reflect(&hit.normal);
-            let reflection_ray = Ray::new(hit.point + hit.normal * 0.001, reflected);
-            let reflection_color = self.ray_color(&reflection_ray, scene, depth - 1);
-            color += reflection_color * hit.material.reflectivity;
+            let reflection_ray = hit.spawn_ray(reflected).with_time(incident_ray.time);
+            stats::count(Counter::ReflectionRays);
+            let reflection_color = self.ray_color(&reflection_ray, scene, depth - 1, rng);
+            shading.reflection = reflection_color * hit.material.reflectivity;
         }
 
-        color.clamp(0.0, 1.0)
+        shading
     }
 }
 
-fn vec3_to_color(color: Vec3) -> Color {
-    Color::new(
-        (color.x.clamp(0.0, 1.0) * 255.0) as u8,
-        (color.y.clamp(0.0, 1.0) * 255.0) as u8,
-        (color.z.clamp(0.0, 1.0) * 255.0) as u8,
-        255,
-    )
+/// Appends a finished tile to the checkpoint, if there is one. After a failed write the
+/// render goes on without it.
+fn save_tile(checkpoint: &Mutex<Option<Checkpoint>>, tile: &TileData) {
+    let mut checkpoint = checkpoint.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(Err(error)) = checkpoint.as_mut().map(|checkpoint| checkpoint.write(tile)) {
+        eprintln!("Checkpoint disabled: {}", error);
+        *checkpoint = None;
+    }
 }
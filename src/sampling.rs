@@ -0,0 +1,402 @@
+// Random numbers, sub-pixel sample patterns and reconstruction filters
+
+use crate::vec3::{Float, Vec3, consts};
+use crate::checkpoint::{Fingerprint, Fingerprinted};
+
+/// Small deterministic generator (SplitMix64) so renders repeat exactly for a given seed
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    /// Independent stream per pixel, so results don't depend on render order
+    pub fn for_pixel(seed: u64, x: u32, y: u32) -> Self {
+        let pixel = ((y as u64) << 32) | x as u64;
+        let mut rng = Rng::new(seed ^ pixel.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        rng.next_u64();
+        rng
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform `Float` in [0, 1), with 24 bits of resolution in either precision
+    pub fn next_float(&mut self) -> Float {
+        (self.next_u64() >> 40) as Float / (1u64 << 24) as Float
+    }
+}
+
+/// `count` points in [0, 1)^2: one jittered point per cell of the largest square grid that
+/// fits, then the points left over placed uniformly at random, so every part of the square
+/// is equally likely to be sampled whatever the count
+pub fn stratified_2d(count: u32, rng: &mut Rng) -> Vec<(Float, Float)> {
+    let size = (count as Float).sqrt().floor() as u32;
+    let cells = size * size;
+
+    (0..count)
+        .map(|i| {
+            if i >= cells {
+                return (rng.next_float(), rng.next_float());
+            }
+            let cell_x = (i % size) as Float;
+            let cell_y = (i / size) as Float;
+            (
+                (cell_x + rng.next_float()) / size as Float,
+                (cell_y + rng.next_float()) / size as Float,
+            )
+        })
+        .collect()
+}
+
+/// `count` jittered values in [0, 1), one per equal slice
+pub fn stratified_1d(count: u32, rng: &mut Rng) -> Vec<Float> {
+    (0..count).map(|i| (i as Float + rng.next_float()) / count as Float).collect()
+}
+
+/// Shuffles samples so two stratified sets can be paired without correlation
+pub fn shuffle<T>(items: &mut [T], rng: &mut Rng) {
+    for i in (1..items.len()).rev() {
+        let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
+
+/// Maps [0, 1)^2 onto the unit disk, keeping strata intact (Shirley-Chiu concentric mapping)
+pub fn concentric_disk(u: Float, v: Float) -> (Float, Float) {
+    let a = u * 2.0 - 1.0;
+    let b = v * 2.0 - 1.0;
+
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let quarter_pi = consts::FRAC_PI_4;
+    let (radius, phi) = if a.abs() > b.abs() {
+        (a, quarter_pi * (b / a))
+    } else {
+        (b, 2.0 * quarter_pi - quarter_pi * (a / b))
+    };
+
+    (radius * phi.cos(), radius * phi.sin())
+}
+
+/// Cosine-weighted direction on the hemisphere around +Z, as (x, y, z)
+pub fn cosine_hemisphere(u: Float, v: Float) -> (Float, Float, Float) {
+    let (x, y) = concentric_disk(u, v);
+    (x, y, (1.0 - x * x - y * y).max(0.0).sqrt())
+}
+
+/// Maps [0, 1)^2 uniformly onto a regular polygon inscribed in the unit circle
+pub fn regular_polygon(u: Float, v: Float, sides: u32, rotation: Float) -> (Float, Float) {
+    let sides = sides.max(3);
+    let scaled = u * sides as Float;
+    let segment = (scaled.floor() as u32).min(sides - 1);
+    let u = scaled - segment as Float;
+
+    let angle_step = consts::TAU / sides as Float;
+    let a0 = rotation + segment as Float * angle_step;
+    let a1 = a0 + angle_step;
+
+    // Uniform point in the triangle (centre, vertex a0, vertex a1)
+    let su = u.sqrt();
+    let w0 = su * (1.0 - v);
+    let w1 = su * v;
+
+    (w0 * a0.cos() + w1 * a1.cos(), w0 * a0.sin() + w1 * a1.sin())
+}
+
+/// Adaptive sampling settings: every pixel gets `min_samples`, then further batches of
+/// `min_samples` until the standard error of its luminance is below `threshold`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveSampling {
+    pub min_samples: u32,
+    pub max_samples: u32,
+    pub threshold: Float,
+}
+
+impl AdaptiveSampling {
+    /// `min_samples` is raised to 2, which the noise estimate needs, and `max_samples` to
+    /// `min_samples`
+    pub fn new(min_samples: u32, max_samples: u32, threshold: Float) -> Self {
+        let min_samples = min_samples.max(2);
+        AdaptiveSampling {
+            min_samples,
+            max_samples: max_samples.max(min_samples),
+            threshold,
+        }
+    }
+}
+
+impl Fingerprinted for AdaptiveSampling {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add(&self.min_samples).add(&self.max_samples).add(&self.threshold);
+    }
+}
+
+/// True when signed filter weights cancel so far that dividing by their sum would amplify
+/// noise: a Mitchell filter's negative lobes often do this at a few samples per pixel
+pub fn weights_cancel(weight_sum: Float, abs_weight_sum: Float) -> bool {
+    weight_sum <= 0.25 * abs_weight_sum
+}
+
+/// Running filtered color and luminance statistics for one pixel
+#[derive(Debug, Clone)]
+pub struct PixelEstimate {
+    pub count: u32,
+    color_sum: Vec3,
+    weight_sum: Float,
+    abs_weight_sum: Float,
+    weight_sq_sum: Float,
+    /// Unweighted, for when the filter weights cancel
+    plain_sum: Vec3,
+    min: Vec3,
+    max: Vec3,
+    luminance_sum: Float,
+    luminance_sq_sum: Float,
+}
+
+impl PixelEstimate {
+    pub fn new() -> Self {
+        PixelEstimate {
+            count: 0,
+            color_sum: Vec3::zero(),
+            weight_sum: 0.0,
+            abs_weight_sum: 0.0,
+            weight_sq_sum: 0.0,
+            plain_sum: Vec3::zero(),
+            min: Vec3::new(Float::INFINITY, Float::INFINITY, Float::INFINITY),
+            max: Vec3::new(Float::NEG_INFINITY, Float::NEG_INFINITY, Float::NEG_INFINITY),
+            luminance_sum: 0.0,
+            luminance_sq_sum: 0.0,
+        }
+    }
+
+    pub fn add(&mut self, color: Vec3, weight: Float) {
+        let luminance = color.luminance();
+
+        self.count += 1;
+        self.color_sum += color * weight;
+        self.weight_sum += weight;
+        self.abs_weight_sum += weight.abs();
+        self.weight_sq_sum += weight * weight;
+        self.plain_sum += color;
+        self.min = self.min.min(&color);
+        self.max = self.max.max(&color);
+        self.luminance_sum += luminance;
+        self.luminance_sq_sum += luminance * luminance;
+    }
+
+    /// The filtered color, kept within the per-component range of the samples so negative
+    /// lobes can't push it outside. Falls back to the plain mean when the weights cancel.
+    pub fn color(&self) -> Vec3 {
+        if self.count == 0 {
+            Vec3::zero()
+        } else if weights_cancel(self.weight_sum, self.abs_weight_sum) {
+            self.plain_sum / self.count as Float
+        } else {
+            (self.color_sum / self.weight_sum).max(&self.min).min(&self.max)
+        }
+    }
+
+    /// Standard error of the luminance of `color`: the variance of the sample luminances,
+    /// scaled by sum(w^2) / sum(w)^2 for the filtered mean (1 / n with equal weights) or by
+    /// 1 / n for the plain mean. Weights that nearly cancel make the filtered mean noisy,
+    /// so adaptive sampling keeps going there.
+    pub fn noise(&self) -> Float {
+        if self.count < 2 {
+            return Float::INFINITY;
+        }
+
+        let n = self.count as Float;
+        let mean = self.luminance_sum / n;
+        let variance = ((self.luminance_sq_sum - n * mean * mean) / (n - 1.0)).max(0.0);
+        let spread = if weights_cancel(self.weight_sum, self.abs_weight_sum) {
+            1.0 / n
+        } else {
+            self.weight_sq_sum / (self.weight_sum * self.weight_sum)
+        };
+        (variance * spread).sqrt()
+    }
+}
+
+impl Default for PixelEstimate {
+    fn default() -> Self {
+        PixelEstimate::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+}
+
+impl Filter {
+    pub const ALL: [Filter; 4] = [Filter::Box, Filter::Tent, Filter::Gaussian, Filter::Mitchell];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Filter::Box => "box",
+            Filter::Tent => "tent",
+            Filter::Gaussian => "gaussian",
+            Filter::Mitchell => "mitchell",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Filter> {
+        Filter::ALL.iter().copied().find(|filter| filter.name() == name)
+    }
+
+    /// Half-width of the filter support in pixels
+    pub fn radius(&self) -> Float {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.0,
+        }
+    }
+
+    /// Separable weight for a sample at offset (dx, dy) from the pixel centre
+    pub fn weight(&self, dx: Float, dy: Float) -> Float {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, d: Float) -> Float {
+        let d = d.abs();
+        let radius = self.radius();
+
+        match self {
+            Filter::Box => if d <= radius { 1.0 } else { 0.0 },
+            Filter::Tent => (radius - d).max(0.0),
+            Filter::Gaussian => {
+                let alpha = 2.0;
+                ((-alpha * d * d).exp() - (-alpha * radius * radius).exp()).max(0.0)
+            }
+            Filter::Mitchell => mitchell_1d(d, 1.0 / 3.0, 1.0 / 3.0),
+        }
+    }
+}
+
+impl Fingerprinted for Filter {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add(self.name());
+    }
+}
+
+/// Mitchell-Netravali cubic with support [-2, 2]
+fn mitchell_1d(x: Float, b: Float, c: Float) -> Float {
+    let x2 = x * x;
+    let x3 = x2 * x;
+
+    if x < 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x3 + (-18.0 + 12.0 * b + 6.0 * c) * x2 + (6.0 - 2.0 * b)) / 6.0
+    } else if x < 2.0 {
+        ((-b - 6.0 * c) * x3 + (6.0 * b + 30.0 * c) * x2 + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c)) / 6.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stratified_2d_covers_every_cell_for_any_count() {
+        for count in [1, 2, 3, 5, 7, 8, 10, 15, 16, 17, 24] {
+            let points = stratified_2d(count, &mut Rng::new(count as u64));
+            assert_eq!(points.len(), count as usize);
+            assert!(points.iter().all(|&(u, v)| (0.0..1.0).contains(&u) && (0.0..1.0).contains(&v)));
+
+            let size = (count as Float).sqrt().floor() as usize;
+            let mut covered = vec![false; size * size];
+            for &(u, v) in &points {
+                covered[(v * size as Float) as usize * size + (u * size as Float) as usize] = true;
+            }
+            assert!(covered.iter().all(|&cell| cell), "{} samples leave a cell of the {}x{} grid empty", count, size, size);
+        }
+    }
+
+    #[test]
+    fn stratified_2d_leftover_points_reach_the_whole_square() {
+        // With 3 samples the leftover points are random, so over many pixels every quadrant
+        // gets its share; a grid of 2 columns and 2 rows would never sample one of them
+        let mut quadrants = [0u32; 4];
+        for seed in 0..1000 {
+            for (u, v) in stratified_2d(3, &mut Rng::new(seed)) {
+                quadrants[(v >= 0.5) as usize * 2 + (u >= 0.5) as usize] += 1;
+            }
+        }
+        assert!(quadrants.iter().all(|&samples| samples > 600), "samples per quadrant: {:?}", quadrants);
+    }
+
+    #[test]
+    fn mixed_sign_weights_stay_within_the_samples() {
+        let cases: [&[(Float, Float)]; 4] = [
+            &[(0.2, 0.9), (0.8, -0.88)],
+            &[(1.0, 0.3), (0.0, -0.29), (0.5, 0.01)],
+            &[(0.1, -0.05), (0.9, 0.06), (0.4, 0.02), (0.6, -0.02)],
+            &[(0.3, 0.5), (0.7, -0.1), (0.5, 0.2)],
+        ];
+        for samples in cases {
+            let mut estimate = PixelEstimate::new();
+            for &(value, weight) in samples {
+                estimate.add(Vec3::new(value, value * 0.5, 1.0 - value), weight);
+            }
+            let color = estimate.color();
+            let low = samples.iter().map(|&(value, _)| value).fold(Float::INFINITY, Float::min);
+            let high = samples.iter().map(|&(value, _)| value).fold(Float::NEG_INFINITY, Float::max);
+            assert!((low..=high).contains(&color.x), "{:?} gives {:?}", samples, color);
+            assert!((low * 0.5..=high * 0.5).contains(&color.y), "{:?} gives {:?}", samples, color);
+            assert!((1.0 - high..=1.0 - low).contains(&color.z), "{:?} gives {:?}", samples, color);
+        }
+    }
+
+    #[test]
+    fn positive_weights_give_the_weighted_mean() {
+        let mut estimate = PixelEstimate::new();
+        estimate.add(Vec3::new(1.0, 0.0, 0.0), 3.0);
+        estimate.add(Vec3::new(0.0, 1.0, 0.0), 1.0);
+        assert!(estimate.color().approx_eq(&Vec3::new(0.75, 0.25, 0.0), 1e-6));
+        assert_eq!(PixelEstimate::new().color(), Vec3::zero());
+    }
+
+    #[test]
+    fn noise_follows_the_filter_weights() {
+        let estimate = |weights: &[Float]| {
+            let mut estimate = PixelEstimate::new();
+            for (i, &weight) in weights.iter().enumerate() {
+                estimate.add(Vec3::new(1.0, 1.0, 1.0) * (i % 2) as Float, weight);
+            }
+            estimate.noise()
+        };
+        // Equal weights give the standard error of the plain mean
+        let equal = estimate(&[1.0; 4]);
+        assert!((equal - (1.0 / 3.0 as Float).sqrt() / 2.0).abs() < 1e-5, "{}", equal);
+        // Uneven weights average fewer samples' worth, so the same spread is noisier
+        assert!(estimate(&[1.0, 0.1, 1.0, 0.1]) > equal);
+        assert_eq!(estimate(&[1.0, 1.0, 1.0, 1.0]), estimate(&[2.0, 2.0, 2.0, 2.0]));
+        assert_eq!(PixelEstimate::new().noise(), Float::INFINITY);
+    }
+
+    #[test]
+    fn stratified_2d_repeats_for_a_seed() {
+        let first = stratified_2d(7, &mut Rng::new(42));
+        assert_eq!(first, stratified_2d(7, &mut Rng::new(42)));
+        assert_ne!(first, stratified_2d(7, &mut Rng::new(43)));
+    }
+}
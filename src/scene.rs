@@ -1,18 +1,22 @@
 // Scene management for raytracing
 
-use crate::vec3::Vec3;
-use crate::ray::Ray;
+use crate::vec3::{Color, Float, Point3, Vec3};
+use crate::ray::{Ray, RayPacket};
 use crate::geometry::{Cube, Plane, HittableList, HitRecord, Hittable};
 use crate::light::Light;
-
-const EPSILON: f32 = 0.001;
+use crate::environment::{Environment, Irradiance};
+use crate::stats::{self, Counter};
+use crate::checkpoint::{Fingerprint, Fingerprinted};
 
 #[derive(Debug)]
 pub struct Scene {
     pub objects: HittableList,
     pub lights: Vec<Light>,
-    pub background_color: Vec3,
-    pub ambient_light: Vec3,
+    pub environment: Environment,
+    pub ambient_light: Color,
+    /// How strongly the environment lights diffuse surfaces (0 = background only)
+    pub environment_lighting: Float,
+    irradiance: Option<Irradiance>,
 }
 
 impl Scene {
@@ -20,13 +24,28 @@ impl Scene {
         Scene {
             objects: HittableList::new(),
             lights: Vec::new(),
-            background_color: Vec3::new(0.1, 0.1, 0.2),
-            ambient_light: Vec3::new(0.1, 0.1, 0.1),
+            environment: Environment::Solid(Vec3::new(0.1, 0.1, 0.2)),
+            ambient_light: Color::new(0.1, 0.1, 0.1),
+            environment_lighting: 0.0,
+            irradiance: None,
         }
     }
 
-    pub fn set_background_color(&mut self, color: Vec3) {
-        self.background_color = color;
+    pub fn set_background_color(&mut self, color: Color) {
+        self.set_environment(Environment::Solid(color.to_vec3()));
+    }
+
+    pub fn set_environment(&mut self, environment: Environment) {
+        self.environment = environment;
+        self.irradiance = None;
+        self.set_environment_lighting(self.environment_lighting);
+    }
+
+    /// Lets the environment light diffuse surfaces, scaled by `strength`. Its irradiance is
+    /// precomputed here, so call this again after changing `environment` directly.
+    pub fn set_environment_lighting(&mut self, strength: Float) {
+        self.environment_lighting = strength.max(0.0);
+        self.irradiance = (self.environment_lighting > 0.0).then(|| Irradiance::from_environment(&self.environment));
     }
 
     pub fn add_cube(&mut self, cube: Cube) {
@@ -41,31 +60,43 @@ impl Scene {
         self.lights.push(light);
     }
 
-    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        self.objects.hit(ray, t_min, t_max)
+    pub fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        stats::trace(|| self.objects.hit(ray, t_min, t_max))
     }
 
-    pub fn get_background_color(&self, _ray: &Ray) -> Vec3 {
-        self.background_color
+    /// Closest hits for four rays at once; see `HittableList::hit_packet_records`
+    pub fn hit_packet(&self, packet: &RayPacket, t_min: Float, t_max: Float) -> [Option<HitRecord>; 4] {
+        stats::trace(|| self.objects.hit_packet_records(packet, t_min, t_max))
     }
 
-    pub fn is_in_shadow(&self, from: Vec3, to: Vec3) -> bool {
-        let direction = to - from;
-        let distance = direction.length();
-        let ray = Ray::new(from, direction.normalize());
+    pub fn get_background_color(&self, ray: &Ray) -> Vec3 {
+        self.environment.radiance(ray.direction)
+    }
 
-        if let Some(_hit) = self.hit(&ray, EPSILON, distance - EPSILON) {
-            true
-        } else {
-            false
+    /// Light reaching a diffuse surface from all around: the flat ambient term plus the
+    /// environment's irradiance when environment lighting is on. Occlusion is ignored.
+    pub fn ambient_at(&self, normal: Vec3) -> Vec3 {
+        match &self.irradiance {
+            Some(irradiance) => self.ambient_light.to_vec3() + irradiance.diffuse(normal) * self.environment_lighting,
+            None => self.ambient_light.to_vec3(),
         }
     }
 
-    pub fn get_lights_affecting_point(&self, point: Vec3) -> Vec<(&Light, f32)> {
+    /// Shadow test from a hit toward `to` at `time` within the shutter interval, so moving
+    /// occluders blur too
+    pub fn is_in_shadow(&self, from: &HitRecord, to: Point3, time: Float) -> bool {
+        stats::count(Counter::ShadowRays);
+        let ray = from.spawn_ray(to - from.point).with_time(time);
+        let distance = (to - ray.origin).length();
+
+        self.hit(&ray, 0.0, distance).is_some()
+    }
+
+    pub fn get_lights_affecting_point(&self, hit: &HitRecord, time: Float) -> Vec<(&Light, Float)> {
         let mut affecting_lights = Vec::new();
 
         for light in &self.lights {
-            let shadow_factor = if self.is_in_shadow(point, light.position) {
+            let shadow_factor = if self.is_in_shadow(hit, light.position, time) {
                 0.3
             } else {
                 1.0
@@ -77,3 +108,21 @@ impl Scene {
         affecting_lights
     }
 }
+
+/// The irradiance is derived from the environment, so it is left out
+impl Fingerprinted for Scene {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint
+            .add(&self.objects)
+            .add(&self.lights[..])
+            .add(&self.environment)
+            .add(&self.ambient_light)
+            .add(&self.environment_lighting);
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Scene::new()
+    }
+}
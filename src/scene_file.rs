@@ -0,0 +1,500 @@
+// Plain-text scene files
+//
+// One entity per line, written as a keyword followed by key=value fields.
+// Vectors are comma separated; '#' starts a comment.
+//
+//   background color=1,1,1
+//   ambient color=0.1,0.1,0.1 environment=1
+//   material name=floor color=0.7,0.7,0.7 roughness=0.9
+//   material name=checker checker=1,1,0,1,0,0,0 specular=0.5
+//   plane point=0,-2,0 normal=0,1,0 material=floor
+//   cube center=0,-0.5,-3 size=1.5,1.5,1.5 material=checker
+//   light position=-3,5,2 color=1,1,0.9 intensity=1
+//   camera position=3,4,2 target=0,-0.5,-3 fov=45 roll=0 projection=perspective
+//
+// Objects (cubes and planes, in file order) can be placed with translate=, rotate= (degrees)
+// and scale=, and given motion=dx,dy,dz, how far they move while the shutter is open.
+//
+// Instead of a flat background, the environment can be a gradient, a procedural sky or an
+// equirectangular Radiance .hdr map (path relative to the scene file). `ambient environment=`
+// sets how strongly it lights diffuse surfaces:
+//
+//   gradient zenith=0.2,0.4,0.9 horizon=0.8,0.9,1 ground=0.3,0.3,0.3
+//   sky sun=1,0.6,-0.5 turbidity=3 intensity=1
+//   environment path=studio.hdr rotation=90 intensity=1
+//
+// The camera's shutter=open,close (seconds) enables motion blur for moving objects and
+// animated frames. The display transform is set with
+//
+//   tonemap mapper=aces exposure=0.5 srgb=true dither=true
+//
+// Animation keys refer to objects and lights by index:
+//
+//   animation duration=4 fps=24
+//   key property=object0.rotation time=0 value=0,0,0
+//   key property=object0.rotation time=4 value=0,360,0 interpolation=linear
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::vec3::{Color, Float, Point3, Vec3};
+use crate::material::Material;
+use crate::geometry::{Cube, Hittable, Instance, Plane};
+use crate::transform::Transform;
+use crate::animation::{Animation, Interpolation, Property};
+use crate::light::Light;
+use crate::camera::{Camera, Projection};
+use crate::scene::Scene;
+use crate::tonemap::{ToneMapper, ToneMapping};
+use crate::environment::{Environment, EnvironmentMap, Sky};
+
+pub struct SceneFile {
+    pub scene: Scene,
+    pub camera: Option<Camera>,
+    pub animation: Option<Animation>,
+    pub tone_mapping: Option<ToneMapping>,
+}
+
+pub fn load(path: &str, aspect_ratio: Float) -> Result<SceneFile, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let base_dir = Path::new(path).parent().unwrap_or(Path::new("."));
+    parse(&text, aspect_ratio, base_dir).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Parses scene text. The camera's aspect ratio comes from the render size, not the file.
+/// Relative paths (environment maps) are resolved against `base_dir`.
+pub fn parse(text: &str, aspect_ratio: Float, base_dir: &Path) -> Result<SceneFile, String> {
+    let mut scene = Scene::new();
+    let mut camera = None;
+    let mut animation = None;
+    let mut tone_mapping = None;
+    let mut materials: HashMap<String, Material> = HashMap::new();
+
+    for (index, raw_line) in text.lines().enumerate() {
+        let line = raw_line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut tokens = line.split_whitespace();
+        let keyword = tokens.next().unwrap_or("");
+        let mut fields = Fields::parse(tokens).map_err(|e| format!("line {}: {}", index + 1, e))?;
+
+        let result = match keyword {
+            "animation" => parse_animation_header(&mut fields).map(|header| animation = Some(header)),
+            "tonemap" => parse_tone_mapping(&mut fields).map(|mapping| tone_mapping = Some(mapping)),
+            "key" => match animation.as_mut() {
+                Some(animation) => parse_key(&mut fields, animation),
+                None => Err("'key' before 'animation'".to_string()),
+            },
+            "gradient" | "sky" | "environment" => parse_environment(keyword, &mut fields, base_dir)
+                .map(|environment| scene.set_environment(environment)),
+            _ => parse_entity(keyword, &mut fields, &mut scene, &mut camera, &mut materials, aspect_ratio),
+        };
+
+        result
+            .and_then(|_| fields.finish())
+            .map_err(|e| format!("line {}: {}", index + 1, e))?;
+    }
+
+    Ok(SceneFile { scene, camera, animation, tone_mapping })
+}
+
+fn parse_entity(
+    keyword: &str,
+    fields: &mut Fields,
+    scene: &mut Scene,
+    camera: &mut Option<Camera>,
+    materials: &mut HashMap<String, Material>,
+    aspect_ratio: Float,
+) -> Result<(), String> {
+    match keyword {
+        "background" => scene.set_background_color(fields.required_vec3("color")?.into()),
+        "ambient" => {
+            if let Some(color) = fields.vec3("color")? {
+                scene.ambient_light = color.into();
+            }
+            if let Some(strength) = fields.float("environment")? {
+                scene.set_environment_lighting(strength);
+            }
+        }
+        "material" => {
+            let name = fields.required_str("name")?;
+            // IDs follow definition order, starting at 1
+            let material = parse_material(fields)?.with_id(materials.len() as u32 + 1);
+            materials.insert(name, material);
+        }
+        "cube" => {
+            let material = lookup_material(fields, materials)?;
+            let cube = Cube::new(
+                fields.required_vec3("center")?.into(),
+                fields.required_vec3("size")?,
+                material,
+            )
+            .with_motion(fields.vec3("motion")?.unwrap_or(Vec3::zero()));
+            match parse_transform(fields, cube.center())? {
+                Some(transform) => scene.objects.add(Instance::new(Box::new(cube), transform)),
+                None => scene.add_cube(cube),
+            }
+        }
+        "plane" => {
+            let material = lookup_material(fields, materials)?;
+            let plane = Plane::new(
+                fields.required_vec3("point")?.into(),
+                fields.vec3("normal")?.unwrap_or(Vec3::up()),
+                material,
+            )
+            .with_motion(fields.vec3("motion")?.unwrap_or(Vec3::zero()));
+            match parse_transform(fields, plane.center())? {
+                Some(transform) => scene.objects.add(Instance::new(Box::new(plane), transform)),
+                None => scene.add_plane(plane),
+            }
+        }
+        "light" => scene.add_light(Light::new(
+            fields.required_vec3("position")?.into(),
+            fields.vec3("color")?.map_or(Color::white(), Color::from),
+            fields.float("intensity")?.unwrap_or(1.0),
+        )),
+        "camera" => *camera = Some(parse_camera(fields, aspect_ratio)?),
+        _ => return Err(format!("unknown entry '{}'", keyword)),
+    }
+
+    Ok(())
+}
+
+fn parse_material(fields: &mut Fields) -> Result<Material, String> {
+    let mut material = Material::new();
+
+    if let Some(color) = fields.vec3("color")? {
+        material = material.with_color(color.into());
+    }
+    if let Some(values) = fields.floats("checker")? {
+        if values.len() != 7 {
+            return Err("checker expects scale,r1,g1,b1,r2,g2,b2".to_string());
+        }
+        material = material.with_checkerboard(
+            values[0],
+            Color::new(values[1], values[2], values[3]),
+            Color::new(values[4], values[5], values[6]),
+        );
+    }
+    if let Some(specular) = fields.float("specular")? {
+        material = material.with_specular(specular);
+    }
+    if let Some(roughness) = fields.float("roughness")? {
+        material = material.with_roughness(roughness);
+    }
+    if let Some(reflectivity) = fields.float("reflectivity")? {
+        material = material.with_reflectivity(reflectivity);
+    }
+    if let Some(emission) = fields.vec3("emission")? {
+        material.emission = emission.into();
+    }
+
+    Ok(material)
+}
+
+/// Optional translate/rotate/scale fields, pivoting around the object's center
+fn parse_transform(fields: &mut Fields, pivot: Point3) -> Result<Option<Transform>, String> {
+    let translation = fields.vec3("translate")?;
+    let rotation = fields.vec3("rotate")?;
+    let scale = fields.vec3("scale")?;
+
+    if translation.is_none() && rotation.is_none() && scale.is_none() {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        Transform::identity()
+            .with_pivot(pivot)
+            .with_translation(translation.unwrap_or(Vec3::zero()))
+            .with_rotation(rotation.unwrap_or(Vec3::zero()))
+            .with_scale(scale.unwrap_or(Vec3::new(1.0, 1.0, 1.0))),
+    ))
+}
+
+fn parse_environment(keyword: &str, fields: &mut Fields, base_dir: &Path) -> Result<Environment, String> {
+    let environment = match keyword {
+        "gradient" => Environment::Gradient {
+            zenith: fields.required_vec3("zenith")?,
+            horizon: fields.required_vec3("horizon")?,
+            ground: fields.vec3("ground")?.unwrap_or(Vec3::new(0.3, 0.3, 0.3)),
+        },
+        "sky" => {
+            let mut sky = Sky::new(fields.required_vec3("sun")?, fields.float("turbidity")?.unwrap_or(3.0))
+                .with_intensity(fields.float("intensity")?.unwrap_or(1.0));
+            if let Some(ground) = fields.vec3("ground")? {
+                sky = sky.with_ground(ground);
+            }
+            Environment::Sky(sky)
+        }
+        _ => {
+            let path = base_dir.join(fields.required_str("path")?);
+            let map = EnvironmentMap::load(&path.to_string_lossy())?
+                .with_rotation(fields.float("rotation")?.unwrap_or(0.0))
+                .with_intensity(fields.float("intensity")?.unwrap_or(1.0));
+            Environment::Map(map)
+        }
+    };
+
+    Ok(environment)
+}
+
+fn parse_tone_mapping(fields: &mut Fields) -> Result<ToneMapping, String> {
+    let mut tone_mapping = ToneMapping::new();
+
+    if let Some(name) = fields.str("mapper") {
+        let mapper = ToneMapper::from_name(&name).ok_or_else(|| format!("unknown tone mapper '{}'", name))?;
+        tone_mapping = tone_mapping.with_tone_mapper(mapper);
+    }
+    if let Some(exposure) = fields.float("exposure")? {
+        tone_mapping = tone_mapping.with_exposure(exposure);
+    }
+    if let Some(srgb) = fields.bool("srgb")? {
+        tone_mapping = tone_mapping.with_srgb(srgb);
+    }
+    if let Some(dither) = fields.bool("dither")? {
+        tone_mapping = tone_mapping.with_dither(dither);
+    }
+
+    Ok(tone_mapping)
+}
+
+fn parse_animation_header(fields: &mut Fields) -> Result<Animation, String> {
+    Ok(Animation::new(
+        fields.required_f32("duration")?,
+        fields.float("fps")?.unwrap_or(24.0),
+    ))
+}
+
+fn parse_key(fields: &mut Fields, animation: &mut Animation) -> Result<(), String> {
+    let name = fields.required_str("property")?;
+    let property = Property::from_name(&name).ok_or_else(|| format!("unknown property '{}'", name))?;
+    let time = fields.required_f32("time")?;
+
+    let interpolation = match fields.str("interpolation") {
+        Some(name) => Some(Interpolation::from_name(&name).ok_or_else(|| format!("unknown interpolation '{}'", name))?),
+        None => None,
+    };
+
+    match property {
+        Property::Vector(property) => {
+            let value = fields.required_vec3("value")?;
+            let track = animation.vector_track(property, interpolation.unwrap_or(Interpolation::Linear));
+            if let Some(interpolation) = interpolation {
+                track.interpolation = interpolation;
+            }
+            track.add_key(time, value);
+        }
+        Property::Scalar(property) => {
+            let value = fields.required_f32("value")?;
+            let track = animation.scalar_track(property, interpolation.unwrap_or(Interpolation::Linear));
+            if let Some(interpolation) = interpolation {
+                track.interpolation = interpolation;
+            }
+            track.add_key(time, value);
+        }
+    }
+
+    Ok(())
+}
+
+fn lookup_material(fields: &mut Fields, materials: &HashMap<String, Material>) -> Result<Material, String> {
+    match fields.str("material") {
+        Some(name) => materials
+            .get(&name)
+            .cloned()
+            .ok_or_else(|| format!("unknown material '{}'", name)),
+        None => Ok(Material::new()),
+    }
+}
+
+/// The files a scene refers to (environment maps), as written in the scene text, so they
+/// can be shipped along with it. Lines that don't parse are skipped; `parse` reports them.
+pub fn referenced_files(text: &str) -> Vec<String> {
+    text.lines()
+        .filter_map(|line| {
+            let mut tokens = line.split('#').next().unwrap_or("").split_whitespace();
+            (tokens.next() == Some("environment")).then(|| Fields::parse(tokens).ok()?.str("path"))?
+        })
+        .collect()
+}
+
+/// Parses a camera entry, with or without the leading `camera`, in the form
+/// `camera_to_line` prints
+pub fn parse_camera_line(line: &str, aspect_ratio: Float) -> Result<Camera, String> {
+    let fields = line.trim().strip_prefix("camera").unwrap_or(line);
+    let mut fields = Fields::parse(fields.split_whitespace())?;
+    let camera = parse_camera(&mut fields, aspect_ratio)?;
+    fields.finish()?;
+    Ok(camera)
+}
+
+fn parse_camera(fields: &mut Fields, aspect_ratio: Float) -> Result<Camera, String> {
+    // The view direction comes from `target`, or from `yaw`/`pitch` in degrees
+    let position = Point3::from(fields.required_vec3("position")?);
+    let yaw = fields.float("yaw")?;
+    let pitch = fields.float("pitch")?;
+    let target = match fields.vec3("target")? {
+        Some(target) => target.into(),
+        None if yaw.is_some() || pitch.is_some() => position + Vec3::new(0.0, 0.0, -1.0),
+        None => return Err("camera needs 'target' or 'yaw'/'pitch'".to_string()),
+    };
+
+    let fov = fields.float("fov")?.unwrap_or(45.0);
+    let mut camera = Camera::new(position, target, fields.vec3("up")?.unwrap_or(Vec3::up()), fov, aspect_ratio);
+
+    if let Some(yaw) = yaw {
+        camera.set_yaw(yaw);
+    }
+    if let Some(pitch) = pitch {
+        camera.set_pitch(pitch);
+    }
+
+    if let Some(roll) = fields.float("roll")? {
+        camera.set_roll(roll);
+    }
+
+    if let Some(name) = fields.str("projection") {
+        let projection = Projection::from_name(&name).ok_or_else(|| format!("unknown projection '{}'", name))?;
+        camera = camera.with_projection(projection);
+        // Again now that the projection is known: a fisheye may see wider than a perspective
+        camera.set_fov(fov);
+    }
+
+    let aperture = fields.float("aperture")?.unwrap_or(0.0);
+    let focus_distance = fields.float("focus_distance")?.unwrap_or(camera.focus_distance);
+    camera = camera.with_aperture(aperture, focus_distance);
+
+    if let Some(blades) = fields.u32("blades")? {
+        camera = camera.with_aperture_blades(blades, fields.float("blade_rotation")?.unwrap_or(0.0));
+    }
+
+    if let Some(shutter) = fields.floats("shutter")? {
+        if shutter.len() != 2 {
+            return Err("shutter expects open,close".to_string());
+        }
+        camera = camera.with_shutter(shutter[0], shutter[1]);
+    }
+
+    Ok(camera)
+}
+
+/// Formats a camera as a scene file line, e.g. to save a viewpoint found in the viewer
+pub fn camera_to_line(camera: &Camera) -> String {
+    let mut line = format!(
+        "camera position={} target={} up={} fov={} projection={}",
+        vec3_field(camera.position().to_vec3()),
+        vec3_field(camera.target().to_vec3()),
+        vec3_field(camera.up()),
+        camera.fov(),
+        camera.projection.name(),
+    );
+
+    if camera.roll() != 0.0 {
+        line += &format!(" roll={}", camera.roll());
+    }
+
+    if camera.aperture > 0.0 {
+        line += &format!(" aperture={} focus_distance={}", camera.aperture, camera.focus_distance);
+        if camera.aperture_blades > 0 {
+            line += &format!(" blades={} blade_rotation={}", camera.aperture_blades, camera.aperture_rotation);
+        }
+    }
+
+    if camera.has_motion_blur() {
+        line += &format!(" shutter={},{}", camera.shutter_open, camera.shutter_close);
+    }
+
+    line
+}
+
+fn vec3_field(v: Vec3) -> String {
+    format!("{},{},{}", v.x, v.y, v.z)
+}
+
+/// The key=value fields of one line. Every field must be used, so typos are reported.
+struct Fields {
+    values: HashMap<String, String>,
+}
+
+impl Fields {
+    fn parse<'a>(tokens: impl Iterator<Item = &'a str>) -> Result<Self, String> {
+        let mut values = HashMap::new();
+
+        for token in tokens {
+            let (key, value) = token
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, found '{}'", token))?;
+            values.insert(key.to_string(), value.to_string());
+        }
+
+        Ok(Fields { values })
+    }
+
+    fn str(&mut self, key: &str) -> Option<String> {
+        self.values.remove(key)
+    }
+
+    fn required_str(&mut self, key: &str) -> Result<String, String> {
+        self.str(key).ok_or_else(|| format!("missing '{}'", key))
+    }
+
+    fn floats(&mut self, key: &str) -> Result<Option<Vec<Float>>, String> {
+        match self.values.remove(key) {
+            Some(value) => value
+                .split(',')
+                .map(|part| part.trim().parse::<Float>().map_err(|_| format!("invalid number in {}={}", key, value)))
+                .collect::<Result<Vec<Float>, String>>()
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn float(&mut self, key: &str) -> Result<Option<Float>, String> {
+        match self.floats(key)? {
+            Some(values) if values.len() == 1 => Ok(Some(values[0])),
+            Some(_) => Err(format!("'{}' expects a single number", key)),
+            None => Ok(None),
+        }
+    }
+
+    fn required_f32(&mut self, key: &str) -> Result<Float, String> {
+        self.float(key)?.ok_or_else(|| format!("missing '{}'", key))
+    }
+
+    fn u32(&mut self, key: &str) -> Result<Option<u32>, String> {
+        match self.values.remove(key) {
+            Some(value) => value.parse().map(Some).map_err(|_| format!("invalid integer {}={}", key, value)),
+            None => Ok(None),
+        }
+    }
+
+    fn bool(&mut self, key: &str) -> Result<Option<bool>, String> {
+        match self.values.remove(key).as_deref() {
+            Some("true" | "on" | "1") => Ok(Some(true)),
+            Some("false" | "off" | "0") => Ok(Some(false)),
+            Some(value) => Err(format!("invalid boolean {}={}", key, value)),
+            None => Ok(None),
+        }
+    }
+
+    fn vec3(&mut self, key: &str) -> Result<Option<Vec3>, String> {
+        match self.floats(key)? {
+            Some(values) if values.len() == 3 => Ok(Some(Vec3::new(values[0], values[1], values[2]))),
+            Some(_) => Err(format!("'{}' expects x,y,z", key)),
+            None => Ok(None),
+        }
+    }
+
+    fn required_vec3(&mut self, key: &str) -> Result<Vec3, String> {
+        self.vec3(key)?.ok_or_else(|| format!("missing '{}'", key))
+    }
+
+    fn finish(&self) -> Result<(), String> {
+        match self.values.keys().next() {
+            Some(key) => Err(format!("unknown field '{}'", key)),
+            None => Ok(()),
+        }
+    }
+}
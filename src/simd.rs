@@ -0,0 +1,182 @@
+// Four scalar lanes: SSE on x86_64, plain arrays elsewhere, with the `scalar-math` feature,
+// or with the `f64` feature
+
+use crate::vec3::Float;
+
+#[cfg(all(target_arch = "x86_64", not(feature = "scalar-math"), not(feature = "f64")))]
+use std::arch::x86_64::*;
+
+/// Four `Float` values operated on together. `min` and `max` follow SSE and return `other`'s
+/// lane when either lane is NaN, on every backend. Comparisons return masks: lanes with
+/// every bit set where true and zero where false, for use with `select` and `&`/`|`.
+#[cfg(all(target_arch = "x86_64", not(feature = "scalar-math"), not(feature = "f64")))]
+#[derive(Clone, Copy)]
+pub struct FloatX4(__m128);
+
+#[cfg(not(all(target_arch = "x86_64", not(feature = "scalar-math"), not(feature = "f64"))))]
+#[derive(Clone, Copy)]
+pub struct FloatX4([Float; 4]);
+
+// SSE2 is part of the x86_64 baseline, so these intrinsics are always available there
+#[cfg(all(target_arch = "x86_64", not(feature = "scalar-math"), not(feature = "f64")))]
+impl FloatX4 {
+    pub fn splat(value: Float) -> Self {
+        unsafe { FloatX4(_mm_set1_ps(value)) }
+    }
+
+    pub fn from_array(lanes: [Float; 4]) -> Self {
+        unsafe { FloatX4(_mm_loadu_ps(lanes.as_ptr())) }
+    }
+
+    pub fn to_array(self) -> [Float; 4] {
+        let mut lanes = [0.0; 4];
+        unsafe { _mm_storeu_ps(lanes.as_mut_ptr(), self.0) };
+        lanes
+    }
+
+    pub fn min(self, other: FloatX4) -> FloatX4 {
+        unsafe { FloatX4(_mm_min_ps(self.0, other.0)) }
+    }
+
+    pub fn max(self, other: FloatX4) -> FloatX4 {
+        unsafe { FloatX4(_mm_max_ps(self.0, other.0)) }
+    }
+
+    pub fn abs(self) -> FloatX4 {
+        unsafe { FloatX4(_mm_andnot_ps(_mm_set1_ps(-0.0), self.0)) }
+    }
+
+    pub fn lt(self, other: FloatX4) -> FloatX4 {
+        unsafe { FloatX4(_mm_cmplt_ps(self.0, other.0)) }
+    }
+
+    /// Mask lanes of self that are not set in `other`
+    pub fn and_not(self, other: FloatX4) -> FloatX4 {
+        unsafe { FloatX4(_mm_andnot_ps(other.0, self.0)) }
+    }
+
+    /// Bit `i` set where lane `i` of a mask is set
+    pub fn bitmask(self) -> u32 {
+        unsafe { _mm_movemask_ps(self.0) as u32 }
+    }
+
+    /// Lanes of `if_true` where `mask` is set, else lanes of `if_false`
+    pub fn select(mask: FloatX4, if_true: FloatX4, if_false: FloatX4) -> FloatX4 {
+        unsafe { FloatX4(_mm_or_ps(_mm_and_ps(mask.0, if_true.0), _mm_andnot_ps(mask.0, if_false.0))) }
+    }
+}
+
+#[cfg(not(all(target_arch = "x86_64", not(feature = "scalar-math"), not(feature = "f64"))))]
+impl FloatX4 {
+    pub fn splat(value: Float) -> Self {
+        FloatX4([value; 4])
+    }
+
+    pub fn from_array(lanes: [Float; 4]) -> Self {
+        FloatX4(lanes)
+    }
+
+    pub fn to_array(self) -> [Float; 4] {
+        self.0
+    }
+
+    pub fn min(self, other: FloatX4) -> FloatX4 {
+        FloatX4(std::array::from_fn(|i| if self.0[i] < other.0[i] { self.0[i] } else { other.0[i] }))
+    }
+
+    pub fn max(self, other: FloatX4) -> FloatX4 {
+        FloatX4(std::array::from_fn(|i| if self.0[i] > other.0[i] { self.0[i] } else { other.0[i] }))
+    }
+
+    pub fn abs(self) -> FloatX4 {
+        FloatX4(self.0.map(Float::abs))
+    }
+
+    pub fn lt(self, other: FloatX4) -> FloatX4 {
+        FloatX4(std::array::from_fn(|i| mask_lane(self.0[i] < other.0[i])))
+    }
+
+    /// Mask lanes of self that are not set in `other`
+    pub fn and_not(self, other: FloatX4) -> FloatX4 {
+        FloatX4(std::array::from_fn(|i| Float::from_bits(self.0[i].to_bits() & !other.0[i].to_bits())))
+    }
+
+    /// Bit `i` set where lane `i` of a mask is set
+    pub fn bitmask(self) -> u32 {
+        (0..4).filter(|&i| self.0[i].to_bits() != 0).fold(0, |mask, i| mask | 1 << i)
+    }
+
+    /// Lanes of `if_true` where `mask` is set, else lanes of `if_false`
+    pub fn select(mask: FloatX4, if_true: FloatX4, if_false: FloatX4) -> FloatX4 {
+        FloatX4(std::array::from_fn(|i| if mask.0[i].to_bits() != 0 { if_true.0[i] } else { if_false.0[i] }))
+    }
+}
+
+#[cfg(not(all(target_arch = "x86_64", not(feature = "scalar-math"), not(feature = "f64"))))]
+fn mask_lane(set: bool) -> Float {
+    Float::from_bits(if set { !0 } else { 0 })
+}
+
+macro_rules! lane_op {
+    ($trait:ident, $method:ident, $intrinsic:ident, $op:tt) => {
+        impl std::ops::$trait for FloatX4 {
+            type Output = FloatX4;
+
+            #[cfg(all(target_arch = "x86_64", not(feature = "scalar-math"), not(feature = "f64")))]
+            fn $method(self, other: FloatX4) -> FloatX4 {
+                unsafe { FloatX4($intrinsic(self.0, other.0)) }
+            }
+
+            #[cfg(not(all(target_arch = "x86_64", not(feature = "scalar-math"), not(feature = "f64"))))]
+            fn $method(self, other: FloatX4) -> FloatX4 {
+                FloatX4(std::array::from_fn(|i| self.0[i] $op other.0[i]))
+            }
+        }
+    };
+}
+
+lane_op!(Add, add, _mm_add_ps, +);
+lane_op!(Sub, sub, _mm_sub_ps, -);
+lane_op!(Mul, mul, _mm_mul_ps, *);
+lane_op!(Div, div, _mm_div_ps, /);
+
+macro_rules! mask_op {
+    ($trait:ident, $method:ident, $intrinsic:ident, $op:tt) => {
+        impl std::ops::$trait for FloatX4 {
+            type Output = FloatX4;
+
+            #[cfg(all(target_arch = "x86_64", not(feature = "scalar-math"), not(feature = "f64")))]
+            fn $method(self, other: FloatX4) -> FloatX4 {
+                unsafe { FloatX4($intrinsic(self.0, other.0)) }
+            }
+
+            #[cfg(not(all(target_arch = "x86_64", not(feature = "scalar-math"), not(feature = "f64"))))]
+            fn $method(self, other: FloatX4) -> FloatX4 {
+                FloatX4(std::array::from_fn(|i| Float::from_bits(self.0[i].to_bits() $op other.0[i].to_bits())))
+            }
+        }
+    };
+}
+
+mask_op!(BitAnd, bitand, _mm_and_ps, &);
+mask_op!(BitOr, bitor, _mm_or_ps, |);
+
+impl std::ops::Neg for FloatX4 {
+    type Output = FloatX4;
+
+    #[cfg(all(target_arch = "x86_64", not(feature = "scalar-math"), not(feature = "f64")))]
+    fn neg(self) -> FloatX4 {
+        unsafe { FloatX4(_mm_xor_ps(self.0, _mm_set1_ps(-0.0))) }
+    }
+
+    #[cfg(not(all(target_arch = "x86_64", not(feature = "scalar-math"), not(feature = "f64"))))]
+    fn neg(self) -> FloatX4 {
+        FloatX4(self.0.map(|lane| -lane))
+    }
+}
+
+impl std::fmt::Debug for FloatX4 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("FloatX4").field(&self.to_array()).finish()
+    }
+}
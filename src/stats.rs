@@ -0,0 +1,290 @@
+// Render statistics: ray and intersection counts gathered per thread while tracing, and
+// optional timing of the intersection queries
+
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+/// Things counted while rendering
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Counter {
+    PrimaryRays,
+    ShadowRays,
+    ReflectionRays,
+    OcclusionRays,
+    CubeTests,
+    PlaneTests,
+}
+
+impl Counter {
+    pub const ALL: [Counter; 6] = [
+        Counter::PrimaryRays,
+        Counter::ShadowRays,
+        Counter::ReflectionRays,
+        Counter::OcclusionRays,
+        Counter::CubeTests,
+        Counter::PlaneTests,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Counter::PrimaryRays => "primary_rays",
+            Counter::ShadowRays => "shadow_rays",
+            Counter::ReflectionRays => "reflection_rays",
+            Counter::OcclusionRays => "occlusion_rays",
+            Counter::CubeTests => "cube_tests",
+            Counter::PlaneTests => "plane_tests",
+        }
+    }
+}
+
+thread_local! {
+    static COUNTS: [Cell<u64>; Counter::ALL.len()] = const { [const { Cell::new(0) }; Counter::ALL.len()] };
+    static TIMING: Cell<bool> = const { Cell::new(false) };
+    static TRACE_TIME: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+}
+
+pub fn count(counter: Counter) {
+    add(counter, 1);
+}
+
+pub fn add(counter: Counter, amount: u64) {
+    COUNTS.with(|counts| {
+        let count = &counts[counter as usize];
+        count.set(count.get() + amount);
+    });
+}
+
+fn totals() -> [u64; Counter::ALL.len()] {
+    COUNTS.with(|counts| std::array::from_fn(|i| counts[i].get()))
+}
+
+/// Primitive intersection tests run on this thread so far. Take differences to count the
+/// tests of one piece of work.
+pub fn intersection_tests() -> u64 {
+    let totals = totals();
+    totals[Counter::CubeTests as usize] + totals[Counter::PlaneTests as usize]
+}
+
+/// Runs an intersection query, adding its duration to the tracing time while a
+/// `Recorder` with timing is active. Untimed queries pay only for a flag check.
+pub fn trace<T>(query: impl FnOnce() -> T) -> T {
+    if !TIMING.with(Cell::get) {
+        return query();
+    }
+
+    let start = Instant::now();
+    let result = query();
+    TRACE_TIME.with(|time| time.set(time.get() + start.elapsed()));
+    result
+}
+
+/// Collects the statistics of one render on the current thread
+pub struct Recorder {
+    start: Instant,
+    counts: [u64; Counter::ALL.len()],
+    trace_time: Duration,
+    timing: bool,
+}
+
+impl Recorder {
+    /// With `timing`, every intersection query is timed, which costs two clock reads each
+    /// and slows the render somewhat
+    pub fn start(timing: bool) -> Self {
+        TIMING.with(|flag| flag.set(timing));
+        Recorder {
+            start: Instant::now(),
+            counts: totals(),
+            trace_time: TRACE_TIME.with(Cell::get),
+            timing,
+        }
+    }
+
+    /// Statistics since `start`. `wall_time` equals `render_time` until the caller adds
+    /// whatever follows, such as denoising.
+    pub fn finish(self) -> RenderStats {
+        TIMING.with(|flag| flag.set(false));
+        let elapsed = self.start.elapsed();
+        let totals = totals();
+        let count = |counter: Counter| totals[counter as usize] - self.counts[counter as usize];
+        let trace_time = self.timing.then(|| TRACE_TIME.with(Cell::get) - self.trace_time);
+
+        RenderStats {
+            wall_time: elapsed,
+            render_time: elapsed,
+            trace_time,
+            shade_time: trace_time.map(|trace_time| elapsed.saturating_sub(trace_time)),
+            primary_rays: count(Counter::PrimaryRays),
+            shadow_rays: count(Counter::ShadowRays),
+            reflection_rays: count(Counter::ReflectionRays),
+            occlusion_rays: count(Counter::OcclusionRays),
+            cube_tests: count(Counter::CubeTests),
+            plane_tests: count(Counter::PlaneTests),
+        }
+    }
+}
+
+/// How `RenderStats` are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsFormat {
+    Table,
+    Json,
+}
+
+impl StatsFormat {
+    pub const ALL: [StatsFormat; 2] = [StatsFormat::Table, StatsFormat::Json];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StatsFormat::Table => "table",
+            StatsFormat::Json => "json",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<StatsFormat> {
+        StatsFormat::ALL.iter().copied().find(|format| format.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RenderStats {
+    /// The whole render, including post-processing such as denoising
+    pub wall_time: Duration,
+    /// Tracing and shading the pixels
+    pub render_time: Duration,
+    /// Time spent in intersection queries, added up over all render threads; only measured
+    /// with timing on
+    pub trace_time: Option<Duration>,
+    /// Time spent on everything else while rendering pixels, such as lighting, texturing and
+    /// sampling, added up over all render threads
+    pub shade_time: Option<Duration>,
+    pub primary_rays: u64,
+    pub shadow_rays: u64,
+    pub reflection_rays: u64,
+    pub occlusion_rays: u64,
+    pub cube_tests: u64,
+    pub plane_tests: u64,
+}
+
+impl RenderStats {
+    /// Adds the counts and thread times of another part of the same render, such as the
+    /// work of another thread. The wall-clock times are left alone.
+    pub fn add(&mut self, other: &RenderStats) {
+        let sum = |a: Option<Duration>, b: Option<Duration>| match (a, b) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
+        self.trace_time = sum(self.trace_time, other.trace_time);
+        self.shade_time = sum(self.shade_time, other.shade_time);
+        self.primary_rays += other.primary_rays;
+        self.shadow_rays += other.shadow_rays;
+        self.reflection_rays += other.reflection_rays;
+        self.occlusion_rays += other.occlusion_rays;
+        self.cube_tests += other.cube_tests;
+        self.plane_tests += other.plane_tests;
+    }
+
+    pub fn count(&self, counter: Counter) -> u64 {
+        match counter {
+            Counter::PrimaryRays => self.primary_rays,
+            Counter::ShadowRays => self.shadow_rays,
+            Counter::ReflectionRays => self.reflection_rays,
+            Counter::OcclusionRays => self.occlusion_rays,
+            Counter::CubeTests => self.cube_tests,
+            Counter::PlaneTests => self.plane_tests,
+        }
+    }
+
+    pub fn set_count(&mut self, counter: Counter, value: u64) {
+        match counter {
+            Counter::PrimaryRays => self.primary_rays = value,
+            Counter::ShadowRays => self.shadow_rays = value,
+            Counter::ReflectionRays => self.reflection_rays = value,
+            Counter::OcclusionRays => self.occlusion_rays = value,
+            Counter::CubeTests => self.cube_tests = value,
+            Counter::PlaneTests => self.plane_tests = value,
+        }
+    }
+
+    pub fn total_rays(&self) -> u64 {
+        self.primary_rays + self.shadow_rays + self.reflection_rays + self.occlusion_rays
+    }
+
+    /// Mean number of surfaces a camera path visits, counting the camera ray as depth 1.
+    /// Each hit spawns at most one reflection ray, so this is one plus reflections per
+    /// camera ray.
+    pub fn average_depth(&self) -> f64 {
+        if self.primary_rays == 0 {
+            return 0.0;
+        }
+        1.0 + self.reflection_rays as f64 / self.primary_rays as f64
+    }
+
+    pub fn format(&self, format: StatsFormat) -> String {
+        match format {
+            StatsFormat::Table => self.to_table(),
+            StatsFormat::Json => self.to_json(),
+        }
+    }
+
+    /// Times with their share of the wall time; tracing and shading as shares of the thread
+    /// time they add up to
+    pub fn to_table(&self) -> String {
+        let mut lines = Vec::new();
+        let mut time = |name: &str, duration: Option<Duration>, whole: Option<Duration>| {
+            let value = duration.map_or("-".to_string(), |duration| format!("{:.2} ms", duration.as_secs_f64() * 1000.0));
+            let share = match (duration, whole.filter(|whole| !whole.is_zero())) {
+                (Some(duration), Some(whole)) => format!("{:>6.1}%", duration.as_secs_f64() / whole.as_secs_f64() * 100.0),
+                _ => String::new(),
+            };
+            lines.push(format!("{:<18} {:>14} {}", name, value, share));
+        };
+        let thread_time = self.trace_time.zip(self.shade_time).map(|(trace, shade)| trace + shade);
+        time("Wall time", Some(self.wall_time), Some(self.wall_time));
+        time("Render", Some(self.render_time), Some(self.wall_time));
+        time("  Tracing", self.trace_time, thread_time);
+        time("  Shading", self.shade_time, thread_time);
+
+        let total_rays = self.total_rays();
+        let mut rays = |name: &str, count: u64| {
+            let share = count as f64 / total_rays.max(1) as f64 * 100.0;
+            lines.push(format!("{:<18} {:>14} {:>6.1}%", name, count, share));
+        };
+        rays("Primary rays", self.primary_rays);
+        rays("Shadow rays", self.shadow_rays);
+        rays("Reflection rays", self.reflection_rays);
+        rays("Occlusion rays", self.occlusion_rays);
+
+        let seconds = self.render_time.as_secs_f64();
+        let rays_per_second = if seconds > 0.0 { total_rays as f64 / seconds } else { 0.0 };
+        lines.push(format!("{:<18} {:>14} ({:.2} Mrays/s)", "Total rays", total_rays, rays_per_second / 1e6));
+        lines.push(format!("{:<18} {:>14}", "Cube tests", self.cube_tests));
+        lines.push(format!("{:<18} {:>14}", "Plane tests", self.plane_tests));
+        lines.push(format!("{:<18} {:>14.3}", "Average depth", self.average_depth()));
+
+        lines.join("\n")
+    }
+
+    /// One JSON object; times are in milliseconds and `null` when not measured
+    pub fn to_json(&self) -> String {
+        let millis = |duration: Option<Duration>| {
+            duration.map_or("null".to_string(), |duration| format!("{:.3}", duration.as_secs_f64() * 1000.0))
+        };
+
+        let fields = [
+            ("wall_time_ms", millis(Some(self.wall_time))),
+            ("render_time_ms", millis(Some(self.render_time))),
+            ("trace_time_ms", millis(self.trace_time)),
+            ("shade_time_ms", millis(self.shade_time)),
+            (Counter::PrimaryRays.name(), self.primary_rays.to_string()),
+            (Counter::ShadowRays.name(), self.shadow_rays.to_string()),
+            (Counter::ReflectionRays.name(), self.reflection_rays.to_string()),
+            (Counter::OcclusionRays.name(), self.occlusion_rays.to_string()),
+            (Counter::CubeTests.name(), self.cube_tests.to_string()),
+            (Counter::PlaneTests.name(), self.plane_tests.to_string()),
+            ("average_depth", format!("{:.4}", self.average_depth())),
+        ];
+
+        let body: Vec<String> = fields.iter().map(|(name, value)| format!("  \"{}\": {}", name, value)).collect();
+        format!("{{\n{}\n}}", body.join(",\n"))
+    }
+}
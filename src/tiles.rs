@@ -0,0 +1,156 @@
+// Render regions and tiles: the part of the image to render, and the order the tiles of
+// that part are handed out in
+
+use crate::sampling::{self, Rng};
+use crate::checkpoint::{Fingerprint, Fingerprinted};
+use crate::vec3::Float;
+
+/// A rectangle of pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Rect { x, y, width, height }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    pub fn area(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+
+    /// The overlap of both rectangles, empty if they don't overlap
+    pub fn intersect(&self, other: &Rect) -> Rect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        Rect::new(x, y, right.saturating_sub(x), bottom.saturating_sub(y))
+    }
+
+    /// Splits the rectangle into `size`-pixel squares, smaller along the right and bottom
+    /// edges, in `order`
+    pub fn tiles(&self, size: u32, order: TileOrder, seed: u64) -> Vec<Rect> {
+        let size = size.max(1);
+        let mut tiles = Vec::new();
+        for y in (self.y..self.y + self.height).step_by(size as usize) {
+            for x in (self.x..self.x + self.width).step_by(size as usize) {
+                tiles.push(Rect::new(x, y, size, size).intersect(self));
+            }
+        }
+
+        match order {
+            TileOrder::Scanline => {}
+            TileOrder::Center => {
+                let center_x = self.x as Float + self.width as Float / 2.0;
+                let center_y = self.y as Float + self.height as Float / 2.0;
+                let distance = |tile: &Rect| {
+                    let dx = tile.x as Float + tile.width as Float / 2.0 - center_x;
+                    let dy = tile.y as Float + tile.height as Float / 2.0 - center_y;
+                    dx * dx + dy * dy
+                };
+                // Stable, so equally distant tiles stay in scanline order
+                tiles.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+            }
+            TileOrder::Random => sampling::shuffle(&mut tiles, &mut Rng::new(seed)),
+        }
+
+        tiles
+    }
+}
+
+impl Fingerprinted for Rect {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add(&self.x).add(&self.y).add(&self.width).add(&self.height);
+    }
+}
+
+/// The part of the image to render. Pixels outside it are left black.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Crop {
+    Pixels(Rect),
+    /// Fractions of the image size from the top-left corner, from (`x0`, `y0`) to
+    /// (`x1`, `y1`); pixels whose centre falls inside are rendered
+    Normalized { x0: Float, y0: Float, x1: Float, y1: Float },
+}
+
+impl Crop {
+    /// Parses "x,y,width,height" in pixels
+    pub fn parse_pixels(text: &str) -> Result<Crop, String> {
+        let values = parse_list::<u32>(text, "x,y,width,height")?;
+        let rect = Rect::new(values[0], values[1], values[2], values[3]);
+        if rect.is_empty() {
+            return Err(format!("'{}' is an empty region", text));
+        }
+        Ok(Crop::Pixels(rect))
+    }
+
+    /// Parses "x0,y0,x1,y1" as fractions of the image size
+    pub fn parse_normalized(text: &str) -> Result<Crop, String> {
+        let values = parse_list::<Float>(text, "x0,y0,x1,y1")?;
+        let (x0, y0, x1, y1) = (values[0], values[1], values[2], values[3]);
+        if !values.iter().all(|value| (0.0..=1.0).contains(value)) || x0 >= x1 || y0 >= y1 {
+            return Err(format!("'{}' needs 0 <= x0 < x1 <= 1 and 0 <= y0 < y1 <= 1", text));
+        }
+        Ok(Crop::Normalized { x0, y0, x1, y1 })
+    }
+
+    /// The pixels of a `width` x `height` image inside the crop; empty if it lies outside
+    /// the image
+    pub fn rect(&self, width: u32, height: u32) -> Rect {
+        let image = Rect::new(0, 0, width, height);
+        match *self {
+            Crop::Pixels(rect) => rect.intersect(&image),
+            Crop::Normalized { x0, y0, x1, y1 } => {
+                let first = |fraction: Float, size: u32| (fraction * size as Float - 0.5).ceil().max(0.0) as u32;
+                let (x, y) = (first(x0, width), first(y0, height));
+                let (right, bottom) = (first(x1, width), first(y1, height));
+                Rect::new(x, y, right.saturating_sub(x), bottom.saturating_sub(y)).intersect(&image)
+            }
+        }
+    }
+}
+
+fn parse_list<T: std::str::FromStr>(text: &str, expected: &str) -> Result<[T; 4], String> {
+    let values: Vec<T> = text
+        .split(',')
+        .map(|value| value.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("'{}' is not {}", text, expected))?;
+    values.try_into().map_err(|_| format!("'{}' is not {}", text, expected))
+}
+
+/// The order tiles are started in. With several threads, tiles finish roughly in this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileOrder {
+    /// Rows of tiles from the top, left to right
+    Scanline,
+    /// Nearest the centre of the region first, for a quick look at the subject
+    Center,
+    /// Shuffled by the render seed, for an early impression of the whole region
+    Random,
+}
+
+impl TileOrder {
+    pub const ALL: [TileOrder; 3] = [TileOrder::Scanline, TileOrder::Center, TileOrder::Random];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TileOrder::Scanline => "scanline",
+            TileOrder::Center => "center",
+            TileOrder::Random => "random",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<TileOrder> {
+        TileOrder::ALL.iter().copied().find(|order| order.name() == name)
+    }
+}
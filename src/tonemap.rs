@@ -0,0 +1,253 @@
+// Display transform: exposure, tone mapping, sRGB encoding and dithered 8-bit quantization
+
+use crate::vec3::{Float, Vec3};
+use crate::sampling::Rng;
+use crate::output::Rgba8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMapper {
+    /// No curve; anything above 1 clips
+    Clamp,
+    /// x / (1 + x) per channel
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic reference rendering transform
+    Aces,
+    /// Minimal AgX with the default contrast curve; desaturates bright colors toward white
+    Agx,
+    /// John Hable's filmic curve from Uncharted 2, with a white point of 11.2
+    Uncharted2,
+}
+
+impl ToneMapper {
+    pub const ALL: [ToneMapper; 5] = [
+        ToneMapper::Clamp,
+        ToneMapper::Reinhard,
+        ToneMapper::Aces,
+        ToneMapper::Agx,
+        ToneMapper::Uncharted2,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ToneMapper::Clamp => "clamp",
+            ToneMapper::Reinhard => "reinhard",
+            ToneMapper::Aces => "aces",
+            ToneMapper::Agx => "agx",
+            ToneMapper::Uncharted2 => "uncharted2",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ToneMapper> {
+        ToneMapper::ALL.iter().copied().find(|mapper| mapper.name() == name)
+    }
+
+    /// Maps linear scene radiance to linear display values in [0, 1]
+    pub fn map(&self, color: Vec3) -> Vec3 {
+        let color = color.max(&Vec3::zero());
+
+        match self {
+            ToneMapper::Clamp => color,
+            ToneMapper::Reinhard => per_channel(color, |x| x / (1.0 + x)),
+            ToneMapper::Aces => per_channel(color, |x| (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)),
+            ToneMapper::Agx => agx(color),
+            ToneMapper::Uncharted2 => {
+                let white_scale = 1.0 / hable(11.2);
+                per_channel(color, |x| hable(x * 2.0) * white_scale)
+            }
+        }
+        .clamp(0.0, 1.0)
+    }
+}
+
+/// How linear radiance becomes 8-bit pixels
+#[derive(Debug, Clone, Copy)]
+pub struct ToneMapping {
+    /// Exposure adjustment in stops; +1 doubles the brightness
+    pub exposure: Float,
+    pub tone_mapper: ToneMapper,
+    /// Encode with the sRGB transfer function; off writes linear values directly
+    pub srgb: bool,
+    /// Add triangular noise of +-1 code value before quantizing, to break up banding
+    pub dither: bool,
+}
+
+impl ToneMapping {
+    pub fn new() -> Self {
+        ToneMapping {
+            exposure: 0.0,
+            tone_mapper: ToneMapper::Aces,
+            srgb: true,
+            dither: true,
+        }
+    }
+
+    /// Clamps and quantizes only; for debug passes whose colors are already display values
+    pub fn none() -> Self {
+        ToneMapping {
+            exposure: 0.0,
+            tone_mapper: ToneMapper::Clamp,
+            srgb: false,
+            dither: false,
+        }
+    }
+
+    pub fn with_exposure(mut self, stops: Float) -> Self {
+        self.exposure = stops;
+        self
+    }
+
+    pub fn with_tone_mapper(mut self, tone_mapper: ToneMapper) -> Self {
+        self.tone_mapper = tone_mapper;
+        self
+    }
+
+    pub fn with_srgb(mut self, srgb: bool) -> Self {
+        self.srgb = srgb;
+        self
+    }
+
+    pub fn with_dither(mut self, dither: bool) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    pub fn apply(&self, image: &[Vec<Vec3>]) -> Vec<Vec<Rgba8>> {
+        image
+            .iter()
+            .enumerate()
+            .map(|(y, row)| {
+                row.iter()
+                    .enumerate()
+                    .map(|(x, &color)| self.map_pixel(color, x as u32, y as u32))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Display color for the linear radiance at pixel (x, y). The position only seeds the dither.
+    pub fn map_pixel(&self, color: Vec3, x: u32, y: u32) -> Rgba8 {
+        let mapped = self.tone_mapper.map(color * Float::powf(2.0, self.exposure));
+        let encoded = if self.srgb { per_channel(mapped, srgb_encode) } else { mapped };
+
+        let noise = if self.dither {
+            let mut rng = Rng::for_pixel(0x5EED_D17E, x, y);
+            rng.next_float() + rng.next_float() - 1.0
+        } else {
+            0.0
+        };
+
+        let quantize = |value: Float| (value * 255.0 + 0.5 + noise).clamp(0.0, 255.0) as u8;
+        Rgba8::new(quantize(encoded.x), quantize(encoded.y), quantize(encoded.z), 255)
+    }
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        ToneMapping::new()
+    }
+}
+
+fn per_channel(color: Vec3, f: impl Fn(Float) -> Float) -> Vec3 {
+    Vec3::new(f(color.x), f(color.y), f(color.z))
+}
+
+/// sRGB opto-electronic transfer function for a linear value in [0, 1]
+fn srgb_encode(linear: Float) -> Float {
+    if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn hable(x: Float) -> Float {
+    const A: Float = 0.15; // Shoulder strength
+    const B: Float = 0.50; // Linear strength
+    const C: Float = 0.10; // Linear angle
+    const D: Float = 0.20; // Toe strength
+    const E: Float = 0.02; // Toe numerator
+    const F: Float = 0.30; // Toe denominator
+
+    // ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F over a common
+    // denominator, so black stays exactly black
+    x * (F * (A * x + C * B) - E * (A * x + B)) / (F * (x * (A * x + B) + D * F))
+}
+
+/// AgX: inset into a smaller gamut, log encode, apply the sigmoid, then outset. The sigmoid
+/// output is display encoded, so it is linearized again for the sRGB step.
+fn agx(color: Vec3) -> Vec3 {
+    const MIN_EV: Float = -12.473_93;
+    const MAX_EV: Float = 4.026_069;
+
+    let inset = Vec3::new(
+        0.842_479 * color.x + 0.078_434 * color.y + 0.079_224 * color.z,
+        0.042_328 * color.x + 0.878_469 * color.y + 0.079_166 * color.z,
+        0.042_376 * color.x + 0.078_434 * color.y + 0.879_143 * color.z,
+    );
+
+    let curve = per_channel(inset, |x| {
+        let x = (x.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.002_32
+    });
+
+    let outset = Vec3::new(
+        1.196_879 * curve.x - 0.098_021 * curve.y - 0.099_030 * curve.z,
+        -0.052_897 * curve.x + 1.151_903 * curve.y - 0.098_961 * curve.z,
+        -0.052_972 * curve.x - 0.098_043 * curve.y + 1.151_074 * curve.z,
+    );
+
+    per_channel(outset, |x| x.max(0.0).powf(2.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey(value: Float) -> Vec3 {
+        Vec3::new(value, value, value)
+    }
+
+    #[test]
+    fn srgb_encoding_is_continuous_at_the_breakpoint() {
+        assert_eq!(srgb_encode(0.0), 0.0);
+        assert!((srgb_encode(1.0) - 1.0).abs() < 1e-6);
+
+        let breakpoint = 0.003_130_8;
+        let linear_side = srgb_encode(breakpoint);
+        let curve_side = srgb_encode(breakpoint + 1e-7);
+        assert!((linear_side - 0.040_45).abs() < 1e-5, "{}", linear_side);
+        assert!((curve_side - linear_side).abs() < 1e-5, "{} vs {}", curve_side, linear_side);
+        assert!((srgb_encode(0.5) - 0.735_357).abs() < 1e-5);
+    }
+
+    #[test]
+    fn mappers_are_monotonic_and_stay_in_range() {
+        for mapper in ToneMapper::ALL {
+            assert_eq!(mapper.map(Vec3::zero()), Vec3::zero(), "{}", mapper.name());
+            assert_eq!(mapper.map(grey(-1.0)), Vec3::zero(), "{}", mapper.name());
+
+            let mut previous = Vec3::zero();
+            for step in 1..=400 {
+                // Up to 2^12, past every mapper's white point
+                let mapped = mapper.map(grey(Float::powf(2.0, step as Float * 0.05 - 8.0)));
+                for (value, before) in [(mapped.x, previous.x), (mapped.y, previous.y), (mapped.z, previous.z)] {
+                    assert!((0.0..=1.0).contains(&value), "{} gives {:?}", mapper.name(), mapped);
+                    assert!(value >= before - 1e-6, "{} decreases to {:?} from {:?}", mapper.name(), mapped, previous);
+                }
+                previous = mapped;
+            }
+        }
+    }
+
+    #[test]
+    fn no_tone_mapping_keeps_8_bit_values() {
+        let tone_mapping = ToneMapping::none();
+        for code in 0..=255u8 {
+            let value = code as Float / 255.0;
+            let pixel = tone_mapping.map_pixel(Vec3::new(value, value, value), code as u32, 7);
+            assert_eq!(pixel, Rgba8::new(code, code, code, 255));
+        }
+    }
+}
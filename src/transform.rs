@@ -0,0 +1,112 @@
+// Object transforms: scale, then rotate (X, Y, Z in degrees) around a pivot, then translate
+
+use crate::vec3::{Float, Point3, Vec3};
+use crate::checkpoint::{Fingerprint, Fingerprinted};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Vec3,
+    pub scale: Vec3,
+    pub pivot: Point3,
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Transform {
+            translation: Vec3::zero(),
+            rotation: Vec3::zero(),
+            scale: Vec3::new(1.0, 1.0, 1.0),
+            pivot: Point3::origin(),
+        }
+    }
+
+    pub fn with_translation(mut self, translation: Vec3) -> Self {
+        self.translation = translation;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation_degrees: Vec3) -> Self {
+        self.rotation = rotation_degrees;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: Vec3) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// Point that rotation and scale are applied around, in object space
+    pub fn with_pivot(mut self, pivot: Point3) -> Self {
+        self.pivot = pivot;
+        self
+    }
+
+    pub fn point_to_world(&self, point: Point3) -> Point3 {
+        self.pivot + self.rotate(self.scale_vector(point - self.pivot)) + self.translation
+    }
+
+    pub fn point_to_local(&self, point: Point3) -> Point3 {
+        self.pivot + self.unscale_vector(self.inverse_rotate(point - self.pivot - self.translation))
+    }
+
+    pub fn vector_to_local(&self, vector: Vec3) -> Vec3 {
+        self.unscale_vector(self.inverse_rotate(vector))
+    }
+
+    /// Normals use the inverse transpose: inverse scale, same rotation
+    pub fn normal_to_world(&self, normal: Vec3) -> Vec3 {
+        self.rotate(self.unscale_vector(normal)).normalize()
+    }
+
+    /// Component-wise blend between two transforms
+    pub fn lerp(&self, other: &Transform, t: Float) -> Transform {
+        Transform {
+            translation: self.translation.lerp(&other.translation, t),
+            rotation: self.rotation.lerp(&other.rotation, t),
+            scale: self.scale.lerp(&other.scale, t),
+            pivot: self.pivot.lerp(&other.pivot, t),
+        }
+    }
+
+    fn scale_vector(&self, v: Vec3) -> Vec3 {
+        v * self.scale
+    }
+
+    fn unscale_vector(&self, v: Vec3) -> Vec3 {
+        Vec3::new(v.x / self.scale.x, v.y / self.scale.y, v.z / self.scale.z)
+    }
+
+    fn rotate(&self, v: Vec3) -> Vec3 {
+        let v = rotate_x(v, self.rotation.x.to_radians());
+        let v = rotate_y(v, self.rotation.y.to_radians());
+        rotate_z(v, self.rotation.z.to_radians())
+    }
+
+    fn inverse_rotate(&self, v: Vec3) -> Vec3 {
+        let v = rotate_z(v, -self.rotation.z.to_radians());
+        let v = rotate_y(v, -self.rotation.y.to_radians());
+        rotate_x(v, -self.rotation.x.to_radians())
+    }
+}
+
+impl Fingerprinted for Transform {
+    fn fingerprint(&self, fingerprint: &mut Fingerprint) {
+        fingerprint.add(&self.translation).add(&self.rotation).add(&self.scale).add(&self.pivot);
+    }
+}
+
+fn rotate_x(v: Vec3, angle: Float) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+    Vec3::new(v.x, v.y * cos - v.z * sin, v.y * sin + v.z * cos)
+}
+
+fn rotate_y(v: Vec3, angle: Float) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+    Vec3::new(v.x * cos + v.z * sin, v.y, -v.x * sin + v.z * cos)
+}
+
+fn rotate_z(v: Vec3, angle: Float) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+    Vec3::new(v.x * cos - v.y * sin, v.x * sin + v.y * cos, v.z)
+}
@@ -1,15 +1,52 @@
-// 3D Vector implementation for raytracing
+// 3D Vector implementation for raytracing, plus Point3 and Color newtypes that keep
+// positions and colors from being passed where the other is expected
 
-#[derive(Debug, Clone, Copy)]
+use crate::simd::FloatX4;
+use std::ops::{Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub, SubAssign};
+
+/// Scalar type of all geometry and shading math. Build with `--features f64` for double
+/// precision in scenes with large coordinates.
+#[cfg(not(feature = "f64"))]
+pub type Float = f32;
+#[cfg(feature = "f64")]
+pub type Float = f64;
+
+/// Mathematical constants at `Float` precision
+#[cfg(not(feature = "f64"))]
+pub use std::f32::consts;
+#[cfg(feature = "f64")]
+pub use std::f64::consts;
+
+/// Narrows to f32 for file formats that store single precision; free unless built with `f64`
+#[allow(clippy::unnecessary_cast)]
+pub fn to_f32(value: Float) -> f32 {
+    value as f32
+}
+
+/// Padded to four lanes and 16-byte aligned so component-wise operations run as one SIMD
+/// instruction (f64 builds use the scalar lanes). The padding lane is not part of the
+/// vector and its value is meaningless.
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
 pub struct Vec3 {
-    pub x: f32,
-    pub y: f32,
-    pub z: f32,
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
+    w: Float,
 }
 
 impl Vec3 {
-    pub fn new(x: f32, y: f32, z: f32) -> Self {
-        Vec3 { x, y, z }
+    pub fn new(x: Float, y: Float, z: Float) -> Self {
+        Vec3 { x, y, z, w: 0.0 }
+    }
+
+    pub fn lanes(&self) -> FloatX4 {
+        // repr(C) with four Float fields has exactly the layout of [Float; 4]
+        FloatX4::from_array(unsafe { std::mem::transmute::<Vec3, [Float; 4]>(*self) })
+    }
+
+    pub fn from_lanes(lanes: FloatX4) -> Self {
+        unsafe { std::mem::transmute::<[Float; 4], Vec3>(lanes.to_array()) }
     }
 
     pub fn zero() -> Self {
@@ -20,10 +57,18 @@ impl Vec3 {
         Vec3::new(0.0, 1.0, 0.0)
     }
 
-    pub fn length(&self) -> f32 {
+    pub fn length(&self) -> Float {
         (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
     }
 
+    pub fn length_squared(&self) -> Float {
+        self.dot(self)
+    }
+
+    pub fn distance(&self, other: &Vec3) -> Float {
+        (*self - *other).length()
+    }
+
     pub fn normalize(&self) -> Self {
         let len = self.length();
         if len == 0.0 {
@@ -33,7 +78,7 @@ impl Vec3 {
         }
     }
 
-    pub fn dot(&self, other: &Vec3) -> f32 {
+    pub fn dot(&self, other: &Vec3) -> Float {
         self.x * other.x + self.y * other.y + self.z * other.z
     }
 
@@ -49,56 +94,202 @@ impl Vec3 {
         *self - *normal * 2.0 * self.dot(normal)
     }
 
-    pub fn clamp(&self, min: f32, max: f32) -> Vec3 {
+    /// Bends a unit direction through a surface with unit `normal` facing against it.
+    /// `eta_ratio` is the incident over the transmitted index of refraction. Returns None
+    /// on total internal reflection.
+    pub fn refract(&self, normal: &Vec3, eta_ratio: Float) -> Option<Vec3> {
+        let cos_theta = (-self.dot(normal)).min(1.0);
+        let sin2_theta = eta_ratio * eta_ratio * (1.0 - cos_theta * cos_theta);
+        if sin2_theta > 1.0 {
+            return None;
+        }
+
+        let perpendicular = (*self + *normal * cos_theta) * eta_ratio;
+        let parallel = *normal * -(1.0 - sin2_theta).sqrt();
+        Some(perpendicular + parallel)
+    }
+
+    /// Two unit vectors that form a right-handed orthonormal basis with this unit vector:
+    /// (tangent, bitangent, self)
+    pub fn orthonormal_basis(&self) -> (Vec3, Vec3) {
+        let axis = if self.x.abs() > 0.9 { Vec3::up() } else { Vec3::new(1.0, 0.0, 0.0) };
+        let tangent = self.cross(&axis).normalize();
+        (tangent, self.cross(&tangent))
+    }
+
+    /// Linear interpolation: `self` at t = 0, `other` at t = 1
+    pub fn lerp(&self, other: &Vec3, t: Float) -> Vec3 {
+        *self + (*other - *self) * t
+    }
+
+    /// Per-component minimum
+    pub fn min(&self, other: &Vec3) -> Vec3 {
+        Vec3::from_lanes(self.lanes().min(other.lanes()))
+    }
+
+    /// Per-component maximum
+    pub fn max(&self, other: &Vec3) -> Vec3 {
+        Vec3::from_lanes(self.lanes().max(other.lanes()))
+    }
+
+    pub fn abs(&self) -> Vec3 {
+        Vec3::from_lanes(self.lanes().abs())
+    }
+
+    pub fn min_component(&self) -> Float {
+        self.x.min(self.y).min(self.z)
+    }
+
+    pub fn max_component(&self) -> Float {
+        self.x.max(self.y).max(self.z)
+    }
+
+    /// Component-wise comparison within `epsilon`, for checks that can't expect exact equality.
+    /// A NaN component is never equal.
+    pub fn approx_eq(&self, other: &Vec3, epsilon: Float) -> bool {
+        let difference = (*self - *other).abs();
+        difference.x <= epsilon && difference.y <= epsilon && difference.z <= epsilon
+    }
+
+    /// Perceptual brightness of an RGB color (Rec. 709 weights)
+    pub fn luminance(&self) -> Float {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
+    }
+
+    pub fn clamp(&self, min: Float, max: Float) -> Vec3 {
         Vec3::new(
             self.x.clamp(min, max),
             self.y.clamp(min, max),
             self.z.clamp(min, max)
         )
     }
+
+    /// Per-component reciprocal; zero components become infinity
+    pub fn recip(&self) -> Vec3 {
+        Vec3::from_lanes(FloatX4::splat(1.0) / self.lanes())
+    }
+}
+
+impl Index<usize> for Vec3 {
+    type Output = Float;
+    fn index(&self, axis: usize) -> &Float {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 axis {} out of range", axis),
+        }
+    }
+}
+
+impl IndexMut<usize> for Vec3 {
+    fn index_mut(&mut self, axis: usize) -> &mut Float {
+        match axis {
+            0 => &mut self.x,
+            1 => &mut self.y,
+            2 => &mut self.z,
+            _ => panic!("Vec3 axis {} out of range", axis),
+        }
+    }
+}
+
+/// Exact equality of x, y and z; see `approx_eq` for a tolerance
+impl PartialEq for Vec3 {
+    fn eq(&self, other: &Vec3) -> bool {
+        self.x == other.x && self.y == other.y && self.z == other.z
+    }
 }
 
 // Implement operators for Vec3
-impl std::ops::Add for Vec3 {
+impl Add for Vec3 {
     type Output = Vec3;
     fn add(self, other: Vec3) -> Vec3 {
-        Vec3::new(self.x + other.x, self.y + other.y, self.z + other.z)
+        Vec3::from_lanes(self.lanes() + other.lanes())
     }
 }
 
-impl std::ops::Sub for Vec3 {
+impl Sub for Vec3 {
     type Output = Vec3;
     fn sub(self, other: Vec3) -> Vec3 {
-        Vec3::new(self.x - other.x, self.y - other.y, self.z - other.z)
+        Vec3::from_lanes(self.lanes() - other.lanes())
     }
 }
 
-impl std::ops::Mul<f32> for Vec3 {
+impl Mul<Float> for Vec3 {
     type Output = Vec3;
-    fn mul(self, scalar: f32) -> Vec3 {
-        Vec3::new(self.x * scalar, self.y * scalar, self.z * scalar)
+    fn mul(self, scalar: Float) -> Vec3 {
+        Vec3::from_lanes(self.lanes() * FloatX4::splat(scalar))
     }
 }
 
-impl std::ops::Mul<Vec3> for Vec3 {
+impl Mul<Vec3> for Float {
+    type Output = Vec3;
+    fn mul(self, vector: Vec3) -> Vec3 {
+        vector * self
+    }
+}
+
+impl Mul<Vec3> for Vec3 {
     type Output = Vec3;
     fn mul(self, other: Vec3) -> Vec3 {
-        Vec3::new(self.x * other.x, self.y * other.y, self.z * other.z)
+        Vec3::from_lanes(self.lanes() * other.lanes())
     }
 }
 
-impl std::ops::Neg for Vec3 {
+impl Div<Float> for Vec3 {
+    type Output = Vec3;
+    fn div(self, scalar: Float) -> Vec3 {
+        Vec3::from_lanes(self.lanes() / FloatX4::splat(scalar))
+    }
+}
+
+impl Div<Vec3> for Vec3 {
+    type Output = Vec3;
+    fn div(self, other: Vec3) -> Vec3 {
+        Vec3::from_lanes(self.lanes() / other.lanes())
+    }
+}
+
+impl Neg for Vec3 {
     type Output = Vec3;
     fn neg(self) -> Vec3 {
-        Vec3::new(-self.x, -self.y, -self.z)
+        Vec3::from_lanes(-self.lanes())
     }
 }
 
-impl std::ops::AddAssign for Vec3 {
+impl AddAssign for Vec3 {
     fn add_assign(&mut self, other: Vec3) {
-        self.x += other.x;
-        self.y += other.y;
-        self.z += other.z;
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Vec3 {
+    fn sub_assign(&mut self, other: Vec3) {
+        *self = *self - other;
+    }
+}
+
+impl MulAssign<Float> for Vec3 {
+    fn mul_assign(&mut self, scalar: Float) {
+        *self = *self * scalar;
+    }
+}
+
+impl MulAssign<Vec3> for Vec3 {
+    fn mul_assign(&mut self, other: Vec3) {
+        *self = *self * other;
+    }
+}
+
+impl DivAssign<Float> for Vec3 {
+    fn div_assign(&mut self, scalar: Float) {
+        *self = *self / scalar;
+    }
+}
+
+impl std::fmt::Debug for Vec3 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Vec3").field("x", &self.x).field("y", &self.y).field("z", &self.z).finish()
     }
 }
 
@@ -107,3 +298,378 @@ impl std::fmt::Display for Vec3 {
         write!(f, "({:.2}, {:.2}, {:.2})", self.x, self.y, self.z)
     }
 }
+
+/// A position in space. Subtracting two points gives the Vec3 between them; adding a Vec3
+/// moves a point. Points can't be scaled or added together.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point3(Vec3);
+
+impl Point3 {
+    pub fn new(x: Float, y: Float, z: Float) -> Self {
+        Point3(Vec3::new(x, y, z))
+    }
+
+    pub fn origin() -> Self {
+        Point3(Vec3::zero())
+    }
+
+    pub fn x(&self) -> Float {
+        self.0.x
+    }
+
+    pub fn y(&self) -> Float {
+        self.0.y
+    }
+
+    pub fn z(&self) -> Float {
+        self.0.z
+    }
+
+    /// The offset of this point from the origin
+    pub fn to_vec3(self) -> Vec3 {
+        self.0
+    }
+
+    pub fn distance(&self, other: &Point3) -> Float {
+        self.0.distance(&other.0)
+    }
+
+    pub fn lerp(&self, other: &Point3, t: Float) -> Point3 {
+        Point3(self.0.lerp(&other.0, t))
+    }
+}
+
+impl Index<usize> for Point3 {
+    type Output = Float;
+    fn index(&self, axis: usize) -> &Float {
+        &self.0[axis]
+    }
+}
+
+impl From<Vec3> for Point3 {
+    fn from(offset: Vec3) -> Self {
+        Point3(offset)
+    }
+}
+
+impl Sub for Point3 {
+    type Output = Vec3;
+    fn sub(self, other: Point3) -> Vec3 {
+        self.0 - other.0
+    }
+}
+
+impl Add<Vec3> for Point3 {
+    type Output = Point3;
+    fn add(self, offset: Vec3) -> Point3 {
+        Point3(self.0 + offset)
+    }
+}
+
+impl Sub<Vec3> for Point3 {
+    type Output = Point3;
+    fn sub(self, offset: Vec3) -> Point3 {
+        Point3(self.0 - offset)
+    }
+}
+
+impl AddAssign<Vec3> for Point3 {
+    fn add_assign(&mut self, offset: Vec3) {
+        self.0 += offset;
+    }
+}
+
+impl std::fmt::Display for Point3 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A linear RGB color, as authored on lights and materials. Colors add, scale and multiply
+/// with each other but don't mix with positions. Radiance buffers hold plain Vec3.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Color(Vec3);
+
+impl Color {
+    pub fn new(r: Float, g: Float, b: Float) -> Self {
+        Color(Vec3::new(r, g, b))
+    }
+
+    pub fn black() -> Self {
+        Color(Vec3::zero())
+    }
+
+    pub fn white() -> Self {
+        Color::new(1.0, 1.0, 1.0)
+    }
+
+    pub fn r(&self) -> Float {
+        self.0.x
+    }
+
+    pub fn g(&self) -> Float {
+        self.0.y
+    }
+
+    pub fn b(&self) -> Float {
+        self.0.z
+    }
+
+    /// The color as (r, g, b) for radiance arithmetic
+    pub fn to_vec3(self) -> Vec3 {
+        self.0
+    }
+
+    pub fn luminance(&self) -> Float {
+        self.0.luminance()
+    }
+
+    pub fn clamp(&self, min: Float, max: Float) -> Color {
+        Color(self.0.clamp(min, max))
+    }
+}
+
+impl From<Vec3> for Color {
+    fn from(rgb: Vec3) -> Self {
+        Color(rgb)
+    }
+}
+
+impl Add for Color {
+    type Output = Color;
+    fn add(self, other: Color) -> Color {
+        Color(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Color {
+    fn add_assign(&mut self, other: Color) {
+        self.0 += other.0;
+    }
+}
+
+impl Mul for Color {
+    type Output = Color;
+    fn mul(self, other: Color) -> Color {
+        Color(self.0 * other.0)
+    }
+}
+
+impl Mul<Float> for Color {
+    type Output = Color;
+    fn mul(self, scalar: Float) -> Color {
+        Color(self.0 * scalar)
+    }
+}
+
+impl Mul<Color> for Float {
+    type Output = Color;
+    fn mul(self, color: Color) -> Color {
+        color * self
+    }
+}
+
+impl Div<Float> for Color {
+    type Output = Color;
+    fn div(self, scalar: Float) -> Color {
+        Color(self.0 / scalar)
+    }
+}
+
+impl std::fmt::Display for Color {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: Float = 1e-5;
+
+    fn sample_vectors() -> Vec<Vec3> {
+        vec![
+            Vec3::new(1.0, -2.0, 3.5),
+            Vec3::new(-0.25, 0.0, 7.0),
+            Vec3::new(1e-3, 1e3, -4.0),
+            Vec3::new(-0.0, 0.5, -0.75),
+        ]
+    }
+
+    /// Applies `op` to x, y and z one at a time, without going through the lanes
+    fn per_component(a: Vec3, b: Vec3, op: impl Fn(Float, Float) -> Float) -> Vec3 {
+        Vec3::new(op(a.x, b.x), op(a.y, b.y), op(a.z, b.z))
+    }
+
+    #[test]
+    fn operators_work_component_wise() {
+        let a = Vec3::new(1.0, 2.0, 3.0);
+        let b = Vec3::new(4.0, -5.0, 0.5);
+        assert_eq!(a + b, Vec3::new(5.0, -3.0, 3.5));
+        assert_eq!(a - b, Vec3::new(-3.0, 7.0, 2.5));
+        assert_eq!(a * b, Vec3::new(4.0, -10.0, 1.5));
+        assert_eq!(a / b, Vec3::new(0.25, -0.4, 6.0));
+        assert_eq!(a * 2.0, Vec3::new(2.0, 4.0, 6.0));
+        assert_eq!(2.0 * a, a * 2.0);
+        assert_eq!(a / 2.0, Vec3::new(0.5, 1.0, 1.5));
+        assert_eq!(-a, Vec3::new(-1.0, -2.0, -3.0));
+
+        let mut v = a;
+        v += b;
+        assert_eq!(v, a + b);
+        v -= b;
+        assert_eq!(v, a);
+        v *= 3.0;
+        assert_eq!(v, a * 3.0);
+        v /= 3.0;
+        assert!(v.approx_eq(&a, EPSILON));
+        v *= b;
+        assert_eq!(v, a * b);
+    }
+
+    #[test]
+    fn products_and_lengths() {
+        let x = Vec3::new(1.0, 0.0, 0.0);
+        let y = Vec3::up();
+        assert_eq!(x.cross(&y), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(Vec3::new(1.0, 2.0, 3.0).dot(&Vec3::new(4.0, 5.0, 6.0)), 32.0);
+        assert_eq!(Vec3::new(3.0, 4.0, 0.0).length(), 5.0);
+        assert_eq!(Vec3::new(3.0, 4.0, 0.0).length_squared(), 25.0);
+        assert!((Vec3::new(2.0, -3.0, 6.0).normalize().length() - 1.0).abs() < EPSILON);
+        assert_eq!(Vec3::zero().normalize(), Vec3::zero());
+        assert_eq!(Vec3::new(1.0, -1.0, 0.0).reflect(&y), Vec3::new(1.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn index_reads_and_writes_each_axis() {
+        let mut v = Vec3::new(1.0, 2.0, 3.0);
+        assert_eq!([v[0], v[1], v[2]], [1.0, 2.0, 3.0]);
+        v[1] = -8.0;
+        assert_eq!(v, Vec3::new(1.0, -8.0, 3.0));
+
+        let point = Point3::new(4.0, 5.0, 6.0);
+        assert_eq!([point[0], point[1], point[2]], [4.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "axis 3 out of range")]
+    fn index_past_z_panics() {
+        let _ = Vec3::zero()[3];
+    }
+
+    #[test]
+    fn refract_follows_snells_law() {
+        let normal = Vec3::up();
+        assert_eq!(Vec3::new(0.0, -1.0, 0.0).refract(&normal, 1.5), Some(Vec3::new(0.0, -1.0, 0.0)));
+
+        // 45 degrees from air into glass
+        let incident = Vec3::new(1.0, -1.0, 0.0).normalize();
+        let refracted = incident.refract(&normal, 1.0 / 1.5).unwrap();
+        assert!((refracted.length() - 1.0).abs() < EPSILON);
+        let sin_in = incident.cross(&normal).length();
+        let sin_out = refracted.cross(&normal).length();
+        assert!((sin_in - 1.5 * sin_out).abs() < EPSILON);
+        assert!(refracted.x > 0.0 && refracted.y < 0.0);
+    }
+
+    #[test]
+    fn refract_returns_none_past_the_critical_angle() {
+        // The critical angle from glass into air is about 41.8 degrees
+        let incident = Vec3::new(1.0, -1.0, 0.0).normalize();
+        assert_eq!(incident.refract(&Vec3::up(), 1.5), None);
+        let steep = Vec3::new(0.5, -1.0, 0.0).normalize();
+        assert!(steep.refract(&Vec3::up(), 1.5).is_some());
+    }
+
+    #[test]
+    fn orthonormal_basis_is_unit_orthogonal_and_right_handed() {
+        let normals = [
+            Vec3::up(),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.3, -0.8, 0.5).normalize(),
+            Vec3::new(0.95, 0.1, -0.2).normalize(),
+        ];
+        for normal in normals {
+            let (tangent, bitangent) = normal.orthonormal_basis();
+            for axis in [tangent, bitangent] {
+                assert!((axis.length() - 1.0).abs() < EPSILON, "{:?} from {:?}", axis, normal);
+                assert!(axis.dot(&normal).abs() < EPSILON, "{:?} from {:?}", axis, normal);
+            }
+            assert!(tangent.dot(&bitangent).abs() < EPSILON);
+            assert!(tangent.cross(&bitangent).approx_eq(&normal, EPSILON));
+        }
+    }
+
+    #[test]
+    fn approx_eq_uses_the_largest_component_difference() {
+        let a = Vec3::new(1.0, 2.0, 3.0);
+        assert!(a.approx_eq(&a, 0.0));
+        assert!(a.approx_eq(&Vec3::new(1.05, 2.0, 2.95), 0.1));
+        assert!(!a.approx_eq(&Vec3::new(1.0, 2.0, 3.2), 0.1));
+        assert!(!a.approx_eq(&Vec3::new(1.0, 2.0, Float::NAN), 0.1));
+    }
+
+    // The lane operations run on SSE by default and on plain arrays with `scalar-math` or
+    // `f64`; both must agree with the one-component-at-a-time result
+    #[test]
+    fn lane_operations_match_scalar_math() {
+        let vectors = sample_vectors();
+        for &a in &vectors {
+            for &b in &vectors {
+                assert_eq!(a + b, per_component(a, b, |x, y| x + y));
+                assert_eq!(a - b, per_component(a, b, |x, y| x - y));
+                assert_eq!(a * b, per_component(a, b, |x, y| x * y));
+                assert_eq!(a * b.y, per_component(a, b, |x, _| x * b.y));
+                assert_eq!(a.min(&b), per_component(a, b, Float::min));
+                assert_eq!(a.max(&b), per_component(a, b, Float::max));
+                if b.x != 0.0 && b.y != 0.0 && b.z != 0.0 {
+                    assert_eq!(a / b, per_component(a, b, |x, y| x / y));
+                }
+            }
+            assert_eq!(-a, per_component(a, a, |x, _| -x));
+            assert_eq!(a.abs(), per_component(a, a, |x, _| x.abs()));
+            assert!(a.abs().x.is_sign_positive());
+        }
+
+        let recip = Vec3::new(2.0, -0.5, 0.0).recip();
+        assert_eq!((recip.x, recip.y, recip.z), (0.5, -2.0, Float::INFINITY));
+    }
+
+    #[test]
+    fn lane_min_and_max_return_the_other_value_for_nan() {
+        let nan = Vec3::new(Float::NAN, 1.0, 2.0);
+        let other = Vec3::new(5.0, 3.0, -1.0);
+        assert_eq!(nan.min(&other).x, 5.0);
+        assert_eq!(nan.max(&other).x, 5.0);
+        assert_eq!(nan.min(&other), Vec3::new(5.0, 1.0, -1.0));
+        assert!(other.min(&nan).x.is_nan());
+    }
+
+    #[test]
+    fn points_and_colors() {
+        let a = Point3::new(1.0, 2.0, 3.0);
+        let b = Point3::new(4.0, 6.0, 3.0);
+        assert_eq!(b - a, Vec3::new(3.0, 4.0, 0.0));
+        assert_eq!(a.distance(&b), 5.0);
+        assert_eq!(a + Vec3::up(), Point3::new(1.0, 3.0, 3.0));
+        assert_eq!(a - Vec3::up(), Point3::new(1.0, 1.0, 3.0));
+        assert_eq!(a.lerp(&b, 0.5), Point3::new(2.5, 4.0, 3.0));
+        let mut moved = a;
+        moved += Vec3::new(1.0, 1.0, 1.0);
+        assert_eq!(moved.to_vec3(), Vec3::new(2.0, 3.0, 4.0));
+
+        let red = Color::new(1.0, 0.0, 0.0);
+        let gray = Color::new(0.5, 0.5, 0.5);
+        assert_eq!(red + gray, Color::new(1.5, 0.5, 0.5));
+        assert_eq!(red * gray, Color::new(0.5, 0.0, 0.0));
+        assert_eq!(gray * 2.0, Color::white());
+        assert_eq!(2.0 * gray, Color::white());
+        assert_eq!(Color::white() / 2.0, gray);
+        assert_eq!(Color::new(2.0, -1.0, 0.5).clamp(0.0, 1.0), Color::new(1.0, 0.0, 0.5));
+        assert!((Color::white().luminance() - 1.0).abs() < EPSILON);
+        assert_eq!(Color::black().luminance(), 0.0);
+    }
+}
@@ -0,0 +1,183 @@
+// Interactive raylib viewer with fly-through camera controls (the `viewer` feature)
+
+use crate::camera::Camera;
+use crate::scene::Scene;
+use crate::raytracer::Raytracer;
+use crate::debug::DebugMode;
+use crate::output::Rgba8;
+use crate::scene_file;
+use crate::vec3::Float;
+use raylib::prelude::*;
+
+const PREVIEW_SCALE: u32 = 4;        // Preview renders at 1/4 resolution while moving
+const REFINE_DELAY: Float = 0.3;       // Seconds without input before the full render
+const MOVE_SPEED: Float = 3.0;         // World units per second for WASD/QE
+const FAST_MULTIPLIER: Float = 4.0;    // Holding shift moves faster
+const ORBIT_SPEED: Float = 0.3;        // Degrees per pixel of mouse movement
+const PAN_SPEED: Float = 0.002;        // Per pixel, scaled by the distance to the target
+const ZOOM_STEP: Float = 0.1;          // Fraction of the target distance per wheel notch
+const TURN_SPEED: Float = 60.0;        // Degrees per second for arrow-key yaw/pitch and roll
+const FOV_STEP: Float = 5.0;           // Degrees per [ / ] press
+
+pub fn run(mut raytracer: Raytracer, scene: &Scene, mut camera: Camera) {
+    let (mut rl, thread) = raylib::init()
+        .size(raytracer.width as i32, raytracer.height as i32)
+        .title("Clean Raytracer")
+        .build();
+
+    let mut image_buffer = raytracer.render(scene, &camera);
+    let mut image_scale = 1;
+    let mut idle_time = 0.0;
+    let mut refined = true;
+
+    while !rl.window_should_close() {
+        let frame_time = rl.get_frame_time() as Float;
+        let moved = handle_camera_input(&rl, &mut camera, frame_time);
+
+        // TAB cycles through the debug visualizations and back to the lit render
+        if rl.is_key_pressed(KeyboardKey::KEY_TAB) {
+            raytracer.debug_mode = DebugMode::cycle(raytracer.debug_mode);
+            println!("Render mode: {}", raytracer.debug_mode.map_or("beauty", |mode| mode.name()));
+            refined = false;
+            idle_time = REFINE_DELAY;
+        }
+
+        // F focuses the lens on the object under the mouse cursor
+        if camera.aperture > 0.0 && rl.is_key_pressed(KeyboardKey::KEY_F) {
+            let mouse = rl.get_mouse_position();
+            match raytracer.autofocus(&mut camera, scene, mouse.x as Float, mouse.y as Float) {
+                Some(distance) => println!("Focus distance: {:.3}", distance),
+                None => println!("Nothing to focus on under the cursor"),
+            }
+            refined = false;
+            idle_time = REFINE_DELAY;
+        }
+
+        // Middle click makes the point under the cursor the new orbit target
+        if rl.is_mouse_button_pressed(MouseButton::MOUSE_BUTTON_MIDDLE) {
+            let mouse = rl.get_mouse_position();
+            let (u, v) = raytracer.image_to_uv(mouse.x as Float + 0.5, mouse.y as Float + 0.5);
+            if let Some(hit) = scene.hit(&camera.get_ray(u, v), 0.0, Float::INFINITY) {
+                camera.set_target(hit.point);
+                refined = false;
+                idle_time = REFINE_DELAY;
+            }
+        }
+
+        // [ and ] narrow and widen the field of view
+        if rl.is_key_pressed(KeyboardKey::KEY_LEFT_BRACKET) || rl.is_key_pressed(KeyboardKey::KEY_RIGHT_BRACKET) {
+            let step = if rl.is_key_pressed(KeyboardKey::KEY_LEFT_BRACKET) { -FOV_STEP } else { FOV_STEP };
+            camera.set_fov(camera.fov() + step);
+            println!("Field of view: {:.1}", camera.fov());
+            refined = false;
+            idle_time = REFINE_DELAY;
+        }
+
+        // P prints the camera as a scene file line, C copies it to the clipboard
+        if rl.is_key_pressed(KeyboardKey::KEY_P) {
+            println!("{}", scene_file::camera_to_line(&camera));
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_C) {
+            let line = scene_file::camera_to_line(&camera);
+            if rl.set_clipboard_text(&line).is_ok() {
+                println!("Copied camera to clipboard: {}", line);
+            }
+        }
+
+        if moved {
+            image_buffer = raytracer.preview(PREVIEW_SCALE).render(scene, &camera);
+            image_scale = PREVIEW_SCALE;
+            idle_time = 0.0;
+            refined = false;
+        } else if !refined {
+            idle_time += frame_time;
+            if idle_time >= REFINE_DELAY {
+                image_buffer = raytracer.render(scene, &camera);
+                image_scale = 1;
+                refined = true;
+            }
+        }
+
+        let mut d = rl.begin_drawing(&thread);
+        d.clear_background(Color::BLACK);
+        draw_image(&mut d, &image_buffer, image_scale as i32);
+    }
+}
+
+/// Applies mouse and keyboard camera controls. Returns true if the camera moved.
+fn handle_camera_input(rl: &RaylibHandle, camera: &mut Camera, frame_time: Float) -> bool {
+    let mut moved = false;
+    let mouse = rl.get_mouse_delta();
+    let (mouse_x, mouse_y) = (mouse.x as Float, mouse.y as Float);
+    let mouse_moved = mouse_x != 0.0 || mouse_y != 0.0;
+
+    // Left drag orbits around the target
+    if mouse_moved && rl.is_mouse_button_down(MouseButton::MOUSE_BUTTON_LEFT) {
+        camera.orbit(-mouse_x * ORBIT_SPEED, mouse_y * ORBIT_SPEED);
+        moved = true;
+    }
+
+    // Right drag pans
+    if mouse_moved && rl.is_mouse_button_down(MouseButton::MOUSE_BUTTON_RIGHT) {
+        let scale = PAN_SPEED * (camera.target() - camera.position()).length();
+        camera.pan(-mouse_x * scale, mouse_y * scale);
+        moved = true;
+    }
+
+    // Wheel zooms toward the target
+    let wheel = rl.get_mouse_wheel_move() as Float;
+    if wheel != 0.0 {
+        camera.dolly(wheel * ZOOM_STEP);
+        moved = true;
+    }
+
+    // Arrow keys turn the camera in place, Z/X roll it
+    let turn = TURN_SPEED * frame_time;
+    let yaw = axis_keys(rl, KeyboardKey::KEY_RIGHT, KeyboardKey::KEY_LEFT);
+    let pitch = axis_keys(rl, KeyboardKey::KEY_UP, KeyboardKey::KEY_DOWN);
+    if yaw != 0.0 || pitch != 0.0 {
+        camera.set_yaw_pitch(camera.yaw() + yaw * turn, camera.pitch() + pitch * turn);
+        moved = true;
+    }
+
+    let roll = axis_keys(rl, KeyboardKey::KEY_X, KeyboardKey::KEY_Z);
+    if roll != 0.0 {
+        camera.set_roll(camera.roll() + roll * turn);
+        moved = true;
+    }
+
+    // WASD moves, Q/E go down/up
+    let forward = axis_keys(rl, KeyboardKey::KEY_W, KeyboardKey::KEY_S);
+    let right = axis_keys(rl, KeyboardKey::KEY_D, KeyboardKey::KEY_A);
+    let up = axis_keys(rl, KeyboardKey::KEY_E, KeyboardKey::KEY_Q);
+
+    if forward != 0.0 || right != 0.0 || up != 0.0 {
+        let mut speed = MOVE_SPEED * frame_time;
+        if rl.is_key_down(KeyboardKey::KEY_LEFT_SHIFT) {
+            speed *= FAST_MULTIPLIER;
+        }
+        camera.fly(forward * speed, right * speed, up * speed);
+        moved = true;
+    }
+
+    moved
+}
+
+/// +1 while `positive` is held, -1 while `negative` is held
+fn axis_keys(rl: &RaylibHandle, positive: KeyboardKey, negative: KeyboardKey) -> Float {
+    rl.is_key_down(positive) as i32 as Float - rl.is_key_down(negative) as i32 as Float
+}
+
+/// Draws the image with each pixel covering a `scale` x `scale` block of the window
+fn draw_image(d: &mut RaylibDrawHandle, image_buffer: &[Vec<Rgba8>], scale: i32) {
+    for (y, row) in image_buffer.iter().enumerate() {
+        for (x, pixel) in row.iter().enumerate() {
+            let color = Color::new(pixel.r, pixel.g, pixel.b, pixel.a);
+            if scale == 1 {
+                d.draw_pixel(x as i32, y as i32, color);
+            } else {
+                d.draw_rectangle(x as i32 * scale, y as i32 * scale, scale, scale, color);
+            }
+        }
+    }
+}
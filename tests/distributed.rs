@@ -0,0 +1,104 @@
+// Distributed rendering on localhost: a coordinator and several workers in this process
+// must produce exactly the image of a local render, even when a worker drops out mid-tile.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use raytracer_clean::distributed::{self, Coordinator, Job};
+use raytracer_clean::{demo, Float, Raytracer, RenderOutput, TileOrder};
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 48;
+
+fn raytracer() -> Raytracer {
+    let mut raytracer = Raytracer::new(WIDTH, HEIGHT).with_samples(4).with_tiles(16, TileOrder::Scanline);
+    raytracer.log_progress = false;
+    raytracer
+}
+
+/// Renders the demo scene on `workers` worker connections, after `before_workers` has had
+/// its turn with the coordinator's address
+fn render_distributed(workers: usize, before_workers: impl FnOnce(SocketAddr) + Send) -> RenderOutput {
+    let raytracer = raytracer();
+    let camera = demo::default_camera(WIDTH as Float / HEIGHT as Float);
+    let job = Job::new(&raytracer, &camera, None).expect("preparing the job");
+    let coordinator = Coordinator::bind("127.0.0.1:0").expect("binding the coordinator");
+    let address = coordinator.local_addr().expect("coordinator address");
+
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            before_workers(address);
+            for _ in 0..workers {
+                thread::spawn(move || distributed::run_worker(&address.to_string(), 1));
+            }
+        });
+        coordinator.render(&raytracer, &job).expect("distributed render")
+    })
+}
+
+fn assert_matches_local(render: &RenderOutput) {
+    let camera = demo::default_camera(WIDTH as Float / HEIGHT as Float);
+    let local = raytracer().render_output(&demo::create_scene(), &camera);
+    assert!(render.image == local.image, "distributed image differs from the local render");
+    assert_eq!(render.sample_counts, local.sample_counts);
+    assert_eq!(render.stats.primary_rays, local.stats.primary_rays);
+}
+
+#[test]
+fn several_workers_match_local_render() {
+    let render = render_distributed(3, |_| {});
+    assert_matches_local(&render);
+}
+
+#[test]
+fn work_of_a_failed_worker_is_reassigned() {
+    // Takes the first tile and disconnects without rendering it
+    let render = render_distributed(2, |address| {
+        let mut stream = TcpStream::connect(address).expect("connecting");
+        stream.write_all(b"hello version=1 bytes=0\n").expect("sending hello");
+        let reader = BufReader::new(stream.try_clone().expect("cloning the stream"));
+        let mut lines = reader.split(b'\n');
+        while let Some(Ok(line)) = lines.next() {
+            if line.starts_with(b"work ") {
+                break;
+            }
+        }
+    });
+    assert_matches_local(&render);
+}
+
+#[test]
+fn render_fails_when_no_worker_connects() {
+    let raytracer = raytracer();
+    let camera = demo::default_camera(WIDTH as Float / HEIGHT as Float);
+    let job = Job::new(&raytracer, &camera, None).expect("preparing the job");
+    let coordinator = Coordinator::bind("127.0.0.1:0")
+        .expect("binding the coordinator")
+        .with_connect_timeout(Duration::from_secs(1));
+
+    let started = Instant::now();
+    let error = coordinator.render(&raytracer, &job).err().expect("a render without workers fails");
+    assert!(error.contains("no worker connected"), "unexpected error: {}", error);
+    assert!(started.elapsed() < Duration::from_secs(10));
+}
+
+#[test]
+fn stalled_connection_is_shut_down_when_the_render_ends() {
+    // Connects but never says hello, so without a shutdown the coordinator would wait out
+    // the 30 second read timeout before returning
+    let (closed, on_close) = mpsc::channel();
+    let started = Instant::now();
+    let render = render_distributed(2, move |address| {
+        let mut stream = TcpStream::connect(address).expect("connecting");
+        thread::spawn(move || {
+            let _ = stream.read(&mut [0; 64]);
+            let _ = closed.send(());
+        });
+    });
+    assert_matches_local(&render);
+    assert!(started.elapsed() < Duration::from_secs(15));
+    on_close.recv_timeout(Duration::from_secs(5)).expect("the stalled connection is closed");
+}
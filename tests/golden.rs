@@ -0,0 +1,209 @@
+// Golden-image regression tests: small reference scenes rendered headlessly and compared
+// with the 8-bit images checked in under tests/golden as binary PPM files.
+//
+// After an intended change to the output, re-bless the images with
+//     UPDATE_GOLDEN=1 cargo test golden
+// A failing test writes the actual, expected and difference images to target/golden-diffs.
+
+use std::path::{Path, PathBuf};
+
+use raytracer_clean::{Camera, Color, Cube, Float, Light, Material, Plane, Point3, Raytracer, Scene, ToneMapping, Vec3};
+use raytracer_clean::occlusion::AmbientOcclusion;
+use raytracer_clean::scene_file;
+
+const WIDTH: u32 = 96;
+const HEIGHT: u32 = 72;
+const SAMPLES: u32 = 4;
+
+/// Largest root-mean-square difference over all channels, in 8-bit code values. Rounding
+/// differences between builds (f64, scalar-math) stay far below it.
+const MAX_RMSE: f64 = 0.5;
+
+/// A pixel whose channels differ by more than this counts as changed
+const PIXEL_THRESHOLD: u8 = 4;
+
+/// Changed pixels allowed, as a fraction of the image. A few silhouette pixels may flip
+/// between builds, but a moved shadow or a retextured face changes far more.
+const MAX_CHANGED_PIXELS: f64 = 0.002;
+
+/// Differences are scaled up by this much in the diff image, so small ones are visible
+const DIFF_GAIN: u8 = 8;
+
+type Pixels = Vec<[u8; 3]>;
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn diff_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("target").join("golden-diffs")
+}
+
+fn raytracer() -> Raytracer {
+    Raytracer::new(WIDTH, HEIGHT).with_samples(SAMPLES).with_tone_mapping(ToneMapping::new().with_dither(false))
+}
+
+fn camera(position: Point3, target: Point3) -> Camera {
+    Camera::new(position, target, Vec3::up(), 45.0, WIDTH as Float / HEIGHT as Float)
+}
+
+/// Renders `scene` through the raytracer's display transform and compares it with
+/// `tests/golden/<name>.ppm`, or writes that file when `UPDATE_GOLDEN` is set
+fn check(name: &str, scene: &Scene, camera: &Camera, raytracer: &Raytracer) {
+    let actual: Pixels = raytracer
+        .render(scene, camera)
+        .iter()
+        .flatten()
+        .map(|color| [color.r, color.g, color.b])
+        .collect();
+    let golden_path = golden_dir().join(format!("{}.ppm", name));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(golden_dir()).expect("creating tests/golden");
+        write_ppm(&golden_path, &actual).unwrap_or_else(|error| panic!("{}: {}", golden_path.display(), error));
+        return;
+    }
+
+    let expected = read_ppm(&golden_path)
+        .unwrap_or_else(|message| panic!("{}: {} (run with UPDATE_GOLDEN=1 to create it)", golden_path.display(), message));
+
+    let differences: Vec<u8> = actual
+        .iter()
+        .zip(&expected)
+        .flat_map(|(a, b)| (0..3).map(move |i| a[i].abs_diff(b[i])))
+        .collect();
+    let squared: f64 = differences.iter().map(|&difference| (difference as f64).powi(2)).sum();
+    let rmse = (squared / differences.len() as f64).sqrt();
+    let changed = differences.chunks(3).filter(|pixel| pixel.iter().any(|&d| d > PIXEL_THRESHOLD)).count();
+    let changed_fraction = changed as f64 / actual.len() as f64;
+
+    if rmse > MAX_RMSE || changed_fraction > MAX_CHANGED_PIXELS {
+        let dir = diff_dir();
+        std::fs::create_dir_all(&dir).expect("creating target/golden-diffs");
+        let diff: Pixels = differences
+            .chunks(3)
+            .map(|pixel| std::array::from_fn(|i| pixel[i].saturating_mul(DIFF_GAIN)))
+            .collect();
+        for (suffix, pixels) in [("actual", &actual), ("expected", &expected), ("diff", &diff)] {
+            let path = dir.join(format!("{}_{}.ppm", name, suffix));
+            write_ppm(&path, pixels).unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+        }
+
+        panic!(
+            "{}: RMSE {:.3} (max {}), {} pixels changed (max {:.0}); images written to {}",
+            name,
+            rmse,
+            MAX_RMSE,
+            changed,
+            MAX_CHANGED_PIXELS * actual.len() as f64,
+            dir.display()
+        );
+    }
+}
+
+fn write_ppm(path: &Path, pixels: &Pixels) -> std::io::Result<()> {
+    let mut data = format!("P6\n{} {}\n255\n", WIDTH, HEIGHT).into_bytes();
+    data.extend(pixels.iter().flatten());
+    std::fs::write(path, data)
+}
+
+/// Reads a binary PPM written by `write_ppm`, which must be WIDTH x HEIGHT
+fn read_ppm(path: &Path) -> Result<Pixels, String> {
+    let data = std::fs::read(path).map_err(|error| error.to_string())?;
+    let header = format!("P6\n{} {}\n255\n", WIDTH, HEIGHT);
+    let pixels = data
+        .strip_prefix(header.as_bytes())
+        .ok_or(format!("not a {}x{} binary PPM", WIDTH, HEIGHT))?;
+    if pixels.len() != (WIDTH * HEIGHT * 3) as usize {
+        return Err("truncated pixel data".to_string());
+    }
+
+    Ok(pixels.chunks(3).map(|pixel| [pixel[0], pixel[1], pixel[2]]).collect())
+}
+
+fn floor(material: Material) -> Plane {
+    Plane::new(Point3::origin(), Vec3::up(), material)
+}
+
+/// Magenta and black squares on each face, as in the demo, so a face whose texture turns or
+/// mirrors shows up
+fn checker() -> Material {
+    Material::new()
+        .with_checkerboard(2.0, Color::new(1.0, 0.0, 1.0), Color::black())
+        .with_roughness(0.3)
+        .with_specular(0.5)
+}
+
+#[test]
+fn golden_checker_cube() {
+    let mut scene = Scene::new();
+    scene.set_background_color(Color::new(0.5, 0.6, 0.7));
+    scene.add_cube(Cube::new(Point3::new(-1.2, 0.0, 0.0), Vec3::new(1.5, 1.5, 1.5), checker()));
+    scene.add_cube(Cube::new(Point3::new(1.2, 0.0, 0.0), Vec3::new(1.5, 1.5, 1.5), checker()));
+    if let Some(transform) = scene.objects.transform_of(1) {
+        *transform = transform.with_rotation(Vec3::new(20.0, 35.0, 0.0));
+    }
+    scene.add_light(Light::new(Point3::new(-3.0, 5.0, 4.0), Color::white(), 1.0));
+
+    check("checker_cube", &scene, &camera(Point3::new(2.0, 2.5, 5.0), Point3::origin()), &raytracer());
+}
+
+#[test]
+fn golden_plane_shading() {
+    let mut scene = Scene::new();
+    scene.set_background_color(Color::black());
+    let material = Material::new().with_color(Color::new(0.8, 0.8, 0.8)).with_specular(0.8).with_roughness(0.2);
+    scene.add_plane(floor(material));
+    scene.add_light(Light::new(Point3::new(-2.0, 1.0, -1.0), Color::new(1.0, 0.3, 0.2), 1.0));
+    scene.add_light(Light::new(Point3::new(2.0, 2.0, -3.0), Color::new(0.2, 0.4, 1.0), 1.5));
+
+    check("plane_shading", &scene, &camera(Point3::new(0.0, 3.0, 4.0), Point3::new(0.0, 0.0, -2.0)), &raytracer());
+}
+
+#[test]
+fn golden_shadows() {
+    let mut scene = Scene::new();
+    scene.set_background_color(Color::new(1.0, 1.0, 1.0));
+    scene.add_plane(floor(Material::new().with_roughness(0.9)));
+    let material = Material::new().with_color(Color::new(0.2, 0.6, 0.9));
+    scene.add_cube(Cube::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 1.0, 1.0), material.clone()));
+    scene.add_cube(Cube::new(Point3::new(-2.0, 0.5, -1.5), Vec3::new(1.0, 1.0, 1.0), material));
+    scene.add_light(Light::new(Point3::new(2.0, 5.0, 1.0), Color::white(), 1.0));
+    scene.add_light(Light::new(Point3::new(-4.0, 3.0, 3.0), Color::new(1.0, 0.9, 0.7), 0.5));
+
+    check("shadows", &scene, &camera(Point3::new(0.5, 3.5, 4.5), Point3::new(-0.7, 0.3, -0.5)), &raytracer());
+}
+
+#[test]
+fn golden_reflections() {
+    let mut scene = Scene::new();
+    scene.set_background_color(Color::new(0.3, 0.4, 0.6));
+    let mirror = Material::new().with_color(Color::new(0.9, 0.9, 0.9)).with_reflectivity(0.6).with_roughness(0.1);
+    scene.add_plane(floor(mirror.clone()));
+    scene.add_cube(Cube::new(Point3::new(-1.0, 1.0, 0.0), Vec3::new(1.5, 2.0, 1.5), checker().with_reflectivity(0.2)));
+    scene.add_cube(Cube::new(Point3::new(1.5, 1.0, -1.0), Vec3::new(1.0, 2.0, 1.0), mirror));
+    scene.add_light(Light::new(Point3::new(-3.0, 6.0, 4.0), Color::white(), 1.0));
+
+    check("reflections", &scene, &camera(Point3::new(1.0, 3.0, 6.0), Point3::new(0.0, 0.8, 0.0)), &raytracer());
+}
+
+#[test]
+fn golden_ambient_occlusion() {
+    let mut scene = Scene::new();
+    scene.set_background_color(Color::new(1.0, 1.0, 1.0));
+    scene.ambient_light = Color::new(0.6, 0.6, 0.6);
+    scene.add_plane(floor(Material::new()));
+    scene.add_cube(Cube::new(Point3::new(0.0, 0.75, 0.0), Vec3::new(1.5, 1.5, 1.5), Material::new()));
+
+    let raytracer = raytracer().with_ambient_occlusion(Some(AmbientOcclusion::new(16, 2.0)));
+    check("ambient_occlusion", &scene, &camera(Point3::new(3.0, 3.0, 4.0), Point3::new(0.0, 0.5, 0.0)), &raytracer);
+}
+
+#[test]
+fn golden_demo_scene() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes").join("demo.scene");
+    let file = scene_file::load(&path.to_string_lossy(), WIDTH as Float / HEIGHT as Float).unwrap();
+    let camera = file.camera.expect("demo.scene has a camera");
+
+    check("demo_scene", &file.scene, &camera, &raytracer());
+}
@@ -0,0 +1,77 @@
+// Render behaviour that doesn't need reference images: where adaptive sampling spends its
+// samples, how the sample count heat map shows it, and settings that must not change the image.
+
+use raytracer_clean::aov::Aov;
+use raytracer_clean::demo;
+use raytracer_clean::sampling::AdaptiveSampling;
+use raytracer_clean::{Camera, Color, Cube, Float, Material, Point3, Raytracer, Scene, Vec3};
+
+const WIDTH: u32 = 48;
+const HEIGHT: u32 = 36;
+
+/// A glowing white cube on black, so every pixel is flat except along the silhouette
+fn silhouette_scene() -> (Scene, Camera) {
+    let mut scene = Scene::new();
+    scene.set_background_color(Color::black());
+    scene.ambient_light = Color::black();
+    let mut glow = Material::new().with_color(Color::black());
+    glow.emission = Color::white();
+    scene.add_cube(Cube::new(Point3::origin(), Vec3::new(1.0, 1.0, 1.0), glow));
+
+    let camera = Camera::new(Point3::new(0.0, 0.0, 4.0), Point3::origin(), Vec3::up(), 40.0, WIDTH as Float / HEIGHT as Float);
+    (scene, camera)
+}
+
+#[test]
+fn adaptive_sampling_stops_early_on_flat_pixels_and_refines_edges() {
+    let (scene, camera) = silhouette_scene();
+    let mut raytracer = Raytracer::new(WIDTH, HEIGHT).with_adaptive_sampling(Some(AdaptiveSampling::new(4, 64, 0.01)));
+    raytracer.log_progress = false;
+    let counts = raytracer.render_output(&scene, &camera).sample_counts;
+
+    let (center_x, center_y) = (WIDTH as usize / 2, HEIGHT as usize / 2);
+    assert_eq!(counts[0][0], 4, "background corner");
+    assert_eq!(counts[center_y][center_x], 4, "middle of the cube face");
+
+    let (edge_x, edge_y) = (0..HEIGHT as usize)
+        .flat_map(|y| (0..WIDTH as usize).map(move |x| (x, y)))
+        .max_by_key(|&(x, y)| counts[y][x])
+        .unwrap();
+    assert!(counts[edge_y][edge_x] > 4, "some silhouette pixel takes more samples");
+    assert!(counts.iter().flatten().all(|&count| (4..=64).contains(&count)));
+
+    let heat_map = raytracer.sample_count_heat_map(&counts);
+    assert_eq!((heat_map.len(), heat_map[0].len()), (HEIGHT as usize, WIDTH as usize));
+    assert_eq!(heat_map[0][0], heat_map[center_y][center_x], "equal counts get equal colors");
+    assert_ne!(heat_map[0][0], heat_map[edge_y][edge_x], "more samples get another color");
+}
+
+#[test]
+fn packets_match_single_rays() {
+    // Widths that leave 1, 2 and 3 pixels after the last full packet, and one that leaves none
+    for (width, height) in [(37, 21), (30, 17), (19, 13), (32, 18)] {
+        let scene = demo::create_scene();
+        let camera = demo::default_camera(width as Float / height as Float);
+        let raytracer = |packets: bool| {
+            let mut raytracer = Raytracer::new(width, height)
+                .with_samples(1)
+                .with_packets(packets)
+                .with_aovs(vec![Aov::Depth, Aov::Normal, Aov::ObjectId]);
+            raytracer.log_progress = false;
+            raytracer.render_output(&scene, &camera)
+        };
+        let (single, packed) = (raytracer(false), raytracer(true));
+
+        for (y, (single_row, packed_row)) in single.image.iter().zip(&packed.image).enumerate() {
+            for (x, (a, b)) in single_row.iter().zip(packed_row).enumerate() {
+                assert!(a.approx_eq(b, 1e-4), "{}x{} pixel ({}, {}): {:?} vs {:?}", width, height, x, y, a, b);
+            }
+        }
+        for aov in [Aov::Depth, Aov::Normal, Aov::ObjectId] {
+            let pixels = single.aovs.get(aov).iter().flatten().zip(packed.aovs.get(aov).iter().flatten());
+            for (a, b) in pixels {
+                assert!(a == b || a.approx_eq(b, 1e-4), "{}x{} {} pass: {:?} vs {:?}", width, height, aov.name(), a, b);
+            }
+        }
+    }
+}